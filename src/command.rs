@@ -1,18 +1,96 @@
 use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
+// `help` lists the chat commands instead
+#[command(disable_help_subcommand = true)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Command,
 }
 
-#[derive(Subcommand, Debug, PartialEq, Eq)]
+impl Cli {
+    /// Parse a chat input. The code of `eval` and `exec` is kept as typed, as a
+    /// single argument, instead of split at whitespace.
+    pub fn parse_input(input: &str) -> Result<Cli, clap::Error> {
+        let mut cli = Cli::try_parse_from(input.split_whitespace())?;
+        match &mut cli.command {
+            Command::Eval { code, .. } => *code = raw_code(input, 3),
+            Command::Exec { code } => *code = raw_code(input, 2),
+            _ => (),
+        }
+        Ok(cli)
+    }
+}
+
+/// The rest of `input` after its first `words` words, empty if there is none.
+fn raw_code(input: &str, words: usize) -> Vec<String> {
+    let mut rest = input.trim_start();
+    for _ in 0..words {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    match rest.trim_end() {
+        "" => vec![],
+        code => vec![code.to_string()],
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
 pub enum Command {
     /// Reload one or all scripts
     Reload {
         /// The name of the script to reload. If not specified, all scripts will be reloaded.
         script: Option<String>,
     },
+    /// List the scripts with their state
+    List,
+    /// Load and run a script of `LuaEngineEx`
+    Load {
+        /// The file name of the script, like `hud.lua`
+        file: String,
+    },
+    /// Stop and unload a script
+    Unload { script: String },
+    /// Enable a script and load it, remembered across sessions
+    Enable { script: String },
+    /// Disable a script and unload it, remembered across sessions
+    Disable { script: String },
+    /// Show the manifest and the resources of a script
+    Info { script: String },
+    /// Show the state of the engine
+    Status,
+    /// Evaluate Lua code in a running script
+    Eval {
+        script: String,
+        /// An expression or statements, continued by the next input if
+        /// incomplete. Empty to cancel an incomplete input. A single argument
+        /// with the code as typed, see [`Cli::parse_input`].
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        code: Vec<String>,
+    },
+    /// Evaluate Lua code in the scratch VM
+    Exec {
+        /// An expression or statements, continued by the next input if
+        /// incomplete. Empty to cancel an incomplete input. A single argument
+        /// with the code as typed, see [`Cli::parse_input`].
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        code: Vec<String>,
+    },
+    /// Show the latest inputs of `eval` and `exec`
+    History {
+        /// The number of inputs, 10 by default
+        n: Option<usize>,
+    },
+    /// List the chat commands of the scripts
+    Help {
+        /// Only list the commands of this script
+        script: Option<String>,
+    },
+    /// Show the recent log records
+    Log {
+        /// A script name or `engine`, a least level and a count, in any order
+        args: Vec<String>,
+    },
     /// Debug commands
     Debug {
         #[command(subcommand)]
@@ -20,10 +98,15 @@ pub enum Command {
     },
 }
 
-#[derive(Subcommand, Debug, PartialEq, Eq)]
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
 pub enum DebugCommand {
     /// Print all VMs
     Vm,
+    /// Print active memory freezes per VM
+    Freeze {
+        /// Only print freezes of this script
+        script: Option<String>,
+    },
 }
 
 #[cfg(test)]
@@ -51,6 +134,66 @@ mod tests {
         assert_eq!(cli.command, Command::Reload { script: None });
     }
 
+    #[test]
+    fn test_manage() {
+        let cli = Cli::try_parse_from(["/lua", "list"]).unwrap();
+        assert_eq!(cli.command, Command::List);
+        let cli = Cli::try_parse_from(["/lua", "disable", "hud.lua"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Disable {
+                script: "hud.lua".to_string()
+            }
+        );
+        assert!(Cli::try_parse_from(["/lua", "info"]).is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let cli = Cli::parse_input("/lua eval  hud.lua return -hp,  \"a   b\" ").unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Eval {
+                script: "hud.lua".to_string(),
+                code: vec!["return -hp,  \"a   b\"".to_string()],
+            }
+        );
+        let cli = Cli::parse_input("/lua exec -- note\tx").unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Exec {
+                code: vec!["-- note\tx".to_string()],
+            }
+        );
+        let cli = Cli::parse_input("/lua exec ").unwrap();
+        assert_eq!(cli.command, Command::Exec { code: vec![] });
+        assert!(Cli::parse_input("/lua eval").is_err());
+    }
+
+    #[test]
+    fn test_help() {
+        let cli = Cli::try_parse_from(["/lua", "help", "hud.lua"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Help {
+                script: Some("hud.lua".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_log() {
+        let cli = Cli::try_parse_from(["/lua", "log", "hud.lua", "error"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Log {
+                args: vec!["hud.lua".to_string(), "error".to_string()]
+            }
+        );
+        let cli = Cli::try_parse_from(["/lua", "log"]).unwrap();
+        assert_eq!(cli.command, Command::Log { args: vec![] });
+    }
+
     #[test]
     fn test_debug_vm() {
         let inputs = "/lua debug vm".split_whitespace().collect::<Vec<&str>>();
@@ -62,4 +205,20 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_debug_freeze() {
+        let inputs = "/lua debug freeze test1.lua"
+            .split_whitespace()
+            .collect::<Vec<&str>>();
+        let cli = Cli::try_parse_from(inputs).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Debug {
+                command: DebugCommand::Freeze {
+                    script: Some("test1.lua".to_string())
+                }
+            }
+        );
+    }
 }
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use log::LevelFilter;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use snafu::prelude::*;
+
+pub const CONFIG_PATH: &str = "LuaEngineEx/config.toml";
+
+static CONFIG: Lazy<RwLock<Arc<Config>>> = Lazy::new(|| RwLock::new(Arc::default()));
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum ConfigError {
+    #[snafu(display("Failed to read config file: {}", source))]
+    Read { source: std::io::Error },
+    #[snafu(display("Failed to parse config file: {}", source))]
+    Parse { source: toml::de::Error },
+    #[snafu(display("Invalid log level of {}: `{}`", key, level))]
+    Level { key: String, level: String },
+}
+
+/// Engine options, loaded from `LuaEngineEx/config.toml`.
+///
+/// Missing file or missing keys fall back to the defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub memory: MemoryConfig,
+    pub frame: FrameConfig,
+    pub chat: ChatConfig,
+    pub log: LogConfig,
+    pub repl: ReplConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    /// Journal the original bytes of every memory write by default.
+    pub journal: bool,
+    /// Restore all journaled writes of a script when it is stopped.
+    pub restore_on_unload: bool,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            journal: true,
+            restore_on_unload: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FrameConfig {
+    /// Time the `OnFrame` listeners may take per frame, in milliseconds.
+    pub budget_ms: u64,
+    /// Drive `OnFrame` by a timer instead of the game update hook.
+    pub timer: bool,
+    /// Interval of the timer in milliseconds, used when `timer` is set or the
+    /// update function is not hooked.
+    pub interval_ms: u64,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            budget_ms: 4,
+            timer: false,
+            interval_ms: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    /// Messages a script may show or send per second, on average.
+    pub rate: f64,
+    /// Messages a script may show or send at once.
+    pub burst: u32,
+    /// Incoming chat messages kept for `Chat.history`.
+    pub history: usize,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            rate: 2.0,
+            burst: 10,
+            history: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Level of the engine and of scripts without an override, like `info`.
+    pub level: String,
+    /// Levels of scripts by file name, like `"hud.lua" = "trace"`.
+    pub scripts: HashMap<String, String>,
+    /// Log files under `LuaEngineEx/logs`.
+    pub file: LogFile,
+    /// Size of a log file before it is rotated, in KiB.
+    pub max_size_kb: u64,
+    /// Rotated files kept of each log file.
+    pub keep: usize,
+    /// Recent records kept in memory for `/lua log` and `Log.recent`.
+    pub buffer: usize,
+    /// Show the errors of scripts as system messages.
+    pub chat_errors: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "debug".to_string(),
+            scripts: HashMap::new(),
+            file: LogFile::None,
+            max_size_kb: 1024,
+            keep: 3,
+            buffer: 500,
+            chat_errors: true,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Check the levels are names of log levels, like `info` or `off`.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut levels = vec![("log.level".to_string(), &self.level)];
+        levels.extend(
+            self.scripts
+                .iter()
+                .map(|(script, level)| (format!("log.scripts.\"{}\"", script), level)),
+        );
+        for (key, level) in levels {
+            ensure!(
+                level.parse::<LevelFilter>().is_ok(),
+                LevelSnafu {
+                    key,
+                    level: level.clone()
+                }
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReplConfig {
+    /// Allow `/lua eval` and `/lua exec`, which run any code typed in the chat.
+    /// Off unless enabled in the config.
+    pub enabled: bool,
+    /// Inputs kept for `/lua history`.
+    pub history: usize,
+    /// Time an input may run before it is aborted, in milliseconds.
+    pub timeout_ms: u64,
+}
+
+impl Default for ReplConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            history: 50,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFile {
+    #[default]
+    None,
+    /// `session.log` with the records of the engine and all scripts
+    Session,
+    /// `<script>.log` per script, `engine.log` for the engine
+    Script,
+}
+
+impl Config {
+    pub fn from_str(s: &str) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(s).context(ParseSnafu)?;
+        config.log.validate()?;
+        Ok(config)
+    }
+}
+
+/// (Re)load the config file. A missing file resets to the defaults, an invalid
+/// one keeps the current config.
+pub fn load() -> Result<(), ConfigError> {
+    let config = match std::fs::read_to_string(CONFIG_PATH) {
+        Ok(s) => Config::from_str(&s)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(ConfigError::Read { source: e }),
+    };
+    *CONFIG.write().unwrap() = Arc::new(config);
+
+    Ok(())
+}
+
+/// The current config, cheap to get on every frame.
+pub fn get() -> Arc<Config> {
+    CONFIG.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default() {
+        let config = Config::from_str("").unwrap();
+        assert!(config.memory.journal);
+        assert!(!config.memory.restore_on_unload);
+        assert_eq!(config.frame.budget_ms, 4);
+        assert!(!config.frame.timer);
+        assert!(!config.repl.enabled);
+        assert!(
+            Config::from_str("repl.enabled = true")
+                .unwrap()
+                .repl
+                .enabled
+        );
+    }
+
+    #[test]
+    fn test_log() {
+        let config = Config::from_str(
+            r#"
+            [log]
+            file = "script"
+            scripts = { "hud.lua" = "trace" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.log.level, "debug");
+        assert_eq!(config.log.file, LogFile::Script);
+        assert_eq!(config.log.scripts["hud.lua"], "trace");
+        assert_eq!(config.log.buffer, 500);
+        assert!(config.log.chat_errors);
+        assert!(Config::from_str("log.file = \"daily\"").is_err());
+        assert!(Config::from_str("log.level = \"verbose\"").is_err());
+        assert!(Config::from_str("log.scripts = { \"hud.lua\" = \"loud\" }").is_err());
+    }
+
+    #[test]
+    fn test_memory() {
+        let config = Config::from_str(
+            r#"
+            [memory]
+            restore_on_unload = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.memory.journal);
+        assert!(config.memory.restore_on_unload);
+    }
+}
@@ -0,0 +1,32 @@
+use serde_json::json;
+
+use crate::luavm::{commands, event};
+
+pub fn init_chat_events() {
+    event::register_builtin(
+        "OnChatInput",
+        "the player sends a chat message, `{text}`, before script commands are run. \
+         Cancelling swallows the message and a changed `text` is sent instead, \
+         if the `chat.input` function of the layout is hooked",
+    );
+}
+
+/// Dispatch `OnChatInput` for a chat message of the player, then run the script
+/// command of the message, on the game thread.
+///
+/// Returns the text the game should send, `None` if the message is swallowed:
+/// cancelled by a listener or run as a script command.
+pub fn dispatch_input(input: &str) -> Option<String> {
+    let text = match event::emit_before("OnChatInput", || json!({ "text": input })) {
+        Some(outcome) if outcome.cancelled => return None,
+        Some(outcome) => outcome.payload["text"]
+            .as_str()
+            .unwrap_or(input)
+            .to_string(),
+        None => input.to_string(),
+    };
+    match commands::execute_blocking(&text) {
+        true => None,
+        false => Some(text),
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use serde_json::json;
+
+use super::monster;
+use crate::luavm::event;
+
+/// Damage of the current quest.
+static STATS: Lazy<Mutex<DamageStats>> = Lazy::new(|| Mutex::new(DamageStats::default()));
+
+/// A hit on a monster, from the damage hook.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    /// address of the attacking entity
+    pub attacker: usize,
+    /// the attacker is the local player
+    pub is_player: bool,
+    /// address of the monster
+    pub monster: usize,
+    pub part: i64,
+    pub value: f64,
+    /// name of the damage type, the number if it has no name
+    pub kind: String,
+}
+
+/// Damage summed since the start of a quest.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DamageStats {
+    /// id of the quest, 0 outside of a quest
+    pub quest: i64,
+    pub total: f64,
+    pub hits: u64,
+    /// damage of the local player
+    pub player: f64,
+    /// by monster handle
+    pub monsters: BTreeMap<u64, f64>,
+    /// by attacker address
+    pub attackers: BTreeMap<usize, f64>,
+    /// by damage type
+    pub types: BTreeMap<String, f64>,
+}
+
+impl DamageStats {
+    /// Add a hit in quest `quest`, the stats restart when the quest changes.
+    fn add(&mut self, hit: &Hit, handle: Option<u64>, quest: i64) {
+        if quest != self.quest {
+            *self = DamageStats {
+                quest,
+                ..Default::default()
+            };
+        }
+        self.total += hit.value;
+        self.hits += 1;
+        if hit.is_player {
+            self.player += hit.value;
+        }
+        if let Some(handle) = handle {
+            *self.monsters.entry(handle).or_default() += hit.value;
+        }
+        *self.attackers.entry(hit.attacker).or_default() += hit.value;
+        *self.types.entry(hit.kind.clone()).or_default() += hit.value;
+    }
+}
+
+pub fn init_damage_events() {
+    event::register_builtin(
+        "OnDamage",
+        "a monster is hit, `{attacker, player, monster, address, part, value, type}`",
+    );
+}
+
+/// Record a hit of quest `quest` and queue `OnDamage`, on the game thread.
+///
+/// The event is queued instead of dispatched, hits are too frequent to wait
+/// for the listeners.
+pub fn on_hit(hit: Hit, quest: i64) {
+    let handle = monster::handle_of(hit.monster);
+    STATS.lock().unwrap().add(&hit, handle, quest);
+    if !event::has_listeners("OnDamage") {
+        return;
+    }
+    event::emit(
+        "OnDamage".to_string(),
+        json!({
+            "attacker": hit.attacker,
+            "player": hit.is_player,
+            "monster": handle,
+            "address": hit.monster,
+            "part": hit.part,
+            "value": hit.value,
+            "type": hit.kind,
+        }),
+    );
+}
+
+/// Damage of the current quest, or of the last one.
+pub fn stats() -> DamageStats {
+    STATS.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let hit = Hit {
+            attacker: 0x100,
+            is_player: true,
+            monster: 0x2000,
+            part: 1,
+            value: 50.0,
+            kind: "physical".to_string(),
+        };
+        let other = Hit {
+            attacker: 0x200,
+            is_player: false,
+            value: 20.0,
+            kind: "element".to_string(),
+            ..hit.clone()
+        };
+        let mut stats = DamageStats::default();
+        stats.add(&hit, Some(1), 101);
+        stats.add(&hit, Some(1), 101);
+        stats.add(&other, None, 101);
+        assert_eq!(stats.total, 120.0);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.player, 100.0);
+        assert_eq!(stats.monsters[&1], 100.0);
+        assert_eq!(stats.attackers[&0x200], 20.0);
+        assert_eq!(stats.types["element"], 20.0);
+
+        // the next quest starts from zero
+        stats.add(&other, Some(2), 102);
+        assert_eq!(stats.quest, 102);
+        assert_eq!(stats.total, 20.0);
+        assert!(!stats.monsters.contains_key(&1));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod chat;
+pub mod damage;
 pub mod monster;
 
 use snafu::prelude::*;
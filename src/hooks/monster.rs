@@ -1,22 +1,44 @@
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 
 use mhw_toolkit::game::hooks::{CallbackPosition, HookHandle, MonsterCtorHook, MonsterDtorHook};
 use once_cell::sync::Lazy;
+use serde_json::json;
 
 use super::HookError;
+use crate::luavm::event;
 
 static MONSTER_CTOR_HOOK: Lazy<Mutex<MonsterCtorHook>> =
     Lazy::new(|| Mutex::new(MonsterCtorHook::new()));
 static MONSTER_DTOR_HOOK: Lazy<Mutex<MonsterDtorHook>> =
     Lazy::new(|| Mutex::new(MonsterDtorHook::new()));
-static MONSTERS: Lazy<Mutex<Vec<usize>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Alive monsters, (handle, address) in spawn order.
+static MONSTERS: Lazy<Mutex<Vec<(u64, usize)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Handles are never reused, so a handle of a destroyed monster stays invalid
+/// even if another monster is created at the same address.
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
 
 pub fn init_monster_hooks() -> Result<(), HookError> {
+    event::register_builtin(
+        "OnMonsterCreate",
+        "a monster is created, `{handle, address}`",
+    );
+    event::register_builtin(
+        "OnMonsterDestroy",
+        "a monster is destroyed, `{handle, address}`",
+    );
+
     MONSTER_CTOR_HOOK
         .lock()
         .unwrap()
         .set_hook(CallbackPosition::Before, |(monster, _, _)| {
-            MONSTERS.lock().unwrap().push(monster as usize);
+            let address = monster as usize;
+            let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+            MONSTERS.lock().unwrap().push((handle, address));
+            event::emit_blocking(
+                "OnMonsterCreate",
+                || json!({ "handle": handle, "address": address }),
+            );
         })
         .map_err(|e| HookError::Hook {
             source: e,
@@ -26,7 +48,21 @@ pub fn init_monster_hooks() -> Result<(), HookError> {
         .lock()
         .unwrap()
         .set_hook(CallbackPosition::Before, |monster| {
-            MONSTERS.lock().unwrap().retain(|&m| m != monster as usize);
+            let address = monster as usize;
+            // `None` for monsters created before the hooks were set
+            let handle = {
+                let mut monsters = MONSTERS.lock().unwrap();
+                let handle = monsters
+                    .iter()
+                    .find(|&&(_, a)| a == address)
+                    .map(|&(h, _)| h);
+                monsters.retain(|&(_, a)| a != address);
+                handle
+            };
+            event::emit_blocking(
+                "OnMonsterDestroy",
+                || json!({ "handle": handle, "address": address }),
+            );
         })
         .map_err(|e| HookError::Hook {
             source: e,
@@ -37,5 +73,35 @@ pub fn init_monster_hooks() -> Result<(), HookError> {
 }
 
 pub fn get_all_monsters() -> Vec<usize> {
+    MONSTERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|&(_, address)| address)
+        .collect()
+}
+
+/// Alive monsters, (handle, address) in spawn order.
+pub fn monsters() -> Vec<(u64, usize)> {
     MONSTERS.lock().unwrap().clone()
 }
+
+/// Address of an alive monster.
+pub fn address_of(handle: u64) -> Option<usize> {
+    MONSTERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|&&(h, _)| h == handle)
+        .map(|&(_, address)| address)
+}
+
+/// Handle of an alive monster.
+pub fn handle_of(address: usize) -> Option<u64> {
+    MONSTERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|&&(_, a)| a == address)
+        .map(|&(handle, _)| handle)
+}
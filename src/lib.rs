@@ -1,13 +1,18 @@
 #![allow(non_snake_case)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::f32::consts::E;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Once};
 use std::thread;
+use std::time::Duration;
 
+use command::{Cli, Command, DebugCommand};
 use log::{debug, error, info};
-use luavm::{LuaHandler, LuaVMError};
+use logger::LogQuery;
+use luavm::{Evaluation, LuaHandler, LuaVMError, Repl};
 use mhw_toolkit::game::hooks::{CallbackPosition, HookHandle};
+use scripts::ScriptSettings;
 use snafu::prelude::*;
 use tokio::runtime::Handle;
 use tokio::sync::{mpsc, Mutex};
@@ -16,10 +21,17 @@ use winapi::um::winnt::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH};
 
 static MAIN_THREAD_ONCE: Once = Once::new();
 
+/// Directory of the scripts.
+const SCRIPT_DIR: &str = "LuaEngineEx";
+/// Name of the VM of `/lua exec`.
+const SCRATCH: &str = "scratch";
+
 mod command;
+mod config;
 mod hooks;
 mod logger;
 mod luavm;
+mod scripts;
 
 mod use_logger {
     use log::LevelFilter;
@@ -33,9 +45,14 @@ mod use_logger {
         log::set_logger(&*LOGGER).unwrap();
         log::set_max_level(LevelFilter::Debug);
     }
+
+    /// Apply the log config, after the config is (re)loaded.
+    pub fn configure_log() {
+        LOGGER.configure(&crate::config::get().log);
+    }
 }
 
-use use_logger::init_log;
+use use_logger::{configure_log, init_log};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -47,26 +64,88 @@ enum Error {
     Hook { source: hooks::HookError },
     #[snafu(display("IO error: {}", source))]
     Io { source: std::io::Error },
+    #[snafu(display("Script settings error: {}", source))]
+    Scripts { source: scripts::ScriptsError },
     #[snafu(display("Error: {}", reason))]
     User { reason: String },
 }
 
+/// Path of script `name` in the script directory.
+fn script_path(name: &str) -> Result<PathBuf> {
+    let path = Path::new(SCRIPT_DIR).join(name);
+    if name.contains(['/', '\\']) || !name.ends_with(".lua") || !path.is_file() {
+        return Err(Error::User {
+            reason: format!("script not found: {}", name),
+        });
+    }
+    Ok(path)
+}
+
+/// File names of the scripts in the script directory.
+fn script_files() -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in std::fs::read_dir(SCRIPT_DIR).context(IoSnafu)? {
+        let path = entry.context(IoSnafu)?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "lua") {
+            names.push(path.file_name().unwrap().to_string_lossy().to_string());
+        }
+    }
+    Ok(names)
+}
+
+fn format_list(items: &[String]) -> String {
+    match items.is_empty() {
+        true => "none".to_string(),
+        false => items.join(", "),
+    }
+}
+
+fn format_memory(bytes: usize) -> String {
+    format!("{:.1} KiB", bytes as f64 / 1024.0)
+}
+
 struct LuaManager {
     vm: HashMap<String, LuaHandler>,
+    settings: ScriptSettings,
+    repl: Repl,
+    /// VM of `/lua exec`, started on first use
+    scratch: Option<LuaHandler>,
 }
 
 impl LuaManager {
     pub fn new() -> Self {
-        Self { vm: HashMap::new() }
+        Self {
+            vm: HashMap::new(),
+            settings: ScriptSettings::default(),
+            repl: Repl::default(),
+            scratch: None,
+        }
     }
 
     pub async fn load_all(&mut self) -> Result<()> {
-        for entry in std::fs::read_dir("LuaEngineEx").context(IoSnafu)? {
+        if let Err(e) = config::load() {
+            error!("config error: {}", e);
+        }
+        configure_log();
+        if let Err(e) = luavm::load_layout() {
+            error!("layout error: {}", e);
+        }
+        luavm::install_hooks();
+        self.settings = ScriptSettings::load().unwrap_or_else(|e| {
+            error!("script settings error: {}", e);
+            ScriptSettings::default()
+        });
+        for entry in std::fs::read_dir(SCRIPT_DIR).context(IoSnafu)? {
             let entry = entry.context(IoSnafu)?;
             let path = entry.path();
             if path.is_file() {
                 if let Some(ext) = path.extension() {
                     if ext == "lua" {
+                        let file_name = path.file_name().unwrap().to_str().unwrap();
+                        if !self.settings.is_enabled(file_name) {
+                            debug!("skipping disabled script: {}", file_name);
+                            continue;
+                        }
                         debug!("loading lua file: {}", path.display());
                         let mut vm = LuaHandler::new(path.file_name().unwrap().to_str().unwrap());
                         vm.load_file(&path).await.context(LuaVMSnafu)?;
@@ -81,58 +160,357 @@ impl LuaManager {
         Ok(())
     }
 
-    pub fn unload_all(&mut self) {
-        // TODO: stop before unload
+    pub async fn unload_all(&mut self) {
+        for vm in self.vm.values() {
+            vm.stop().await;
+        }
         self.vm.clear();
+        // started again by the next `exec`
+        if let Some(scratch) = self.scratch.take() {
+            scratch.stop().await;
+            self.repl.cancel(SCRATCH);
+        }
     }
 
+    /// Run all loaded scripts. A failing script doesn't stop the others, its
+    /// error is kept for `/lua list`.
     pub async fn run_all(&self) -> Result<()> {
-        for vm in self.vm.values() {
-            vm.run().await.context(LuaVMSnafu)?;
+        for (name, vm) in self.vm.iter() {
+            if let Err(e) = vm.run().await {
+                error!(target: &logger::script_target(name), "{} run error: {}", name, e);
+            }
         }
 
         Ok(())
     }
 
-    pub fn unload(&mut self, name: &str) -> Result<()> {
-        // TODO: stop before unload
-        self.vm.remove(name);
+    /// Load and run a script of the script directory.
+    pub async fn load(&mut self, name: &str) -> Result<()> {
+        if self.vm.contains_key(name) {
+            return Err(Error::User {
+                reason: format!("{} is already loaded", name),
+            });
+        }
+        let path = script_path(name)?;
+        let mut vm = LuaHandler::new(name);
+        vm.load_file(&path).await.context(LuaVMSnafu)?;
+        // kept if it fails, to show its error
+        self.vm.insert(name.to_string(), vm.clone());
+        vm.run().await.context(LuaVMSnafu)
+    }
+
+    pub async fn unload(&mut self, name: &str) -> Result<()> {
+        let Some(vm) = self.vm.remove(name) else {
+            return Err(Error::User {
+                reason: format!("{} is not loaded", name),
+            });
+        };
+        vm.stop().await;
 
         Ok(())
     }
 
-    pub async fn reload(&mut self, name: &str) -> Result<()> {
-        // find in scheduler
-        if self.vm.contains_key(name) {
-            let vm = self.vm.get_mut(name).unwrap();
-            match vm.reload().await {
-                Ok(_) => return Ok(()),
-                Err(e) => {
-                    return Err(Error::LuaVM { source: e });
+    /// Enable or disable a script for the next sessions, and load or unload it.
+    pub async fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<()> {
+        script_path(name)?;
+        if self.settings.set_enabled(name, enabled) {
+            self.settings.save().context(ScriptsSnafu)?;
+        }
+        match (enabled, self.vm.contains_key(name)) {
+            (true, false) => self.load(name).await,
+            (false, true) => self.unload(name).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// State of a loaded script, `failed` if its last run failed.
+    async fn state_of(vm: &LuaHandler) -> String {
+        match vm.data.lock().await.error.is_some() {
+            true => "failed".to_string(),
+            false => vm.state().await.to_string(),
+        }
+    }
+
+    /// One line per script: name, state, load time and error.
+    pub async fn list(&self) -> Result<Vec<String>> {
+        let mut names = script_files()?.into_iter().collect::<BTreeSet<_>>();
+        names.extend(self.vm.keys().cloned());
+        let mut lines = vec![];
+        for name in names {
+            let Some(vm) = self.vm.get(&name) else {
+                match self.settings.is_enabled(&name) {
+                    true => lines.push(format!("{} - not loaded", name)),
+                    false => lines.push(format!("{} - disabled", name)),
                 }
+                continue;
             };
+            let (loaded_at, error) = {
+                let data = vm.data.lock().await;
+                (data.loaded_at, data.error.clone())
+            };
+            let loaded_at = loaded_at.map(logger::format_time).unwrap_or_default();
+            match error {
+                // without the traceback
+                Some(e) => lines.push(format!(
+                    "{} - failed, loaded {}: {}",
+                    name,
+                    loaded_at,
+                    e.lines().next().unwrap_or_default()
+                )),
+                None => lines.push(format!(
+                    "{} - {}, loaded {}",
+                    name,
+                    vm.state().await,
+                    loaded_at
+                )),
+            }
         }
-        // find in fs
-        for entry in std::fs::read_dir("LuaEngineEx").context(IoSnafu)? {
-            let entry = entry.context(IoSnafu)?;
-            let path = entry.path();
-            if path.is_file() && path.file_name().unwrap().to_str().unwrap() == name {
-                debug!("loading lua file: {}", path.display());
-                let mut vm = LuaHandler::new(path.file_name().unwrap().to_str().unwrap());
-                vm.load_file(&path).await.context(LuaVMSnafu)?;
-                let name = vm.data.lock().await.name.clone();
-                self.vm.insert(name, vm);
-                break;
+        if lines.is_empty() {
+            lines.push("no scripts".to_string());
+        }
+
+        Ok(lines)
+    }
+
+    /// Manifest, listeners, intervals, commands, services and memory of a
+    /// loaded script.
+    pub async fn info(&self, name: &str) -> Result<Vec<String>> {
+        let Some(vm) = self.vm.get(name) else {
+            return Err(Error::User {
+                reason: format!("{} is not loaded", name),
+            });
+        };
+        let (file_path, loaded_at, error, manifest) = {
+            let data = vm.data.lock().await;
+            let manifest = data
+                .script
+                .as_deref()
+                .map(scripts::parse_manifest)
+                .unwrap_or_default();
+            (
+                data.file_path.clone().unwrap_or_default(),
+                data.loaded_at,
+                data.error.clone(),
+                manifest,
+            )
+        };
+        let usage = vm.usage().await;
+        let mut lines = vec![
+            format!("{} ({})", name, Self::state_of(vm).await),
+            format!(
+                "file: {}, loaded {}",
+                file_path,
+                loaded_at.map(logger::format_time).unwrap_or_default()
+            ),
+        ];
+        if let Some(e) = error {
+            lines.push(format!("error: {}", e));
+        }
+        let manifest = manifest
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        let intervals = usage
+            .intervals
+            .iter()
+            .map(|(interval, count)| format!("{}ms x{}", interval, count))
+            .collect::<Vec<_>>();
+        lines.push(format!("manifest: {}", format_list(&manifest)));
+        lines.push(format!("listeners: {}", format_list(&usage.listeners)));
+        lines.push(format!("intervals: {}", format_list(&intervals)));
+        lines.push(format!("commands: {}", format_list(&usage.commands)));
+        lines.push(format!("services: {}", format_list(&usage.services)));
+        lines.push(format!("memory: {}", format_memory(usage.memory)));
+
+        Ok(lines)
+    }
+
+    /// Scripts by state and the resources of all scripts.
+    pub async fn status(&self) -> Result<Vec<String>> {
+        let mut states: BTreeMap<String, usize> = BTreeMap::new();
+        let (mut memory, mut listeners, mut commands) = (0, 0, 0);
+        for vm in self.vm.values() {
+            *states.entry(Self::state_of(vm).await).or_default() += 1;
+            let usage = vm.usage().await;
+            memory += usage.memory;
+            listeners += usage.listeners.len();
+            commands += usage.commands.len();
+        }
+        let mut scripts = states
+            .into_iter()
+            .map(|(state, count)| format!("{} {}", count, state))
+            .collect::<Vec<_>>();
+        let files = script_files()?;
+        let disabled = self
+            .settings
+            .disabled
+            .iter()
+            .filter(|name| files.contains(name))
+            .count();
+        scripts.push(format!("{} disabled", disabled));
+
+        Ok(vec![
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            format!("scripts: {}", scripts.join(", ")),
+            format!(
+                "memory: {}, listeners: {}, commands: {}",
+                format_memory(memory),
+                listeners,
+                commands
+            ),
+        ])
+    }
+
+    async fn scratch(&mut self) -> Result<LuaHandler> {
+        if let Some(vm) = &self.scratch {
+            return Ok(vm.clone());
+        }
+        let vm = LuaHandler::new(SCRATCH);
+        vm.data.lock().await.script = Some(String::new());
+        vm.run().await.context(LuaVMSnafu)?;
+        self.scratch = Some(vm.clone());
+
+        Ok(vm)
+    }
+
+    /// Evaluate an input of the REPL in a running script, or in the scratch VM.
+    /// An incomplete input waits for the next one.
+    pub async fn eval(&mut self, script: Option<String>, code: &str) -> Result<Vec<String>> {
+        let config = config::get();
+        let target = script.clone().unwrap_or_else(|| SCRATCH.to_string());
+        if code.is_empty() {
+            return match self.repl.cancel(&target) {
+                true => Ok(vec![format!("{}: input cancelled", target)]),
+                false => Ok(vec![format!("{}: nothing to cancel", target)]),
+            };
+        }
+        let vm = match &script {
+            Some(name) => self.vm.get(name).cloned().ok_or(Error::User {
+                reason: format!("{} is not loaded", name),
+            })?,
+            None => self.scratch().await?,
+        };
+
+        let input = self.repl.input(&target, code);
+        let prompt = format!("{}> {}", target, input.replace('\n', " "));
+        let timeout = Duration::from_millis(config.repl.timeout_ms);
+        match vm.eval(&input, timeout).await {
+            Ok(Evaluation::Incomplete) => {
+                self.repl.keep(&target, input);
+                Ok(vec![format!("{} ...", prompt)])
+            }
+            Ok(Evaluation::Done(text)) => {
+                self.repl.record(prompt.clone(), config.repl.history);
+                Ok(vec![prompt, text])
+            }
+            Err(e) => {
+                self.repl.record(prompt, config.repl.history);
+                Err(Error::LuaVM { source: e })
             }
         }
+    }
 
-        Err(Error::User {
-            reason: "script not found".to_string(),
-        })
+    /// Run a script management command, returns the lines to show.
+    pub async fn manage(&mut self, command: Command) -> Result<Vec<String>> {
+        match command {
+            Command::List => self.list().await,
+            Command::Load { file } => {
+                self.load(&file).await?;
+                Ok(vec![format!("{} loaded", file)])
+            }
+            Command::Unload { script } => {
+                self.unload(&script).await?;
+                Ok(vec![format!("{} unloaded", script)])
+            }
+            Command::Enable { script } => {
+                self.set_enabled(&script, true).await?;
+                Ok(vec![format!("{} enabled", script)])
+            }
+            Command::Disable { script } => {
+                self.set_enabled(&script, false).await?;
+                Ok(vec![format!("{} disabled", script)])
+            }
+            Command::Info { script } => self.info(&script).await,
+            Command::Status => self.status().await,
+            Command::Eval { .. } | Command::Exec { .. } | Command::History { .. }
+                if !config::get().repl.enabled =>
+            {
+                Err(Error::User {
+                    reason: format!(
+                        "the REPL is disabled, set `repl.enabled = true` in {} to enable it",
+                        config::CONFIG_PATH
+                    ),
+                })
+            }
+            Command::Eval { script, code } => self.eval(Some(script), &code.join(" ")).await,
+            Command::Exec { code } => self.eval(None, &code.join(" ")).await,
+            Command::History { n } => {
+                let history = self.repl.history(n.unwrap_or(10));
+                match history.is_empty() {
+                    true => Ok(vec!["no history".to_string()]),
+                    false => Ok(history),
+                }
+            }
+            Command::Reload {
+                script: Some(script),
+            } => {
+                self.reload(&script).await?;
+                Ok(vec![format!("{} reloaded", script)])
+            }
+            Command::Reload { script: None } => {
+                self.reload_all().await?;
+                Ok(vec!["all scripts reloaded".to_string()])
+            }
+            Command::Debug { command } => {
+                self.debug(command).await;
+                Ok(vec![])
+            }
+            // shown by the input hook, without waiting for the manager
+            Command::Help { .. } | Command::Log { .. } => Ok(vec![]),
+        }
+    }
+
+    pub async fn reload(&mut self, name: &str) -> Result<()> {
+        // a script not loaded yet, like a new file, is loaded and run
+        let Some(vm) = self.vm.get_mut(name) else {
+            return self.load(name).await;
+        };
+        vm.reload().await.context(LuaVMSnafu)
+    }
+
+    pub async fn debug(&self, command: DebugCommand) {
+        match command {
+            DebugCommand::Vm => {
+                for (name, vm) in self.vm.iter() {
+                    info!("vm: {} ({:?})", name, vm.data.lock().await.file_path);
+                }
+            }
+            DebugCommand::Freeze { script } => {
+                let mut freezes: BTreeMap<String, Vec<luavm::FreezeEntry>> = BTreeMap::new();
+                for entry in luavm::list_freezes() {
+                    if script.as_ref().is_some_and(|s| *s != entry.owner.name) {
+                        continue;
+                    }
+                    freezes
+                        .entry(entry.owner.name.clone())
+                        .or_default()
+                        .push(entry);
+                }
+                if freezes.is_empty() {
+                    info!("no active freezes");
+                }
+                for (name, entries) in freezes {
+                    info!("{}: {} freezes", name, entries.len());
+                    for entry in entries {
+                        info!("  {}", entry);
+                    }
+                }
+            }
+        }
     }
 
     pub async fn reload_all(&mut self) -> Result<()> {
-        self.unload_all();
+        self.unload_all().await;
         self.load_all().await?;
         self.run_all().await?;
 
@@ -144,16 +522,80 @@ impl LuaManager {
 pub enum ManagerEvent {
     ReloadAll,
     Reload(String),
+    Debug(DebugCommand),
+    /// script management commands, like `/lua list`
+    Manage(Command),
+}
+
+/// Print the chat commands of the engine and the scripts.
+fn print_help(script: Option<&str>) {
+    let mut lines = Vec::new();
+    if script.is_none() {
+        lines.extend(
+            [
+                "engine:",
+                "  /lua reload [script] - reload one or all scripts",
+                "  /lua list - list the scripts with their state",
+                "  /lua load <file> - load and run a script",
+                "  /lua unload <script> - stop and unload a script",
+                "  /lua enable|disable <script> - enable or disable a script across sessions",
+                "  /lua info <script> - show the manifest and resources of a script",
+                "  /lua status - show the state of the engine",
+                "  /lua eval <script> <code> - evaluate code in a script",
+                "  /lua exec <code> - evaluate code in the scratch VM",
+                "  /lua history [n] - show the latest evaluated inputs",
+                "  /lua help [script] - list the chat commands",
+                "  /lua log [script] [level] [n] - show the recent log records",
+                "  /lua debug <vm|freeze> - debug commands",
+            ]
+            .map(String::from),
+        );
+    }
+    let help = luavm::commands::help(script);
+    if let (true, Some(script)) = (help.is_empty(), script) {
+        lines.push(format!("no commands registered by {}", script));
+    }
+    for (name, commands) in help {
+        lines.push(format!("{}:", name));
+        lines.extend(commands.into_iter().map(|line| format!("  {}", line)));
+    }
+    luavm::show_engine_message(&lines.join("\n"));
+}
+
+/// Show the recent log records matching the arguments of `/lua log`.
+fn show_log(args: &[String]) {
+    let query = match LogQuery::parse(args) {
+        Ok(query) => query,
+        Err(e) => {
+            error!("log command error: {}", e);
+            luavm::show_engine_message(&e.to_string());
+            return;
+        }
+    };
+    let entries = logger::recent(&query);
+    if entries.is_empty() {
+        luavm::show_engine_message("no log records");
+        return;
+    }
+    let lines = entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    luavm::show_engine_message(&lines.join("\n"));
 }
 
 async fn lua_main() -> Result<(), Error> {
     let (manager_tx, mut manager_rx) = mpsc::channel(128);
+    luavm::event::init(Handle::current());
     // in game chat command listener
     let tx1 = manager_tx.clone();
     let mut hook_input_dispatch = mhw_toolkit::game::hooks::InputDispatchHook::new();
     hook_input_dispatch
         .set_hook(CallbackPosition::Before, move |input| {
+            // engine commands can't be intercepted by scripts
             if !input.starts_with("/lua ") {
+                // without the input hook of the layout, the game sends the
+                // message anyway, even if it's swallowed
+                if !luavm::is_input_hooked() {
+                    hooks::chat::dispatch_input(input);
+                }
                 return;
             }
 
@@ -162,20 +604,43 @@ async fn lua_main() -> Result<(), Error> {
                 return;
             }
             debug!("user command: {:?}", inputs);
-            if inputs[1] == "debug" {
-                // debug!("vm: {:#?}", vm_manager1.lock().unwrap().vm);
-            } else if inputs[1] == "reload" {
-                if inputs.len() < 3 {
-                    // reload all
-                    if let Err(e) = tx1.blocking_send(ManagerEvent::ReloadAll) {
-                        error!("reload all error: {}", e);
-                    };
-                } else {
-                    // reload specified
-                    if let Err(e) = tx1.blocking_send(ManagerEvent::Reload(inputs[2].to_string())) {
-                        error!("reload `{}` error: {}", inputs[2], e)
-                    };
+            if inputs[1] != "reload" {
+                match Cli::parse_input(input) {
+                    Ok(Cli {
+                        command: Command::Debug { command },
+                    }) => {
+                        if let Err(e) = tx1.blocking_send(ManagerEvent::Debug(command)) {
+                            error!("debug command error: {}", e);
+                        }
+                    }
+                    Ok(Cli {
+                        command: Command::Help { script },
+                    }) => print_help(script.as_deref()),
+                    Ok(Cli {
+                        command: Command::Log { args },
+                    }) => show_log(&args),
+                    Ok(Cli { command }) => {
+                        if let Err(e) = tx1.blocking_send(ManagerEvent::Manage(command)) {
+                            error!("command error: {}", e);
+                        }
+                    }
+                    // usage errors, like a missing argument or an unknown subcommand
+                    Err(e) => {
+                        let text = e.render().to_string();
+                        error!("{}", text.trim_end());
+                        luavm::show_engine_message(text.trim_end());
+                    }
                 }
+            } else if inputs.len() < 3 {
+                // reload all
+                if let Err(e) = tx1.blocking_send(ManagerEvent::ReloadAll) {
+                    error!("reload all error: {}", e);
+                };
+            } else {
+                // reload specified
+                if let Err(e) = tx1.blocking_send(ManagerEvent::Reload(inputs[2].to_string())) {
+                    error!("reload `{}` error: {}", inputs[2], e)
+                };
             }
         })
         .map_err(|e| hooks::HookError::Hook {
@@ -185,7 +650,10 @@ async fn lua_main() -> Result<(), Error> {
         .context(HookSnafu)?;
 
     // init basic services
+    hooks::chat::init_chat_events();
     hooks::monster::init_monster_hooks().context(HookSnafu)?;
+    hooks::damage::init_damage_events();
+    luavm::init_game_events();
 
     // start lua main thread
     let vm_manager = Arc::new(Mutex::new(LuaManager::new()));
@@ -240,6 +708,17 @@ async fn lua_main() -> Result<(), Error> {
                         info!("reload {} successfully", name);
                     }
                 }
+                ManagerEvent::Debug(command) => vm_manager.lock().await.debug(command).await,
+                ManagerEvent::Manage(command) => {
+                    let text = match vm_manager.lock().await.manage(command).await {
+                        Ok(lines) => lines.join("\n"),
+                        Err(e) => {
+                            error!("{}", e);
+                            e.to_string()
+                        }
+                    };
+                    luavm::show_engine_message(&text);
+                }
             }
         } else {
             error!("Command handler channel closed");
@@ -1,30 +1,448 @@
-use log::{Metadata, Record};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Metadata, Record};
+use once_cell::sync::Lazy;
+
+use crate::config::{LogConfig, LogFile};
+
+/// Directory of the log files.
+pub const LOG_DIR: &str = "LuaEngineEx/logs";
+/// Target of the records of a script, followed by the script name.
+const SCRIPT_TARGET: &str = "script:";
+/// Name of the engine in the log queries.
+const ENGINE: &str = "engine";
+
+/// Recent records of the engine and the scripts.
+static RECENT: Lazy<Mutex<LogBuffer>> = Lazy::new(|| Mutex::new(LogBuffer::default()));
+
+/// Log target of the records of script `name`.
+pub fn script_target(name: &str) -> String {
+    format!("{}{}", SCRIPT_TARGET, name)
+}
+
+/// Levels and files of the logger, from [`LogConfig`].
+struct Settings {
+    level: LevelFilter,
+    scripts: HashMap<String, LevelFilter>,
+    file: LogFile,
+    max_size: u64,
+    keep: usize,
+    buffer: usize,
+    chat_errors: bool,
+}
+
+impl Settings {
+    /// Invalid levels are rejected by [`crate::config::Config::from_str`].
+    fn new(config: &LogConfig) -> Self {
+        let parse = |level: &str| LevelFilter::from_str(level).unwrap_or(LevelFilter::Debug);
+        Self {
+            level: parse(&config.level),
+            scripts: config
+                .scripts
+                .iter()
+                .map(|(script, level)| (script.clone(), parse(level)))
+                .collect(),
+            file: config.file,
+            max_size: config.max_size_kb * 1024,
+            keep: config.keep,
+            buffer: config.buffer,
+            chat_errors: config.chat_errors,
+        }
+    }
+
+    /// Level of a record target, the override of a script or the default.
+    fn level_of(&self, target: &str) -> LevelFilter {
+        target
+            .strip_prefix(SCRIPT_TARGET)
+            .and_then(|script| self.scripts.get(script))
+            .copied()
+            .unwrap_or(self.level)
+    }
+
+    /// Most verbose level of all targets.
+    fn max_level(&self) -> LevelFilter {
+        self.scripts
+            .values()
+            .copied()
+            .fold(self.level, std::cmp::max)
+    }
+}
 
 pub struct MHWLogger {
     prefix: String,
+    settings: RwLock<Settings>,
+    /// open log files by file name
+    files: Mutex<HashMap<String, RotatingFile>>,
 }
 
 impl MHWLogger {
     pub fn new() -> Self {
         Self {
             prefix: "LuaEngineEx".to_string(),
+            settings: RwLock::new(Settings::new(&LogConfig::default())),
+            files: Mutex::new(HashMap::new()),
         }
     }
+
+    /// Apply the log config, files are reopened with the new limits.
+    pub fn configure(&self, config: &LogConfig) {
+        let settings = Settings::new(config);
+        log::set_max_level(settings.max_level());
+        *self.settings.write().unwrap() = settings;
+        self.files.lock().unwrap().clear();
+    }
+
+    fn write_file(&self, settings: &Settings, script: Option<&str>, line: &str) {
+        let name = match (settings.file, script) {
+            (LogFile::None, _) => return,
+            (LogFile::Session, _) => "session.log".to_string(),
+            (LogFile::Script, Some(script)) => format!("{}.log", script),
+            (LogFile::Script, None) => "engine.log".to_string(),
+        };
+        let mut files = self.files.lock().unwrap();
+        let file = files.entry(name.clone()).or_insert_with(|| {
+            RotatingFile::new(
+                Path::new(LOG_DIR).join(name),
+                settings.max_size,
+                settings.keep,
+            )
+        });
+        // nowhere to report a failing log file
+        let _ = file.write_line(&format!("{} {}", timestamp(), line));
+    }
 }
 
 impl log::Log for MHWLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= log::Level::Debug
+        metadata.level() <= self.settings.read().unwrap().level_of(metadata.target())
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            mhw_toolkit::logger::log_to_loader(
-                record.level().into(),
-                &format!("[{}] {} - {}", self.prefix, record.level(), record.args()),
-            );
+        let settings = self.settings.read().unwrap();
+        if record.level() > settings.level_of(record.target()) {
+            return;
+        }
+        let script = record.target().strip_prefix(SCRIPT_TARGET);
+        let line = match script {
+            Some(script) => format!("{} [{}] {}", record.level(), script, record.args()),
+            None => format!("{} - {}", record.level(), record.args()),
+        };
+        mhw_toolkit::logger::log_to_loader(
+            record.level().into(),
+            &format!("[{}] {}", self.prefix, line),
+        );
+        self.write_file(&settings, script, &line);
+        let entry = LogEntry {
+            time: timestamp(),
+            level: record.level(),
+            script: script.map(str::to_string),
+            message: record.args().to_string(),
+        };
+        RECENT.lock().unwrap().push(entry, settings.buffer);
+        let mirror = settings.chat_errors && record.level() == Level::Error;
+        drop(settings);
+        if let (true, Some(script)) = (mirror, script) {
+            crate::luavm::show_script_error(script, &record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {
+        for file in self.files.lock().unwrap().values_mut() {
+            if let Some(file) = &mut file.file {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+fn timestamp() -> String {
+    format_time(SystemTime::now())
+}
+
+/// Time of day in UTC, like `13:05:09.042`.
+pub fn format_time(time: SystemTime) -> String {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let secs = millis / 1000 % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        millis % 1000
+    )
+}
+
+/// A record kept for `/lua log` and `Log.recent`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// time of day in UTC
+    pub time: String,
+    pub level: Level,
+    /// `None` for the engine
+    pub script: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for LogEntry {
+    /// `13:05:09.042 ERROR [hud.lua] message`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let script = self.script.as_deref().unwrap_or(ENGINE);
+        write!(
+            f,
+            "{} {} [{}] {}",
+            self.time, self.level, script, self.message
+        )
+    }
+}
+
+/// Filter of the recent records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogQuery {
+    /// script name, or `engine` for the records of the engine
+    pub script: Option<String>,
+    /// least severe level of the records
+    pub level: LevelFilter,
+    /// latest records at most
+    pub n: usize,
+}
+
+impl Default for LogQuery {
+    fn default() -> Self {
+        Self {
+            script: None,
+            level: LevelFilter::Trace,
+            n: 10,
+        }
+    }
+}
+
+impl LogQuery {
+    /// Parse the arguments of `/lua log` in any order: a count, a level and a
+    /// script name, like `hud.lua warn 20`.
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<LogQuery, String> {
+        let mut query = LogQuery::default();
+        let (mut n, mut level) = (None, None);
+        for arg in args.iter().map(AsRef::as_ref) {
+            let duplicate = if let Ok(count) = arg.parse::<usize>() {
+                n.replace(count).is_some()
+            } else if let Ok(filter) = LevelFilter::from_str(arg) {
+                level.replace(filter).is_some()
+            } else {
+                query.script.replace(arg.to_string()).is_some()
+            };
+            if duplicate {
+                return Err(format!("unexpected argument `{}`", arg));
+            }
+        }
+        query.n = n.unwrap_or(query.n);
+        query.level = level.unwrap_or(query.level);
+        Ok(query)
+    }
+
+    fn matches(&self, entry: &LogEntry) -> bool {
+        let script = entry.script.as_deref().unwrap_or(ENGINE);
+        entry.level <= self.level && self.script.as_ref().is_none_or(|s| s == script)
+    }
+}
+
+/// Ring buffer of the recent records.
+#[derive(Default)]
+struct LogBuffer {
+    entries: VecDeque<LogEntry>,
+}
+
+impl LogBuffer {
+    fn push(&mut self, entry: LogEntry, capacity: usize) {
+        while self.entries.len() >= capacity.max(1) {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// The latest `query.n` matching records, oldest first.
+    fn query(&self, query: &LogQuery) -> Vec<LogEntry> {
+        let mut entries = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| query.matches(entry))
+            .take(query.n)
+            .cloned()
+            .collect::<Vec<_>>();
+        entries.reverse();
+        entries
+    }
+}
+
+/// The latest records matching `query`, oldest first.
+pub fn recent(query: &LogQuery) -> Vec<LogEntry> {
+    RECENT.lock().unwrap().query(query)
+}
+
+/// A log file renamed to `<name>.1` when it reaches `max_size`, keeping
+/// `keep` rotated files.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn new(path: PathBuf, max_size: u64, keep: usize) -> Self {
+        Self {
+            path,
+            max_size,
+            keep,
+            file: None,
+            size: 0,
         }
     }
 
-    fn flush(&self) {}
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+            self.open()?;
+        }
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line)?;
+            self.size += len;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Shift `<name>.i` to `<name>.i+1`, dropping the oldest.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        self.size = 0;
+        let rotated = |i: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", i));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        let _ = fs::remove_file(rotated(self.keep));
+        for i in (1..self.keep).rev() {
+            let _ = fs::rename(rotated(i), rotated(i + 1));
+        }
+        fs::rename(&self.path, rotated(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_of() {
+        let mut config = LogConfig {
+            level: "info".to_string(),
+            ..Default::default()
+        };
+        config
+            .scripts
+            .insert("hud.lua".to_string(), "trace".to_string());
+        let settings = Settings::new(&config);
+        assert_eq!(settings.level_of("LuaEngineEx::luavm"), LevelFilter::Info);
+        assert_eq!(settings.level_of("script:meter.lua"), LevelFilter::Info);
+        assert_eq!(settings.level_of("script:hud.lua"), LevelFilter::Trace);
+        assert_eq!(settings.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_log_query() {
+        assert_eq!(LogQuery::parse::<&str>(&[]).unwrap(), LogQuery::default());
+        assert_eq!(
+            LogQuery::parse(&["20", "hud.lua", "WARN"]).unwrap(),
+            LogQuery {
+                script: Some("hud.lua".to_string()),
+                level: LevelFilter::Warn,
+                n: 20,
+            }
+        );
+        assert!(LogQuery::parse(&["hud.lua", "meter.lua"]).is_err());
+
+        let mut buffer = LogBuffer::default();
+        let entry = |level, script: Option<&str>, message: &str| LogEntry {
+            time: "13:05:09.042".to_string(),
+            level,
+            script: script.map(str::to_string),
+            message: message.to_string(),
+        };
+        buffer.push(entry(Level::Info, None, "loaded"), 3);
+        buffer.push(entry(Level::Error, Some("hud.lua"), "one"), 3);
+        buffer.push(entry(Level::Debug, Some("hud.lua"), "two"), 3);
+        buffer.push(entry(Level::Warn, Some("hud.lua"), "three"), 3);
+        buffer.push(entry(Level::Error, None, "failed"), 3);
+        let messages = |query: LogQuery| {
+            buffer
+                .query(&query)
+                .into_iter()
+                .map(|e| e.message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages(LogQuery::default()), ["two", "three", "failed"]);
+        let query = LogQuery::parse(&["hud.lua", "warn"]).unwrap();
+        assert_eq!(messages(query), ["three"]);
+        let query = LogQuery::parse(&["engine"]).unwrap();
+        assert_eq!(messages(query), ["failed"]);
+        let query = LogQuery::parse(&["2"]).unwrap();
+        assert_eq!(messages(query), ["three", "failed"]);
+        assert_eq!(
+            entry(Level::Warn, Some("hud.lua"), "low hp").to_string(),
+            "13:05:09.042 WARN [hud.lua] low hp"
+        );
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = std::env::temp_dir().join(format!("lua-engine-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut file = RotatingFile::new(dir.join("hud.lua.log"), 10, 2);
+        for line in ["one", "two", "three", "four", "five"] {
+            file.write_line(line).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("hud.lua.log"), "four\nfive\n");
+        assert_eq!(read("hud.lua.log.1"), "three\n");
+        assert_eq!(read("hud.lua.log.2"), "one\ntwo\n");
+        assert!(!dir.join("hud.lua.log.3").exists());
+
+        // appends to an existing file, rotating it when full
+        let mut file = RotatingFile::new(dir.join("hud.lua.log"), 10, 2);
+        file.write_line("six").unwrap();
+        assert_eq!(read("hud.lua.log"), "six\n");
+        assert_eq!(read("hud.lua.log.1"), "four\nfive\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,30 @@
+use std::time::Instant;
+
+use mlua::prelude::*;
+
+/// Instructions between the checks of a deadline.
+const HOOK_INTERVAL: u32 = 1000;
+
+/// Call `func` in a new thread, aborted with a runtime error `message` once
+/// `deadline` has passed.
+pub async fn call_until<'lua, R: FromLuaMulti<'lua>>(
+    lua: &'lua Lua,
+    func: LuaFunction<'lua>,
+    args: impl IntoLuaMulti<'lua>,
+    deadline: Instant,
+    message: &'static str,
+) -> LuaResult<R> {
+    let thread = lua.create_thread(func)?;
+    thread.set_hook(
+        LuaHookTriggers::new().every_nth_instruction(HOOK_INTERVAL),
+        move |_, _| match Instant::now() > deadline {
+            true => Err(LuaError::runtime(message)),
+            false => Ok(()),
+        },
+    );
+    // the hook aborts busy loops, the timeout aborts waiting
+    let result = tokio::time::timeout_at(deadline.into(), thread.into_async::<_, R>(args)).await;
+    lua.remove_hook();
+
+    result.map_err(|_| LuaError::runtime(message))?
+}
@@ -0,0 +1,500 @@
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CString;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use mhw_toolkit::game_util::{self, ChatMessageSender, SystemMessageColor};
+use mlua::prelude::*;
+use mlua::UserData;
+use once_cell::sync::Lazy;
+use serde_json::json;
+
+use super::function::{LayoutHook, LayoutHookError};
+use super::layout::{self, Layout};
+use crate::config;
+use crate::hooks;
+use crate::luavm::libs::hook::Invocation;
+use crate::luavm::{event, VmInfo};
+
+/// Messages queued at most while the chat UI isn't ready.
+const MAX_QUEUED: usize = 100;
+/// Interval of delivering the queued messages.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// Max length of the strings read from the receive hook.
+const MAX_TEXT_LEN: usize = 256;
+
+static CHAT_MESSAGE_SENDER: Lazy<ChatMessageSender> = Lazy::new(ChatMessageSender::new);
+/// Messages waiting for the chat UI, in order.
+static OUTBOX: Lazy<Mutex<VecDeque<Outgoing>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static LIMITER: Lazy<Mutex<RateLimiter<u64>>> = Lazy::new(|| Mutex::new(RateLimiter::default()));
+/// Limits the errors mirrored to the chat, by script name.
+static ERROR_LIMITER: Lazy<Mutex<RateLimiter<String>>> =
+    Lazy::new(|| Mutex::new(RateLimiter::default()));
+/// Received player messages, oldest first.
+static HISTORY: Lazy<Mutex<VecDeque<Received>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static RECEIVE_HOOK: Lazy<Mutex<LayoutHook>> = Lazy::new(|| Mutex::new(LayoutHook::default()));
+static INPUT_HOOK: Lazy<Mutex<LayoutHook>> = Lazy::new(|| Mutex::new(LayoutHook::default()));
+/// Chat input is dispatched by the input hook of the layout, not by the input
+/// hook of the toolkit.
+static INPUT_HOOKED: AtomicBool = AtomicBool::new(false);
+
+/// Colors of system messages, by name and alias.
+///
+/// These are all the colors `SystemMessageColor` of the toolkit offers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Blue,
+    Purple,
+}
+
+impl Color {
+    const NAMES: &'static str = "`blue|general` or `purple|primary`";
+
+    fn from_name(name: &str) -> Option<Color> {
+        match name.to_lowercase().as_str() {
+            "blue" | "general" => Some(Color::Blue),
+            "purple" | "primary" => Some(Color::Purple),
+            _ => None,
+        }
+    }
+
+    fn to_system(self) -> SystemMessageColor {
+        match self {
+            Color::Blue => SystemMessageColor::Blue,
+            Color::Purple => SystemMessageColor::Purple,
+        }
+    }
+}
+
+enum Outgoing {
+    System(String, Color),
+    Chat(String),
+}
+
+impl Outgoing {
+    fn deliver(&self) {
+        match self {
+            Outgoing::System(text, color) => {
+                game_util::show_system_message(text, color.to_system())
+            }
+            Outgoing::Chat(text) => CHAT_MESSAGE_SENDER.send(text),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Received {
+    sender: String,
+    text: String,
+    /// seconds since the Unix epoch
+    time: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    warned: bool,
+}
+
+/// Token buckets of the scripts showing or sending messages.
+struct RateLimiter<K> {
+    buckets: HashMap<K, Bucket>,
+}
+
+impl<K> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self {
+            buckets: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    /// Take a token of `key`, refilled by `rate` per second up to `burst`.
+    ///
+    /// Returns whether the message may go, and whether this is the first
+    /// message dropped since the last one that went.
+    fn take(&mut self, key: K, now: Instant, rate: f64, burst: u32) -> (bool, bool) {
+        let burst = burst.max(1) as f64;
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            last: now,
+            warned: false,
+        });
+        let refill = now.duration_since(bucket.last).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(burst);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.warned = false;
+            return (true, false);
+        }
+        let first = !bucket.warned;
+        bucket.warned = true;
+        (false, first)
+    }
+
+    fn remove(&mut self, key: &K) {
+        self.buckets.remove(key);
+    }
+}
+
+/// Show or send a message of the script of `lua`, queued until the chat UI is
+/// ready. Returns `false` if the script sends too many messages.
+fn post(lua: &Lua, message: Outgoing) -> bool {
+    let owner = VmInfo::of(lua);
+    let config = config::get();
+    let key = owner.as_ref().map(|o| o.id).unwrap_or(0);
+    let (allowed, first) =
+        LIMITER
+            .lock()
+            .unwrap()
+            .take(key, Instant::now(), config.chat.rate, config.chat.burst);
+    if !allowed {
+        if first {
+            let name = owner.map(|o| o.name).unwrap_or_default();
+            warn!("{} sends too many chat messages, dropping them", name);
+        }
+        return false;
+    }
+
+    if !enqueue(message) {
+        warn!("Chat is not ready, dropping a message");
+        return false;
+    }
+    true
+}
+
+/// Deliver a message, or queue it until the chat UI is ready. Returns `false`
+/// if the queue is full.
+fn enqueue(message: Outgoing) -> bool {
+    let mut outbox = OUTBOX.lock().unwrap();
+    if outbox.is_empty() && layout::get().chat.is_ready() {
+        message.deliver();
+        return true;
+    }
+    if outbox.len() >= MAX_QUEUED {
+        return false;
+    }
+    outbox.push_back(message);
+    true
+}
+
+/// Stop phase of the chat, dropping the rate limit of the VM.
+pub fn on_stop(lua: &Lua) {
+    if let Some(vm) = VmInfo::of(lua) {
+        LIMITER.lock().unwrap().remove(&vm.id);
+    }
+}
+
+/// Show a system message of the engine, like the output of `/lua` commands.
+pub fn show_engine_message(text: &str) {
+    enqueue(Outgoing::System(text.to_string(), Color::Blue));
+}
+
+/// Show an error logged by `script`, dropped silently if the script logs too
+/// many. Called by the logger, so this must not log.
+pub fn show_script_error(script: &str, message: &str) {
+    let config = config::get();
+    let (allowed, _) = ERROR_LIMITER.lock().unwrap().take(
+        script.to_string(),
+        Instant::now(),
+        config.chat.rate,
+        config.chat.burst,
+    );
+    if allowed {
+        enqueue(Outgoing::System(
+            format!("[{}] {}", script, message),
+            Color::Purple,
+        ));
+    }
+}
+
+/// Deliver the queued messages if the chat UI is ready.
+fn flush() {
+    let mut outbox = OUTBOX.lock().unwrap();
+    if outbox.is_empty() || !layout::get().chat.is_ready() {
+        return;
+    }
+    for message in outbox.drain(..) {
+        message.deliver();
+    }
+}
+
+/// Text of a message, a table is joined as lines.
+fn message_text(lua: &Lua, message: LuaValue) -> LuaResult<String> {
+    match message {
+        LuaValue::Table(lines) => Ok(lines
+            .sequence_values::<String>()
+            .collect::<LuaResult<Vec<_>>>()?
+            .join("\n")),
+        other => Ok(lua.unpack::<String>(other)?.replace("\r\n", "\n")),
+    }
+}
+
+pub struct Chat;
+
+impl UserData for Chat {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function("sendMessage", |lua, arg: String| {
+            Ok(post(lua, Outgoing::Chat(arg)))
+        });
+        methods.add_function(
+            "showSystemMessage",
+            |lua, (msg, color): (LuaValue, Option<String>)| {
+                let color = match color {
+                    Some(c) => Color::from_name(&c).ok_or(LuaError::runtime(format!(
+                        "Unsupported color: {}, expect {}",
+                        c,
+                        Color::NAMES
+                    )))?,
+                    None => Color::Blue,
+                };
+                Ok(post(lua, Outgoing::System(message_text(lua, msg)?, color)))
+            },
+        );
+        methods.add_function("isReady", |_, ()| Ok(layout::get().chat.is_ready()));
+        // latest `n` received messages, oldest first
+        methods.add_function("history", |lua, n: Option<usize>| {
+            let history = HISTORY.lock().unwrap();
+            let n = n.unwrap_or(history.len()).min(history.len());
+            let messages = lua.create_table()?;
+            for received in history.iter().skip(history.len() - n) {
+                let message = lua.create_table()?;
+                message.set("sender", received.sender.as_str())?;
+                message.set("text", received.text.as_str())?;
+                message.set("time", received.time)?;
+                messages.push(message)?;
+            }
+            Ok(messages)
+        });
+    }
+}
+
+/// Register `OnChatMessage` and start delivering the queued messages.
+pub fn init_chat_events() {
+    event::register_builtin(
+        "OnChatMessage",
+        "a player chat message is received, `{sender, text}`",
+    );
+    thread::spawn(|| loop {
+        thread::sleep(FLUSH_INTERVAL);
+        flush();
+    });
+}
+
+/// Hook the function receiving player messages of the layout.
+pub fn install_hook() -> Result<(), LayoutHookError> {
+    let layout = layout::get();
+    let receive = &layout.chat.receive;
+    RECEIVE_HOOK.lock().unwrap().update(
+        "chat receive",
+        receive.function,
+        &receive.signature,
+        &receive.prologue,
+        |_, invocation| {
+            on_receive(&layout::get(), invocation);
+            invocation.call_original(&invocation.args())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Hook the function sending the chat input of the layout.
+pub fn install_input_hook() -> Result<(), LayoutHookError> {
+    // the text index belongs to the signature the hook is installed with
+    let input = layout::get().chat.input.clone();
+    let (key, text) = (format!("{:?}", input), input.text);
+    let result = INPUT_HOOK.lock().unwrap().update_keyed(
+        "chat input",
+        input.function,
+        &input.signature,
+        &input.prologue,
+        &key,
+        move |_, invocation| on_input(invocation, text, hooks::chat::dispatch_input),
+    );
+    INPUT_HOOKED.store(matches!(result, Ok(true)), Ordering::Relaxed);
+
+    result.map(|_| ())
+}
+
+/// Whether chat input is dispatched by the input hook of the layout, which can
+/// swallow messages.
+pub fn is_input_hooked() -> bool {
+    INPUT_HOOKED.load(Ordering::Relaxed)
+}
+
+/// Run the chat input function with the text returned by `dispatch` for the
+/// message, or skip it and return 0 if `dispatch` swallows the message.
+fn on_input(
+    invocation: &Invocation,
+    text: usize,
+    dispatch: impl FnOnce(&str) -> Option<String>,
+) -> u64 {
+    let mut args = invocation.args();
+    let Some(input) = layout::read_cstr(args[text].1 as usize, MAX_TEXT_LEN)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    else {
+        return invocation.call_original(&args);
+    };
+    // engine commands are run by the input hook of the toolkit
+    if input.starts_with("/lua ") {
+        return invocation.call_original(&args);
+    }
+    let Some(output) = dispatch(&input) else {
+        return 0;
+    };
+    if output == input {
+        return invocation.call_original(&args);
+    }
+    // alive until the original returns
+    let output = CString::new(output.replace('\0', "")).unwrap_or_default();
+    args[text].1 = output.as_ptr() as u64;
+    invocation.call_original(&args)
+}
+
+/// Record a received message and queue `OnChatMessage`, on the game thread.
+fn on_receive(layout: &Layout, invocation: &Invocation) {
+    let receive = &layout.chat.receive;
+    let read = |i: usize| {
+        layout::read_cstr(invocation.arg(i) as usize, MAX_TEXT_LEN)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    };
+    let (Some(sender), Some(text)) = (read(receive.sender), read(receive.text)) else {
+        return;
+    };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    {
+        let mut history = HISTORY.lock().unwrap();
+        history.push_back(Received {
+            sender: sender.clone(),
+            text: text.clone(),
+            time,
+        });
+        let max = config::get().chat.history;
+        while history.len() > max {
+            history.pop_front();
+        }
+    }
+    if event::has_listeners("OnChatMessage") {
+        event::emit(
+            "OnChatMessage".to_string(),
+            json!({ "sender": sender, "text": text }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{c_char, CStr};
+    use std::hint::black_box;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::luavm::event::ListenerOptions;
+    use crate::luavm::libs::hook::Detour;
+    use crate::luavm::libs::native::Class;
+    use crate::luavm::LuaVM;
+
+    /// Messages sent by [`send_chat`].
+    static SENT: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+    #[inline(never)]
+    extern "win64" fn send_chat(_ui: u64, text: *const c_char) -> u64 {
+        let text = unsafe { CStr::from_ptr(text) };
+        SENT.lock()
+            .unwrap()
+            .push(text.to_string_lossy().into_owned());
+        black_box(1)
+    }
+
+    #[test]
+    fn test_input_hook() {
+        static RUNTIME: Lazy<tokio::runtime::Runtime> =
+            Lazy::new(|| tokio::runtime::Runtime::new().unwrap());
+        event::init(RUNTIME.handle().clone());
+        let luavm = Arc::new(tokio::sync::Mutex::new(LuaVM::new("test_input.lua")));
+        let owner = RUNTIME.block_on(async {
+            let mut vm = luavm.lock().await;
+            vm.lua.set_app_data(Arc::downgrade(&luavm));
+            vm.run("").await.unwrap();
+            let listener = vm
+                .lua
+                .load(
+                    r#"function(e)
+                        if e.text == "/hp 10" then return false end
+                        return { text = e.text .. "!" }
+                    end"#,
+                )
+                .eval()
+                .unwrap();
+            event::add_listener(&vm.lua, "OnChatInput", listener, ListenerOptions::default())
+                .unwrap();
+            VmInfo::of(&vm.lua).unwrap()
+        });
+
+        let f = black_box(send_chat as extern "win64" fn(u64, *const c_char) -> u64);
+        let send = |text: &str| {
+            // the hook reads up to `MAX_TEXT_LEN` bytes
+            let mut buf = [0u8; MAX_TEXT_LEN];
+            buf[..text.len()].copy_from_slice(text.as_bytes());
+            f(0, buf.as_ptr() as *const c_char)
+        };
+        let mut detour = unsafe {
+            Detour::install(
+                send_chat as *const () as usize,
+                vec![Class::Int, Class::Int],
+                Class::Int,
+                |invocation| on_input(invocation, 1, hooks::chat::dispatch_input),
+            )
+        }
+        .unwrap();
+        // cancelled by the listener, the game doesn't send it
+        assert_eq!(send("/hp 10"), 0);
+        assert_eq!(send("hello"), 1);
+        unsafe { detour.remove() }.unwrap();
+        event::remove_owner(owner.id);
+
+        assert_eq!(*SENT.lock().unwrap(), vec!["hello!".to_string()]);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.take(1, start, 2.0, 3), (true, false));
+        }
+        assert_eq!(limiter.take(1, start, 2.0, 3), (false, true));
+        assert_eq!(limiter.take(1, start, 2.0, 3), (false, false));
+        // other scripts have their own bucket
+        assert_eq!(limiter.take(2, start, 2.0, 3), (true, false));
+        // 2 per second
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.take(1, later, 2.0, 3), (true, false));
+        assert_eq!(limiter.take(1, later, 2.0, 3), (false, true));
+        // a stopped script starts with a full bucket again
+        limiter.remove(&1);
+        assert_eq!(limiter.take(1, later, 2.0, 3), (true, false));
+    }
+
+    #[test]
+    fn test_message_text() {
+        let lua = Lua::new();
+        let lines = lua.load("{ 'HP', 'Stamina' }").eval().unwrap();
+        assert_eq!(message_text(&lua, lines).unwrap(), "HP\nStamina");
+        let text = LuaValue::String(lua.create_string("a\r\nb").unwrap());
+        assert_eq!(message_text(&lua, text).unwrap(), "a\nb");
+        assert_eq!(Color::from_name("Primary"), Some(Color::Purple));
+        assert_eq!(Color::from_name("red"), None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::Mutex;
+
+use mlua::prelude::*;
+use once_cell::sync::Lazy;
+
+use super::function::{LayoutHook, LayoutHookError};
+use super::layout::{self, DamageLayout, Layout};
+use crate::hooks::damage::{self, Hit};
+use crate::luavm::libs::hook::Invocation;
+use crate::luavm::libs::native::NativeType;
+
+static DAMAGE_HOOK: Lazy<Mutex<LayoutHook>> = Lazy::new(|| Mutex::new(LayoutHook::default()));
+
+/// Hook the damage function of the layout.
+pub fn install_hook() -> Result<(), LayoutHookError> {
+    // the argument indices belong to the signature the hook is installed with
+    let damage = layout::get().damage.clone();
+    let (function, signature, prologue) = (
+        damage.function,
+        damage.signature.clone(),
+        damage.prologue.clone(),
+    );
+    let key = format!("{:?}", damage);
+    DAMAGE_HOOK.lock().unwrap().update_keyed(
+        "damage",
+        function,
+        &signature,
+        &prologue,
+        &key,
+        move |signature, invocation| {
+            on_call(&damage, &layout::get(), &signature.args, invocation);
+            invocation.call_original(&invocation.args())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Record a call of the damage function, before the original runs.
+fn on_call(damage: &DamageLayout, layout: &Layout, args: &[NativeType], invocation: &Invocation) {
+    let arg = |i: usize| number(args[i], invocation.arg(i));
+    let attacker = invocation.arg(damage.attacker) as usize;
+    let hit = Hit {
+        attacker,
+        is_player: layout.player.address() == Some(attacker),
+        monster: invocation.arg(damage.monster) as usize,
+        part: arg(damage.part) as i64,
+        value: arg(damage.value),
+        kind: damage.type_name(arg(damage.kind) as i64),
+    };
+    let quest = layout
+        .quest
+        .address()
+        .and_then(|base| layout.quest.fields.get("id")?.read_number(base))
+        .unwrap_or(0.0);
+    damage::on_hit(hit, quest as i64);
+}
+
+/// Numeric value of the raw bits of an argument.
+fn number(ty: NativeType, bits: u64) -> f64 {
+    match ty {
+        NativeType::F32 => f32::from_bits(bits as u32) as f64,
+        NativeType::F64 => f64::from_bits(bits),
+        NativeType::I8 => bits as i8 as f64,
+        NativeType::I16 => bits as i16 as f64,
+        NativeType::I32 => bits as i32 as f64,
+        NativeType::I64 => bits as i64 as f64,
+        NativeType::U8 | NativeType::Bool => bits as u8 as f64,
+        NativeType::U16 => bits as u16 as f64,
+        NativeType::U32 => bits as u32 as f64,
+        _ => bits as f64,
+    }
+}
+
+/// Damage of the current quest, see [`damage::DamageStats`].
+pub fn stats<'lua>(lua: &'lua Lua) -> LuaResult<LuaTable<'lua>> {
+    let stats = damage::stats();
+    let table = lua.create_table()?;
+    table.set("quest", stats.quest)?;
+    table.set("total", stats.total)?;
+    table.set("hits", stats.hits)?;
+    table.set("player", stats.player)?;
+    table.set("monsters", lua.create_table_from(stats.monsters)?)?;
+    table.set("attackers", lua.create_table_from(stats.attackers)?)?;
+    table.set("types", lua.create_table_from(stats.types)?)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number(NativeType::F32, 1.5f32.to_bits() as u64), 1.5);
+        assert_eq!(number(NativeType::I32, (-3i32) as u32 as u64), -3.0);
+        // upper bits of 32-bit registers are garbage
+        assert_eq!(number(NativeType::I32, 0xFFFF_FFFF_0000_0007), 7.0);
+        assert_eq!(number(NativeType::U8, 0x1FF), 255.0);
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde_json::json;
+use tokio::runtime::Handle;
+
+use super::function::{LayoutHook, LayoutHookError};
+use super::layout;
+use crate::config;
+use crate::luavm::event::{self, Carryover};
+use crate::luavm::libs::memory;
+
+static FRAME_HOOK: Lazy<Mutex<LayoutHook>> = Lazy::new(|| Mutex::new(LayoutHook::default()));
+/// Frames come from the update hook, so the timer idles.
+static HOOKED: AtomicBool = AtomicBool::new(false);
+static CLOCK: Lazy<Mutex<FrameClock>> = Lazy::new(|| Mutex::new(FrameClock::default()));
+
+/// Frame counter and the listeners carried over by the budget.
+#[derive(Default)]
+struct FrameClock {
+    frame: u64,
+    last: Option<Instant>,
+    carryover: Carryover,
+}
+
+impl FrameClock {
+    /// Start the next frame, returns its number and the seconds since the
+    /// previous one.
+    fn advance(&mut self, now: Instant) -> (u64, f64) {
+        self.frame += 1;
+        let delta = self
+            .last
+            .map(|last| now.duration_since(last).as_secs_f64())
+            .unwrap_or(0.0);
+        self.last = Some(now);
+        (self.frame, delta)
+    }
+}
+
+/// Register `OnFrame` and start the timer driving it while the update function
+/// isn't hooked, like in headless tests.
+pub fn init_frame_events() {
+    event::register_builtin(
+        "OnFrame",
+        "a game frame is updated, `{frame, delta}`. Throttled by the listener option `everyNFrames`",
+    );
+    thread::spawn(|| loop {
+        thread::sleep(Duration::from_millis(
+            config::get().frame.interval_ms.max(1),
+        ));
+        if !HOOKED.load(Ordering::Relaxed) {
+            on_frame();
+        }
+    });
+}
+
+/// Hook the update function of the layout, unless the timer is forced by the
+/// config.
+pub fn install_hook() -> Result<(), LayoutHookError> {
+    let layout = layout::get();
+    let offset = match config::get().frame.timer {
+        true => 0,
+        false => layout.frame.function,
+    };
+    let result = FRAME_HOOK.lock().unwrap().update(
+        "frame",
+        offset,
+        &layout.frame.signature,
+        &layout.frame.prologue,
+        |_, invocation| {
+            let ret = invocation.call_original(&invocation.args());
+            on_frame();
+            ret
+        },
+    );
+    HOOKED.store(matches!(result, Ok(true)), Ordering::Relaxed);
+
+    result.map(|_| ())
+}
+
+/// End of a frame: invalidate the cached pointers and dispatch `OnFrame`,
+/// on the game thread.
+fn on_frame() {
+    memory::next_tick();
+    let (frame, delta) = CLOCK.lock().unwrap().advance(Instant::now());
+    if !event::has_listeners("OnFrame") {
+        return;
+    }
+    // the VMs can't be locked synchronously from async tasks
+    if Handle::try_current().is_ok() {
+        return;
+    }
+    let Some(handle) = event::runtime() else {
+        return;
+    };
+    let budget = Duration::from_millis(config::get().frame.budget_ms);
+    // the clock isn't held while the listeners run
+    let mut carryover = std::mem::take(&mut CLOCK.lock().unwrap().carryover);
+    handle.block_on(event::dispatch_frame(
+        "OnFrame",
+        &json!({ "frame": frame, "delta": delta }),
+        frame,
+        budget,
+        &mut carryover,
+    ));
+    CLOCK.lock().unwrap().carryover = carryover;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let mut clock = FrameClock::default();
+        let start = Instant::now();
+        assert_eq!(clock.advance(start), (1, 0.0));
+        let (frame, delta) = clock.advance(start + Duration::from_millis(16));
+        assert_eq!(frame, 2);
+        assert!((delta - 0.016).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,142 @@
+use log::debug;
+use snafu::prelude::*;
+
+use crate::luavm::libs::hook::{Detour, DetourError, Invocation};
+use crate::luavm::libs::memory::{is_readable, DefaultModules, Modules};
+use crate::luavm::libs::native::Signature;
+
+#[derive(Debug, Snafu)]
+pub enum LayoutHookError {
+    #[snafu(display("{}", source))]
+    Detour { source: DetourError },
+    #[snafu(display("No `prologue` to check the function at 0x{:x} against", addr))]
+    NoPrologue { addr: usize },
+    #[snafu(display(
+        "Function at 0x{:x} doesn't start with the `prologue` of the layout, expected {:02X?}, found {:02X?}",
+        addr,
+        expected,
+        found
+    ))]
+    PrologueMismatch {
+        addr: usize,
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+}
+
+/// A hook of a game function found by the layout.
+#[derive(Default)]
+pub struct LayoutHook {
+    /// the detour with the signature (or key) it was installed with
+    installed: Option<(Detour, String)>,
+}
+
+impl LayoutHook {
+    /// Hook the function at `offset` of the main module, again if a reloaded
+    /// layout moved it or changed its signature. Offset 0 removes the hook.
+    ///
+    /// The function is only patched if it starts with `prologue`, so an offset
+    /// of another game version can't patch the middle of an instruction.
+    ///
+    /// Returns whether the function is hooked.
+    pub fn update(
+        &mut self,
+        name: &str,
+        offset: usize,
+        signature: &str,
+        prologue: &[u8],
+        handler: impl Fn(&Signature, &Invocation) -> u64 + Send + Sync + 'static,
+    ) -> Result<bool, LayoutHookError> {
+        self.update_keyed(name, offset, signature, prologue, signature, handler)
+    }
+
+    /// [`LayoutHook::update`] for a handler capturing parts of the layout,
+    /// installed again whenever `key` (a description of them) changes.
+    pub fn update_keyed(
+        &mut self,
+        name: &str,
+        offset: usize,
+        signature: &str,
+        prologue: &[u8],
+        key: &str,
+        handler: impl Fn(&Signature, &Invocation) -> u64 + Send + Sync + 'static,
+    ) -> Result<bool, LayoutHookError> {
+        let target = match offset {
+            0 => None,
+            offset => DefaultModules::default()
+                .find(None)
+                .map(|module| module.base + offset),
+        };
+        if let Some((detour, installed)) = &self.installed {
+            if Some(detour.target()) == target && installed == key && detour.is_installed() {
+                return Ok(true);
+            }
+        }
+        if let Some((mut detour, _)) = self.installed.take() {
+            unsafe { detour.remove() }.context(DetourSnafu)?;
+        }
+        let Some(target) = target else {
+            return Ok(false);
+        };
+        // the original bytes are back after removing the previous detour
+        check_prologue(target, prologue)?;
+
+        let parsed = Signature::parse(signature).expect("signature checked by the layout");
+        let detour = unsafe {
+            Detour::install(
+                target,
+                parsed.arg_classes(),
+                parsed.ret.class(),
+                move |invocation| handler(&parsed, invocation),
+            )
+        }
+        .context(DetourSnafu)?;
+        debug!("{} hook installed at 0x{:x}", name, target);
+        self.installed = Some((detour, key.to_string()));
+
+        Ok(true)
+    }
+}
+
+/// Check that the code at `addr` starts with `prologue`.
+fn check_prologue(addr: usize, prologue: &[u8]) -> Result<(), LayoutHookError> {
+    ensure!(!prologue.is_empty(), NoPrologueSnafu { addr });
+    if !is_readable(addr, prologue.len()) {
+        return Err(LayoutHookError::Detour {
+            source: DetourError::Unreadable { addr },
+        });
+    }
+    let found = unsafe { std::slice::from_raw_parts(addr as *const u8, prologue.len()) };
+    ensure!(
+        found == prologue,
+        PrologueMismatchSnafu {
+            addr,
+            expected: prologue.to_vec(),
+            found: found.to_vec(),
+        }
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_prologue() {
+        // mov [rsp+8], rbx; push rdi
+        let code = [0x48, 0x89, 0x5C, 0x24, 0x08, 0x57];
+        let addr = code.as_ptr() as usize;
+        assert!(check_prologue(addr, &code[..5]).is_ok());
+        assert!(matches!(
+            check_prologue(addr, &[0x48, 0x83, 0xEC, 0x28]),
+            Err(LayoutHookError::PrologueMismatch { .. })
+        ));
+        assert!(matches!(
+            check_prologue(addr, &[]),
+            Err(LayoutHookError::NoPrologue { .. })
+        ));
+        assert!(check_prologue(0x10, &code).is_err());
+    }
+}
@@ -0,0 +1,630 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use mlua::prelude::*;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use snafu::prelude::*;
+
+use crate::luavm::libs::memory::{is_readable, DefaultModules, Modules, Resolution, TypeName};
+use crate::luavm::libs::native::Signature;
+
+/// Layouts of the game objects, see `layout.toml`.
+const DEFAULT_LAYOUT: &str = include_str!("layout.toml");
+/// Overrides of the default layouts, for other game versions.
+pub const LAYOUT_PATH: &str = "LuaEngineEx/layout.toml";
+
+static LAYOUT: Lazy<RwLock<Arc<Layout>>> = Lazy::new(|| {
+    RwLock::new(Arc::new(
+        Layout::from_str(DEFAULT_LAYOUT).expect("invalid default layout"),
+    ))
+});
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum LayoutError {
+    #[snafu(display("Failed to read layout file: {}", source))]
+    Read { source: std::io::Error },
+    #[snafu(display("Failed to parse layout: {}", source))]
+    Parse { source: toml::de::Error },
+    #[snafu(display("Invalid layout of `{}`: {}", field, reason))]
+    Invalid { field: String, reason: String },
+}
+
+/// Fields of an object by name.
+pub type ObjectLayout = BTreeMap<String, FieldLayout>;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Layout {
+    pub monster: ObjectLayout,
+    #[serde(default)]
+    pub player: RootLayout,
+    #[serde(default)]
+    pub party: RootLayout,
+    #[serde(default)]
+    pub quest: RootLayout,
+    #[serde(default)]
+    pub damage: DamageLayout,
+    #[serde(default)]
+    pub frame: FunctionLayout,
+    #[serde(default)]
+    pub chat: ChatLayout,
+}
+
+/// A singleton object, found from the main module.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RootLayout {
+    /// pointer chain to the object, relative to the main module
+    pub base: Vec<isize>,
+    #[serde(default)]
+    pub fields: ObjectLayout,
+    /// named values of the `state` field
+    #[serde(default)]
+    pub states: BTreeMap<String, i64>,
+}
+
+impl RootLayout {
+    /// Address of the object, `None` if it doesn't exist now, like the player
+    /// outside of a save.
+    pub fn address(&self) -> Option<usize> {
+        resolve_global(&self.base)
+    }
+}
+
+/// A game function hooked by the engine.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionLayout {
+    /// offset of the function in the main module, 0 to not hook it
+    #[serde(default)]
+    pub function: usize,
+    /// `Native` signature of the function
+    #[serde(default)]
+    pub signature: String,
+    /// first bytes of the function, checked before hooking it
+    #[serde(default)]
+    pub prologue: Vec<u8>,
+}
+
+/// The chat UI.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatLayout {
+    /// pointer chain to the chat UI, messages are queued while it doesn't
+    /// resolve
+    #[serde(default)]
+    pub base: Vec<isize>,
+    #[serde(default)]
+    pub receive: ChatReceiveLayout,
+    #[serde(default)]
+    pub input: ChatInputLayout,
+}
+
+impl ChatLayout {
+    /// Whether the chat UI can show messages, always without a `base`.
+    pub fn is_ready(&self) -> bool {
+        self.base.is_empty() || resolve_global(&self.base).is_some()
+    }
+}
+
+/// The function adding a player message to the chat log, hooked for
+/// `OnChatMessage`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatReceiveLayout {
+    /// offset of the function in the main module, 0 to not hook it
+    #[serde(default)]
+    pub function: usize,
+    /// `Native` signature of the function
+    #[serde(default)]
+    pub signature: String,
+    /// first bytes of the function, checked before hooking it
+    #[serde(default)]
+    pub prologue: Vec<u8>,
+    /// 0-based indices of the string arguments
+    #[serde(default)]
+    pub sender: usize,
+    #[serde(default)]
+    pub text: usize,
+}
+
+/// The function sending a chat message typed by the player, hooked for
+/// `OnChatInput` so listeners and script commands can swallow or change it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatInputLayout {
+    /// offset of the function in the main module, 0 to not hook it
+    #[serde(default)]
+    pub function: usize,
+    /// `Native` signature of the function
+    #[serde(default)]
+    pub signature: String,
+    /// first bytes of the function, checked before hooking it
+    #[serde(default)]
+    pub prologue: Vec<u8>,
+    /// 0-based index of the message argument
+    #[serde(default)]
+    pub text: usize,
+}
+
+/// The function applying a hit to a monster, hooked for `OnDamage`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DamageLayout {
+    /// offset of the function in the main module, 0 to not hook it
+    #[serde(default)]
+    pub function: usize,
+    /// `Native` signature of the function
+    #[serde(default)]
+    pub signature: String,
+    /// first bytes of the function, checked before hooking it
+    #[serde(default)]
+    pub prologue: Vec<u8>,
+    /// 0-based indices of the arguments
+    #[serde(default)]
+    pub monster: usize,
+    #[serde(default)]
+    pub attacker: usize,
+    #[serde(default)]
+    pub part: usize,
+    #[serde(default)]
+    pub value: usize,
+    #[serde(default, rename = "type")]
+    pub kind: usize,
+    /// named values of the `type` argument
+    #[serde(default)]
+    pub types: BTreeMap<String, i64>,
+}
+
+impl DamageLayout {
+    /// Name of a `type` value, the value itself if it has no name.
+    pub fn type_name(&self, kind: i64) -> String {
+        self.types
+            .iter()
+            .find(|(_, &v)| v == kind)
+            .map(|(name, _)| name.clone())
+            .unwrap_or_else(|| kind.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldLayout {
+    pub path: Vec<isize>,
+    /// `path` is relative to the main module instead of the object
+    #[serde(default)]
+    pub global: bool,
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    /// max length of a `cstr`
+    pub len: Option<usize>,
+    /// element size of an `array`
+    pub stride: Option<usize>,
+    /// element count of an `array`
+    pub count: Option<usize>,
+    /// field of an `array` element, the element is skipped if it's zero
+    pub present: Option<String>,
+    /// fields of an `array` element
+    #[serde(default)]
+    pub fields: ObjectLayout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum FieldKind {
+    Scalar(TypeName),
+    CStr,
+    Vec3,
+    Array,
+}
+
+impl TryFrom<String> for FieldKind {
+    type Error = String;
+
+    fn try_from(type_name: String) -> Result<FieldKind, String> {
+        match type_name.as_str() {
+            "cstr" | "string" => Ok(FieldKind::CStr),
+            "vec3" => Ok(FieldKind::Vec3),
+            "array" => Ok(FieldKind::Array),
+            _ => TypeName::from_str(&type_name)
+                .map(FieldKind::Scalar)
+                .ok_or(format!("unknown type `{}`", type_name)),
+        }
+    }
+}
+
+impl Layout {
+    pub fn from_str(s: &str) -> Result<Layout, LayoutError> {
+        Layout::from_table(toml::from_str(s).context(ParseSnafu)?)
+    }
+
+    /// Default layouts with the objects and fields of `overrides` replaced.
+    pub fn with_overrides(overrides: &str) -> Result<Layout, LayoutError> {
+        let mut table: toml::Table = toml::from_str(DEFAULT_LAYOUT).context(ParseSnafu)?;
+        merge(&mut table, toml::from_str(overrides).context(ParseSnafu)?);
+        Layout::from_table(table)
+    }
+
+    fn from_table(table: toml::Table) -> Result<Layout, LayoutError> {
+        let layout: Layout = table.try_into().context(ParseSnafu)?;
+        validate(&layout.monster, "monster")?;
+        validate(&layout.player.fields, "player")?;
+        validate(&layout.party.fields, "party")?;
+        validate(&layout.quest.fields, "quest")?;
+        let damage = &layout.damage;
+        validate_hook(
+            "damage",
+            damage.function,
+            &damage.signature,
+            &[
+                ("monster", damage.monster),
+                ("attacker", damage.attacker),
+                ("part", damage.part),
+                ("value", damage.value),
+                ("type", damage.kind),
+            ],
+        )?;
+        validate_hook("frame", layout.frame.function, &layout.frame.signature, &[])?;
+        let receive = &layout.chat.receive;
+        validate_hook(
+            "chat.receive",
+            receive.function,
+            &receive.signature,
+            &[("sender", receive.sender), ("text", receive.text)],
+        )?;
+        let input = &layout.chat.input;
+        validate_hook(
+            "chat.input",
+            input.function,
+            &input.signature,
+            &[("text", input.text)],
+        )?;
+        Ok(layout)
+    }
+}
+
+/// Merge `overrides` into `table`, recursively for tables.
+fn merge(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(overrides)) => {
+                merge(table, overrides)
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+fn validate(object: &ObjectLayout, prefix: &str) -> Result<(), LayoutError> {
+    for (name, field) in object {
+        let path = format!("{}.{}", prefix, name);
+        let invalid = |reason: &str| LayoutError::Invalid {
+            field: path.clone(),
+            reason: reason.to_string(),
+        };
+        if field.path.is_empty() {
+            return Err(invalid("empty path"));
+        }
+        if field.kind != FieldKind::Array {
+            continue;
+        }
+        if field.stride.is_none() || field.count.is_none() {
+            return Err(invalid("an array needs a `stride` and a `count`"));
+        }
+        if let Some(present) = &field.present {
+            if !field.fields.contains_key(present) {
+                return Err(invalid(&format!("unknown `present` field `{}`", present)));
+            }
+        }
+        validate(&field.fields, &path)?;
+    }
+
+    Ok(())
+}
+
+/// Check the signature of a hooked function and the indices of its arguments.
+fn validate_hook(
+    name: &str,
+    function: usize,
+    signature: &str,
+    args: &[(&str, usize)],
+) -> Result<(), LayoutError> {
+    if function == 0 {
+        return Ok(());
+    }
+    let invalid = |reason: String| LayoutError::Invalid {
+        field: name.to_string(),
+        reason,
+    };
+    let signature = Signature::parse(signature).map_err(|e| invalid(e.to_string()))?;
+    for (arg, i) in args {
+        if *i >= signature.args.len() {
+            return Err(invalid(format!(
+                "argument `{}` is out of the signature",
+                arg
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Layouts of the game objects.
+pub fn get() -> Arc<Layout> {
+    LAYOUT.read().unwrap().clone()
+}
+
+/// (Re)load the layout file. A missing file resets to the defaults.
+pub fn load() -> Result<(), LayoutError> {
+    let layout = match std::fs::read_to_string(LAYOUT_PATH) {
+        Ok(s) => Layout::with_overrides(&s)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Layout::from_str(DEFAULT_LAYOUT)?,
+        Err(e) => return Err(LayoutError::Read { source: e }),
+    };
+    *LAYOUT.write().unwrap() = Arc::new(layout);
+
+    Ok(())
+}
+
+/// Resolve a pointer chain relative to the main module.
+fn resolve_global(path: &[isize]) -> Option<usize> {
+    let (first, rest) = path.split_first()?;
+    let module = DefaultModules::default().find(None)?;
+    Resolution::resolve(module.base.checked_add_signed(*first)?, rest).address()
+}
+
+impl FieldLayout {
+    /// Address of the field of the object at `base`, `None` if the path
+    /// doesn't resolve.
+    pub fn address(&self, base: usize) -> Option<usize> {
+        if self.global {
+            return resolve_global(&self.path);
+        }
+        let (first, rest) = self.path.split_first()?;
+        Resolution::resolve(base.checked_add_signed(*first)?, rest).address()
+    }
+
+    /// Read a numeric or boolean field of the object at `base`.
+    pub fn read_number(&self, base: usize) -> Option<f64> {
+        let FieldKind::Scalar(ty) = self.kind else {
+            return None;
+        };
+        let bytes = read_bytes(self.address(base)?, ty.size()?)?;
+        match ty.decode(&bytes) {
+            LuaValue::Integer(i) => Some(i as f64),
+            LuaValue::Number(n) => Some(n),
+            LuaValue::Boolean(b) => Some(b as u8 as f64),
+            _ => None,
+        }
+    }
+
+    /// Read a `cstr` field of the object at `base`.
+    pub fn read_string(&self, base: usize) -> Option<String> {
+        if self.kind != FieldKind::CStr {
+            return None;
+        }
+        let bytes = read_cstr(self.address(base)?, self.len.unwrap_or(32))?;
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Read the field of the object at `base`, `nil` if it's not readable.
+    pub fn read<'lua>(&self, lua: &'lua Lua, base: usize) -> LuaResult<LuaValue<'lua>> {
+        let Some(addr) = self.address(base) else {
+            return Ok(LuaNil);
+        };
+        match self.kind {
+            FieldKind::Scalar(ty) => {
+                let Some(size) = ty.size() else {
+                    return Ok(LuaNil);
+                };
+                Ok(read_bytes(addr, size)
+                    .map(|bytes| ty.decode(&bytes))
+                    .unwrap_or(LuaNil))
+            }
+            FieldKind::CStr => match read_cstr(addr, self.len.unwrap_or(32)) {
+                Some(s) => Ok(LuaValue::String(lua.create_string(s)?)),
+                None => Ok(LuaNil),
+            },
+            FieldKind::Vec3 => {
+                let Some(bytes) = read_bytes(addr, 12) else {
+                    return Ok(LuaNil);
+                };
+                let table = lua.create_table()?;
+                for (i, key) in ["x", "y", "z"].into_iter().enumerate() {
+                    table.set(key, TypeName::F32.decode(&bytes[i * 4..]))?;
+                }
+                Ok(LuaValue::Table(table))
+            }
+            FieldKind::Array => {
+                let elements = lua.create_table()?;
+                for i in 0..self.count.unwrap_or(0) {
+                    let element = addr + i * self.stride.unwrap_or(0);
+                    if let Some(present) = self.present.as_ref().and_then(|p| self.fields.get(p)) {
+                        if is_zero(&present.read(lua, element)?) {
+                            continue;
+                        }
+                    }
+                    let table = read_object(lua, &self.fields, element)?;
+                    table.set("index", i + 1)?;
+                    elements.push(table)?;
+                }
+                Ok(LuaValue::Table(elements))
+            }
+        }
+    }
+}
+
+/// Read all fields of the object at `base`.
+pub fn read_object<'lua>(
+    lua: &'lua Lua,
+    object: &ObjectLayout,
+    base: usize,
+) -> LuaResult<LuaTable<'lua>> {
+    let table = lua.create_table()?;
+    for (name, field) in object {
+        table.set(name.as_str(), field.read(lua, base)?)?;
+    }
+    Ok(table)
+}
+
+fn is_zero(value: &LuaValue) -> bool {
+    match value {
+        LuaNil => true,
+        LuaValue::Boolean(b) => !b,
+        LuaValue::Integer(i) => *i == 0,
+        LuaValue::Number(n) => *n == 0.0,
+        LuaValue::String(s) => s.as_bytes().is_empty(),
+        _ => false,
+    }
+}
+
+fn read_bytes(addr: usize, len: usize) -> Option<Vec<u8>> {
+    if !is_readable(addr, len) {
+        return None;
+    }
+    let mut bytes = vec![0u8; len];
+    unsafe { std::ptr::copy_nonoverlapping(addr as *const u8, bytes.as_mut_ptr(), len) };
+    Some(bytes)
+}
+
+/// Read a NUL terminated string of at most `max_len` bytes. The whole
+/// `max_len` range must be readable.
+pub fn read_cstr(addr: usize, max_len: usize) -> Option<Vec<u8>> {
+    let mut bytes = read_bytes(addr, max_len)?;
+    if let Some(end) = bytes.iter().position(|&b| b == 0) {
+        bytes.truncate(end);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    struct Part {
+        max_hp: f32,
+        hp: f32,
+    }
+
+    #[repr(C)]
+    struct Object {
+        id: i32,
+        position: [f32; 3],
+        name: *const u8,
+        parts: [Part; 3],
+    }
+
+    const LAYOUT: &str = r#"
+        [monster.id]
+        path = [0x0]
+        type = "i32"
+
+        [monster.position]
+        path = [0x4]
+        type = "vec3"
+
+        [monster.name]
+        path = [0x10, 0x0]
+        type = "cstr"
+
+        [monster.parts]
+        path = [0x18]
+        type = "array"
+        stride = 8
+        count = 3
+        present = "maxHp"
+        fields.maxHp = { path = [0x0], type = "f32" }
+        fields.hp = { path = [0x4], type = "f32" }
+    "#;
+
+    #[test]
+    fn test_default_layout() {
+        let layout = get();
+        assert!(layout.monster.contains_key("hp"));
+        assert!(layout.player.fields.contains_key("weaponType"));
+        assert!(layout.party.fields.contains_key("members"));
+        assert_eq!(layout.quest.states.get("active"), Some(&2));
+        assert_eq!(layout.damage.type_name(1), "element");
+        assert_eq!(layout.damage.type_name(9), "9");
+    }
+
+    #[test]
+    fn test_overrides() {
+        let layout = Layout::with_overrides(
+            r#"
+            player.base = [0x100, 0x8]
+            monster.hp = { path = [0x10], type = "f64" }
+            damage.prologue = [0x48, 0x89, 0x5C]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(layout.damage.prologue, vec![0x48, 0x89, 0x5C]);
+        assert_eq!(layout.damage.types.get("status"), Some(&2));
+        assert_eq!(layout.player.base, vec![0x100, 0x8]);
+        assert!(layout.player.fields.contains_key("hp"));
+        assert_eq!(layout.monster["hp"].path, vec![0x10]);
+        assert_eq!(layout.monster["hp"].kind, FieldKind::Scalar(TypeName::F64));
+        assert!(layout.monster.contains_key("maxHp"));
+    }
+
+    #[test]
+    fn test_read() {
+        let layout = Layout::from_str(LAYOUT).unwrap();
+        let name = b"em001_00\0";
+        let object = Object {
+            id: 7,
+            position: [1.0, 2.0, 3.0],
+            name: name.as_ptr(),
+            parts: [
+                Part {
+                    max_hp: 100.0,
+                    hp: 50.0,
+                },
+                Part {
+                    max_hp: 0.0,
+                    hp: 0.0,
+                },
+                Part {
+                    max_hp: 20.0,
+                    hp: 20.0,
+                },
+            ],
+        };
+        let lua = Lua::new();
+        let table = read_object(&lua, &layout.monster, &object as *const _ as usize).unwrap();
+        lua.globals().set("m", table).unwrap();
+        lua.load(
+            r#"
+            assert(m.id == 7)
+            assert(m.position.x == 1 and m.position.z == 3)
+            assert(m.name == "em001_00")
+            assert(#m.parts == 2 and m.parts[1].hp == 50)
+            assert(m.parts[2].index == 3 and m.parts[2].maxHp == 20)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_invalid() {
+        for invalid in [
+            r#"monster.hp = { path = [], type = "f32" }"#,
+            r#"monster.hp = { path = [0], type = "u128" }"#,
+            r#"monster.parts = { path = [0], type = "array" }"#,
+            r#"monster.parts = { path = [0], type = "array", stride = 1, count = 1, present = "hp" }"#,
+            "monster = {}\ndamage = { function = 1, signature = \"void(ptr)\", value = 3 }",
+            "monster = {}\ndamage = { function = 1, signature = \"void(u128)\" }",
+            "monster = {}\ndamage = { prologue = [0x100] }",
+            "monster = {}\nframe = { function = 1, signature = \"void(\" }",
+            "monster = {}\nchat.receive = { function = 1, signature = \"void(ptr, str)\", text = 2 }",
+            "monster = {}\nchat.input = { function = 1, signature = \"void(ptr)\", text = 1 }",
+        ] {
+            assert!(Layout::from_str(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+mod chat;
+mod damage;
+mod frame;
+mod function;
+mod layout;
+mod monster;
+mod player;
+mod poll;
+mod quest;
+
+use log::error;
+use mlua::prelude::*;
+use mlua::UserData;
+
+use crate::hooks;
+use chat::Chat;
+use monster::{Monster, MonsterFilter};
+use player::Player;
+use quest::Quest;
+
+pub use chat::{is_input_hooked, show_engine_message, show_script_error};
+pub use layout::load as load_layout;
+pub use poll::init_game_events;
+
+/// Hook the game functions of the layout, called after the layout is loaded.
+pub fn install_hooks() {
+    if let Err(e) = damage::install_hook() {
+        error!("damage hook error: {}", e);
+    }
+    if let Err(e) = frame::install_hook() {
+        error!("frame hook error: {}", e);
+    }
+    if let Err(e) = chat::install_hook() {
+        error!("chat hook error: {}", e);
+    }
+    if let Err(e) = chat::install_input_hook() {
+        error!("chat input hook error: {}", e);
+    }
+}
+
+/// Stop phase of the game library.
+pub fn on_stop(lua: &Lua) {
+    chat::on_stop(lua);
+}
+
+pub struct Game;
+
+impl UserData for Game {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("Chat", |_, _| Ok(Chat));
+        fields.add_field_method_get("player", |_, _| Ok(Player));
+        fields.add_field_method_get("quest", |_, _| Ok(Quest));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function("getAllMonsters", |lua, ()| {
+            let monsters = hooks::monster::get_all_monsters();
+            if monsters.is_empty() {
+                return Ok(LuaValue::Nil);
+            }
+
+            Ok(LuaValue::Table(lua.create_sequence_from(monsters)?))
+        });
+        methods.add_function("monsters", |_, filter: Option<LuaTable>| {
+            Ok(Monster::all(&MonsterFilter::from_lua(filter)?))
+        });
+        methods.add_function("monster", |_, handle: u64| Ok(Monster::get(handle)));
+        methods.add_function("party", |lua, ()| player::party(lua));
+        methods.add_function("damage", |lua, ()| damage::stats(lua));
+    }
+}
@@ -0,0 +1,159 @@
+use mlua::prelude::*;
+use mlua::UserData;
+
+use super::layout;
+use crate::hooks;
+
+/// Handle of a spawned monster.
+///
+/// Fields are read from the monster layout on access. Once the monster is
+/// destroyed the handle is invalid: reading its fields is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Monster {
+    pub handle: u64,
+    pub address: usize,
+}
+
+/// Filters of `Game.monsters`.
+#[derive(Debug, Clone, Default)]
+pub struct MonsterFilter {
+    pub large_only: bool,
+    pub species: Option<String>,
+}
+
+impl MonsterFilter {
+    pub fn from_lua(filter: Option<LuaTable>) -> LuaResult<MonsterFilter> {
+        let Some(filter) = filter else {
+            return Ok(MonsterFilter::default());
+        };
+        Ok(MonsterFilter {
+            large_only: filter.get::<_, Option<bool>>("largeOnly")?.unwrap_or(false),
+            species: filter.get("species")?,
+        })
+    }
+
+    fn matches(&self, monster: &Monster) -> bool {
+        if !self.large_only && self.species.is_none() {
+            return true;
+        }
+        let species = monster.species();
+        if self.large_only && !species.as_deref().is_some_and(is_large) {
+            return false;
+        }
+        if let Some(expected) = &self.species {
+            return species.as_ref() == Some(expected);
+        }
+        true
+    }
+}
+
+impl Monster {
+    pub fn get(handle: u64) -> Option<Monster> {
+        hooks::monster::address_of(handle).map(|address| Monster { handle, address })
+    }
+
+    /// Alive monsters in spawn order.
+    pub fn all(filter: &MonsterFilter) -> Vec<Monster> {
+        hooks::monster::monsters()
+            .into_iter()
+            .map(|(handle, address)| Monster { handle, address })
+            .filter(|monster| filter.matches(monster))
+            .collect()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        hooks::monster::address_of(self.handle) == Some(self.address)
+    }
+
+    fn check_valid(&self) -> LuaResult<()> {
+        if !self.is_valid() {
+            return Err(LuaError::runtime(format!(
+                "Monster {} was destroyed",
+                self.handle
+            )));
+        }
+        Ok(())
+    }
+
+    /// Model name like `em001_00`, small monsters are `ems...`.
+    pub fn species(&self) -> Option<String> {
+        let path = layout::get()
+            .monster
+            .get("species")?
+            .read_string(self.address)?;
+        Some(normalize_species(&path))
+    }
+}
+
+/// Last component of a model path like `em\em001\00\mod\em001_00`.
+fn normalize_species(path: &str) -> String {
+    path.rsplit(['\\', '/']).next().unwrap_or(path).to_string()
+}
+
+fn is_large(species: &str) -> bool {
+    species.starts_with("em") && !species.starts_with("ems")
+}
+
+impl UserData for Monster {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("handle", |_, this| Ok(this.handle));
+        fields.add_field_method_get("address", |_, this| Ok(this.address));
+        fields.add_field_method_get("species", |_, this| {
+            this.check_valid()?;
+            Ok(this.species())
+        });
+        fields.add_field_method_get("isLarge", |_, this| {
+            this.check_valid()?;
+            Ok(this.species().as_deref().is_some_and(is_large))
+        });
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("isValid", |_, this, ()| Ok(this.is_valid()));
+        // all layout fields at once
+        methods.add_method("read", |lua, this, ()| {
+            this.check_valid()?;
+            layout::read_object(lua, &layout::get().monster, this.address)
+        });
+        methods.add_meta_method(LuaMetaMethod::Index, |lua, this, key: String| {
+            let layout = layout::get();
+            let Some(field) = layout.monster.get(&key) else {
+                return Err(LuaError::runtime(format!(
+                    "Unknown monster field `{}`",
+                    key
+                )));
+            };
+            this.check_valid()?;
+            field.read(lua, this.address)
+        });
+        methods.add_meta_method(
+            LuaMetaMethod::Eq,
+            |_, this, other: LuaUserDataRef<Monster>| Ok(*this == *other),
+        );
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            if !this.is_valid() {
+                return Ok(format!("Monster({}, destroyed)", this.handle));
+            }
+            Ok(format!(
+                "Monster({}, {}, 0x{:x})",
+                this.handle,
+                this.species().unwrap_or_else(|| "?".to_string()),
+                this.address
+            ))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_species() {
+        assert_eq!(normalize_species(r"em\em001\00\mod\em001_00"), "em001_00");
+        assert_eq!(normalize_species("em001_00"), "em001_00");
+        assert!(is_large("em001_00"));
+        assert!(!is_large("ems001_00"));
+        assert!(!is_large(""));
+    }
+}
@@ -0,0 +1,151 @@
+use mlua::prelude::*;
+use mlua::UserData;
+use serde_json::json;
+
+use super::layout::{self, Layout};
+use super::poll::Watch;
+use crate::luavm::libs::plugin::payload::Payload;
+
+/// The local player, fields are read from the player layout on access and are
+/// `nil` outside of a save.
+pub struct Player;
+
+impl UserData for Player {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("isAvailable", |_, _, ()| {
+            Ok(layout::get().player.address().is_some())
+        });
+        // all layout fields at once
+        methods.add_method("read", |lua, _, ()| {
+            let layout = layout::get();
+            match layout.player.address() {
+                Some(base) => Ok(Some(layout::read_object(lua, &layout.player.fields, base)?)),
+                None => Ok(None),
+            }
+        });
+        methods.add_meta_method(LuaMetaMethod::Index, |lua, _, key: String| {
+            let layout = layout::get();
+            let Some(field) = layout.player.fields.get(&key) else {
+                return Err(LuaError::runtime(format!("Unknown player field `{}`", key)));
+            };
+            match layout.player.address() {
+                Some(base) => field.read(lua, base),
+                None => Ok(LuaNil),
+            }
+        });
+    }
+}
+
+/// Members of the current party, an empty table outside of a session.
+pub fn party<'lua>(lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+    let layout = layout::get();
+    let (Some(base), Some(members)) = (layout.party.address(), layout.party.fields.get("members"))
+    else {
+        return Ok(LuaValue::Table(lua.create_table()?));
+    };
+    members.read(lua, base)
+}
+
+/// Player state compared between polls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PlayerState {
+    hp: f64,
+    max_hp: f64,
+    weapon_type: i64,
+}
+
+impl PlayerState {
+    fn read(layout: &Layout) -> Option<PlayerState> {
+        let base = layout.player.address()?;
+        let number = |name: &str| layout.player.fields.get(name)?.read_number(base);
+        Some(PlayerState {
+            hp: number("hp")?,
+            max_hp: number("maxHp")?,
+            weapon_type: number("weaponType")? as i64,
+        })
+    }
+
+    /// Events of the changes from `prev`.
+    fn changes(&self, prev: &PlayerState) -> Vec<(&'static str, Payload)> {
+        let mut events = Vec::new();
+        if self.hp < prev.hp {
+            events.push((
+                "OnPlayerDamage",
+                json!({ "hp": self.hp, "maxHp": self.max_hp, "damage": prev.hp - self.hp }),
+            ));
+        }
+        if self.weapon_type != prev.weapon_type {
+            events.push((
+                "OnWeaponChange",
+                json!({ "weaponType": self.weapon_type, "previous": prev.weapon_type }),
+            ));
+        }
+        events
+    }
+}
+
+/// Player events from changes of the player state.
+#[derive(Default)]
+pub struct PlayerWatch {
+    prev: Option<PlayerState>,
+}
+
+impl Watch for PlayerWatch {
+    fn events(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            (
+                "OnPlayerDamage",
+                "the player loses HP, `{hp, maxHp, damage}`",
+            ),
+            (
+                "OnWeaponChange",
+                "the player changes weapon, `{weaponType, previous}`",
+            ),
+        ]
+    }
+
+    fn poll(&mut self, layout: &Layout) -> Vec<(&'static str, Payload)> {
+        let state = PlayerState::read(layout);
+        let events = match (&self.prev, &state) {
+            (Some(prev), Some(state)) => state.changes(prev),
+            _ => Vec::new(),
+        };
+        self.prev = state;
+        events
+    }
+
+    fn reset(&mut self) {
+        self.prev = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let prev = PlayerState {
+            hp: 150.0,
+            max_hp: 150.0,
+            weapon_type: 3,
+        };
+        assert!(prev.changes(&prev).is_empty());
+
+        let hit = PlayerState { hp: 120.0, ..prev };
+        let events = hit.changes(&prev);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, "OnPlayerDamage");
+        assert_eq!(events[0].1["damage"], 30.0);
+        // healing is not an event
+        assert!(prev.changes(&hit).is_empty());
+
+        let swap = PlayerState {
+            weapon_type: 11,
+            ..prev
+        };
+        let events = swap.changes(&prev);
+        assert_eq!(events[0].0, "OnWeaponChange");
+        assert_eq!(events[0].1["previous"], 3);
+    }
+}
@@ -0,0 +1,55 @@
+use std::thread;
+use std::time::Duration;
+
+use super::layout::{self, Layout};
+use super::player::PlayerWatch;
+use super::quest::QuestWatch;
+use super::{chat, frame};
+use crate::luavm::event;
+use crate::luavm::libs::plugin::payload::Payload;
+
+/// Interval of the game state polling.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Events from changes of game state between polls.
+pub trait Watch: Send {
+    /// Name and description of the events.
+    fn events(&self) -> &'static [(&'static str, &'static str)];
+
+    /// Read the state and return the events of its changes since the last poll.
+    fn poll(&mut self, layout: &Layout) -> Vec<(&'static str, Payload)>;
+
+    /// Forget the last state, called while the events have no listeners.
+    fn reset(&mut self);
+}
+
+/// Register the game state events and start polling the game state.
+pub fn init_game_events() {
+    frame::init_frame_events();
+    chat::init_chat_events();
+    let mut watches: Vec<Box<dyn Watch>> =
+        vec![Box::<PlayerWatch>::default(), Box::<QuestWatch>::default()];
+    for watch in watches.iter() {
+        for (name, description) in watch.events() {
+            event::register_builtin(name, description);
+        }
+    }
+
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+        let layout = layout::get();
+        for watch in watches.iter_mut() {
+            if !watch
+                .events()
+                .iter()
+                .any(|(name, _)| event::has_listeners(name))
+            {
+                watch.reset();
+                continue;
+            }
+            for (name, payload) in watch.poll(&layout) {
+                event::emit(name.to_string(), payload);
+            }
+        }
+    });
+}
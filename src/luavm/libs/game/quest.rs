@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use mlua::prelude::*;
+use mlua::UserData;
+use serde_json::json;
+
+use super::layout::{self, Layout};
+use super::poll::Watch;
+use crate::luavm::libs::plugin::payload::Payload;
+
+/// The current quest, fields are read from the quest layout on access and are
+/// `nil` without a quest. `state` is the name of the state, like `active`.
+pub struct Quest;
+
+impl UserData for Quest {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("isActive", |_, _, ()| {
+            Ok(QuestState::read(&layout::get()).is_some_and(|state| state.phase == Phase::Active))
+        });
+        // all layout fields at once
+        methods.add_method("read", |lua, _, ()| {
+            let layout = layout::get();
+            let Some(base) = layout.quest.address() else {
+                return Ok(None);
+            };
+            let table = layout::read_object(lua, &layout.quest.fields, base)?;
+            table.set("state", state_name(lua, &layout, table.get("state")?)?)?;
+            Ok(Some(table))
+        });
+        methods.add_meta_method(LuaMetaMethod::Index, |lua, _, key: String| {
+            let layout = layout::get();
+            let Some(field) = layout.quest.fields.get(&key) else {
+                return Err(LuaError::runtime(format!("Unknown quest field `{}`", key)));
+            };
+            let value = if field.global {
+                field.read(lua, 0)?
+            } else {
+                match layout.quest.address() {
+                    Some(base) => field.read(lua, base)?,
+                    None => return Ok(LuaNil),
+                }
+            };
+            if key == "state" {
+                return state_name(lua, &layout, value);
+            }
+            Ok(value)
+        });
+    }
+}
+
+/// Name of a `state` value, the value itself if it has no name.
+fn state_name<'lua>(
+    lua: &'lua Lua,
+    layout: &Layout,
+    value: LuaValue<'lua>,
+) -> LuaResult<LuaValue<'lua>> {
+    let LuaValue::Integer(state) = value else {
+        return Ok(value);
+    };
+    match layout.quest.states.iter().find(|(_, &v)| v == state) {
+        Some((name, _)) => Ok(LuaValue::String(lua.create_string(name)?)),
+        None => Ok(value),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Active,
+    Complete,
+    Failed,
+}
+
+impl Phase {
+    fn of(states: &BTreeMap<String, i64>, state: i64) -> Phase {
+        let name = states
+            .iter()
+            .find(|(_, &v)| v == state)
+            .map(|(name, _)| name);
+        match name.map(String::as_str) {
+            Some("active") => Phase::Active,
+            Some("complete") => Phase::Complete,
+            Some("failed") => Phase::Failed,
+            _ => Phase::Idle,
+        }
+    }
+}
+
+/// Quest state compared between polls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct QuestState {
+    id: i64,
+    phase: Phase,
+    stage: Option<i64>,
+}
+
+impl QuestState {
+    /// `None` if the quest state isn't readable, like on a loading screen.
+    fn read(layout: &Layout) -> Option<QuestState> {
+        let quest = &layout.quest;
+        let base = quest.address();
+        let number = |name: &str| {
+            let field = quest.fields.get(name)?;
+            field.read_number(if field.global { 0 } else { base? })
+        };
+        Some(QuestState {
+            id: number("id")? as i64,
+            phase: Phase::of(&quest.states, number("state")? as i64),
+            stage: number("stage").map(|stage| stage as i64),
+        })
+    }
+
+    /// Events of the changes from `prev`, `elapsed` is the time since the quest
+    /// started in seconds.
+    fn changes(&self, prev: &QuestState, elapsed: f64) -> Vec<(&'static str, Payload)> {
+        let mut events = Vec::new();
+        if let (Some(stage), true) = (self.stage, self.stage != prev.stage) {
+            events.push((
+                "OnStageChange",
+                json!({ "stage": stage, "previous": prev.stage }),
+            ));
+        }
+        let end = |result: &str| json!({ "id": prev.id, "result": result, "elapsed": elapsed });
+        match (prev.phase, self.phase) {
+            (Phase::Active, Phase::Active) if prev.id == self.id => (),
+            (Phase::Active, Phase::Complete) => events.push(("OnQuestEnd", end("complete"))),
+            (Phase::Active, Phase::Failed) => events.push(("OnQuestEnd", end("failed"))),
+            (Phase::Active, _) => events.push((
+                "OnQuestAbandon",
+                json!({ "id": prev.id, "elapsed": elapsed }),
+            )),
+            _ => (),
+        }
+        if self.phase == Phase::Active && (prev.phase != Phase::Active || prev.id != self.id) {
+            events.push(("OnQuestStart", json!({ "id": self.id })));
+        }
+        events
+    }
+}
+
+/// Quest events from changes of the quest state.
+#[derive(Default)]
+pub struct QuestWatch {
+    prev: Option<QuestState>,
+    started: Option<Instant>,
+}
+
+impl Watch for QuestWatch {
+    fn events(&self) -> &'static [(&'static str, &'static str)] {
+        &[
+            ("OnQuestStart", "a quest starts, `{id}`"),
+            (
+                "OnQuestEnd",
+                "a quest is complete or failed, `{id, result, elapsed}`",
+            ),
+            (
+                "OnQuestAbandon",
+                "a quest is abandoned or left before its end, `{id, elapsed}`",
+            ),
+            (
+                "OnStageChange",
+                "the player enters a stage, `{stage, previous}`",
+            ),
+        ]
+    }
+
+    fn poll(&mut self, layout: &Layout) -> Vec<(&'static str, Payload)> {
+        self.update(QuestState::read(layout))
+    }
+
+    fn reset(&mut self) {
+        self.prev = None;
+        self.started = None;
+    }
+}
+
+impl QuestWatch {
+    /// Events of a new state. An unreadable state is skipped, so the next
+    /// readable one is compared to the last readable one.
+    fn update(&mut self, state: Option<QuestState>) -> Vec<(&'static str, Payload)> {
+        let Some(state) = state else {
+            return Vec::new();
+        };
+        let elapsed = self
+            .started
+            .map(|started| started.elapsed().as_secs_f64())
+            .unwrap_or(0.0);
+        let events = match &self.prev {
+            Some(prev) => state.changes(prev, elapsed),
+            None => Vec::new(),
+        };
+        if events.iter().any(|(name, _)| *name == "OnQuestStart") {
+            self.started = Some(Instant::now());
+        }
+        self.prev = Some(state);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(id: i64, phase: Phase, stage: i64) -> QuestState {
+        QuestState {
+            id,
+            phase,
+            stage: Some(stage),
+        }
+    }
+
+    fn names(events: Vec<(&'static str, Payload)>) -> Vec<&'static str> {
+        events.into_iter().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn test_changes() {
+        let hub = state(0, Phase::Idle, 1);
+        let active = state(101, Phase::Active, 5);
+        assert!(hub.changes(&hub, 0.0).is_empty());
+        assert_eq!(
+            names(active.changes(&hub, 0.0)),
+            vec!["OnStageChange", "OnQuestStart"]
+        );
+        assert!(active.changes(&active, 10.0).is_empty());
+
+        let events = state(101, Phase::Complete, 5).changes(&active, 42.0);
+        assert_eq!(events[0].0, "OnQuestEnd");
+        assert_eq!(
+            events[0].1,
+            json!({"id": 101, "result": "complete", "elapsed": 42.0})
+        );
+        assert_eq!(
+            names(state(101, Phase::Failed, 5).changes(&active, 1.0)),
+            vec!["OnQuestEnd"]
+        );
+        assert_eq!(
+            names(hub.changes(&active, 1.0)),
+            vec!["OnStageChange", "OnQuestAbandon"]
+        );
+        // returning to the hub after the end
+        assert_eq!(
+            names(hub.changes(&state(101, Phase::Complete, 5), 1.0)),
+            vec!["OnStageChange"]
+        );
+        assert_eq!(
+            names(state(102, Phase::Active, 5).changes(&active, 1.0)),
+            vec!["OnQuestAbandon", "OnQuestStart"]
+        );
+    }
+
+    #[test]
+    fn test_unreadable() {
+        let mut watch = QuestWatch::default();
+        let active = state(101, Phase::Active, 5);
+        assert!(watch.update(Some(state(0, Phase::Idle, 5))).is_empty());
+        assert_eq!(names(watch.update(Some(active))), vec!["OnQuestStart"]);
+        // a loading screen during the quest
+        assert!(watch.update(None).is_empty());
+        assert!(watch.update(Some(active)).is_empty());
+        assert!(watch.started.is_some());
+    }
+
+    #[test]
+    fn test_phase() {
+        let states = layout::get().quest.states.clone();
+        assert_eq!(Phase::of(&states, states["active"]), Phase::Active);
+        assert_eq!(Phase::of(&states, states["failed"]), Phase::Failed);
+        assert_eq!(Phase::of(&states, -1), Phase::Idle);
+    }
+}
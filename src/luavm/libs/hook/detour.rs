@@ -0,0 +1,532 @@
+//! Inline hooks of Win64 functions.
+//!
+//! The first instructions of the target are replaced by a jump to a
+//! generated entry thunk, which spills the register arguments and calls
+//! [`dispatch`] with the hook data. The replaced instructions are relocated
+//! into a trampoline followed by a jump back, so the original function can
+//! still be called.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use iced_x86::code_asm::*;
+use iced_x86::{
+    BlockEncoder, BlockEncoderOptions, Decoder, DecoderOptions, FlowControl, Instruction,
+    InstructionBlock,
+};
+use once_cell::sync::Lazy;
+use snafu::prelude::*;
+
+use super::exec::{self, ExecError};
+use crate::luavm::libs::memory::{is_readable, DefaultProtection, Patch, PatchError, NOP};
+use crate::luavm::libs::native::call::{self, Abi, Class};
+
+/// `jmp rel32`
+const JMP_REL32_LEN: usize = 5;
+/// `jmp qword ptr [rip]` followed by the absolute address
+const JMP_ABS_LEN: usize = 14;
+/// Bytes decoded at most to find the instructions to relocate.
+const MAX_PROLOGUE: usize = 32;
+/// Size of the executable block holding the thunk and the trampoline.
+const BLOCK_SIZE: usize = 256;
+/// Offset of the trampoline in the block.
+const TRAMPOLINE_OFFSET: usize = 128;
+
+/// Blocks of removed hooks by target, reused when the target is hooked again.
+static POOL: Lazy<Mutex<HashMap<usize, Vec<Block>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Snafu)]
+pub enum DetourError {
+    #[snafu(display("{}", source))]
+    Exec { source: ExecError },
+    #[snafu(display("{}", source))]
+    Patch { source: PatchError },
+    #[snafu(display("Function at 0x{:x} is not readable", addr))]
+    Unreadable { addr: usize },
+    #[snafu(display("Invalid instruction at 0x{:x}", addr))]
+    InvalidInstruction { addr: usize },
+    #[snafu(display("Function at 0x{:x} is too short to hook", addr))]
+    TooShort { addr: usize },
+    #[snafu(display("Failed to relocate instructions of 0x{:x}: {}", addr, message))]
+    Relocate { addr: usize, message: String },
+    #[snafu(display("Too many arguments: {} (at most {})", count, max))]
+    TooManyArgs { count: usize, max: usize },
+}
+
+/// Called for every call of a hooked function, returns the raw return value.
+type Handler = Box<dyn Fn(&Invocation) -> u64 + Send + Sync>;
+
+thread_local! {
+    /// Set while handlers of this thread run, so nested hooked calls go
+    /// straight to the original function.
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` with hook handlers disabled on this thread.
+pub fn passthrough<R>(f: impl FnOnce() -> R) -> R {
+    let busy = BUSY.with(|busy| busy.replace(true));
+    let result = f();
+    BUSY.with(|b| b.set(busy));
+    result
+}
+
+/// Data of a block referenced by its thunk.
+struct HookData {
+    target: usize,
+    trampoline: usize,
+    hook: RwLock<Arc<Hook>>,
+}
+
+/// Signature and handler of the hook using a block, replaced as a whole when
+/// the block is reused.
+struct Hook {
+    args: Vec<Class>,
+    ret: Class,
+    /// `None` once the hook is removed
+    handler: Option<Handler>,
+}
+
+/// Executable block with the thunk and the trampoline of a target.
+#[derive(Clone)]
+struct Block {
+    addr: usize,
+    /// original instructions relocated into the trampoline
+    prologue: Vec<u8>,
+    data: &'static HookData,
+}
+
+/// Arguments of one call of a hooked function.
+pub struct Invocation<'a> {
+    data: &'a HookData,
+    hook: &'a Hook,
+    /// rcx, rdx, r8, r9, xmm0 - xmm3
+    regs: &'a [u64; 8],
+    /// first stack argument
+    stack: *const u64,
+}
+
+impl Invocation<'_> {
+    /// Address of the hooked function.
+    pub fn target(&self) -> usize {
+        self.data.target
+    }
+
+    /// Raw bits of the `i`-th (0-based) argument.
+    pub fn arg(&self, i: usize) -> u64 {
+        match i {
+            0..=3 if self.hook.args[i] == Class::Float => self.regs[4 + i],
+            0..=3 => self.regs[i],
+            _ => unsafe { *self.stack.add(i - 4) },
+        }
+    }
+
+    pub fn args(&self) -> Vec<(Class, u64)> {
+        (0..self.hook.args.len())
+            .map(|i| (self.hook.args[i], self.arg(i)))
+            .collect()
+    }
+
+    /// Call the original function with (possibly modified) raw arguments.
+    pub fn call_original(&self, args: &[(Class, u64)]) -> u64 {
+        unsafe { call::call(Abi::Win64, self.data.trampoline, args, self.hook.ret) }
+    }
+}
+
+/// Entry of all thunks.
+extern "win64" fn dispatch(data: *const HookData, regs: *const [u64; 8], stack: *const u64) -> u64 {
+    let data = unsafe { &*data };
+    // not holding the lock while the handler runs, so hooks can be removed meanwhile
+    let hook = data.hook.read().unwrap().clone();
+    let invocation = Invocation {
+        data,
+        hook: &hook,
+        regs: unsafe { &*regs },
+        stack,
+    };
+    match &hook.handler {
+        Some(handler) if !BUSY.with(|busy| busy.get()) => passthrough(|| handler(&invocation)),
+        _ => invocation.call_original(&invocation.args()),
+    }
+}
+
+/// An installed inline hook.
+pub struct Detour {
+    patch: Patch,
+    block: Block,
+}
+
+impl Detour {
+    /// Hook the Win64 function at `target` taking arguments of the classes `args`.
+    ///
+    /// The block of a removed hook of `target` is reused if there is one.
+    ///
+    /// # Safety
+    ///
+    /// `target` must be the start of a function matching `args` and `ret`,
+    /// whose first instructions are not jump targets.
+    pub unsafe fn install(
+        target: usize,
+        args: Vec<Class>,
+        ret: Class,
+        handler: impl Fn(&Invocation) -> u64 + Send + Sync + 'static,
+    ) -> Result<Detour, DetourError> {
+        call::check(Abi::Win64, &args).map_err(|_| DetourError::TooManyArgs {
+            count: args.len(),
+            max: call::WIN64_MAX_ARGS,
+        })?;
+        let hook = Arc::new(Hook {
+            args,
+            ret,
+            handler: Some(Box::new(handler)),
+        });
+        let block = match Block::take(target) {
+            Some(block) => {
+                *block.data.hook.write().unwrap() = hook;
+                block
+            }
+            None => Block::build(target, hook)?,
+        };
+        let original = std::slice::from_raw_parts(target as *const u8, block.prologue.len());
+        let patch = Patch::new(
+            DefaultProtection::default(),
+            target,
+            block.jump(),
+            Some(original.to_vec()),
+        )
+        .and_then(|mut patch| patch.enable().map(|_| patch));
+        match patch {
+            Ok(patch) => Ok(Detour { patch, block }),
+            Err(e) => {
+                block.release();
+                Err(DetourError::Patch { source: e })
+            }
+        }
+    }
+
+    pub fn target(&self) -> usize {
+        self.block.data.target
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.patch.is_enabled()
+    }
+
+    /// Restore the original instructions and drop the handler.
+    ///
+    /// # Safety
+    ///
+    /// No thread may be executing the replaced instructions.
+    pub unsafe fn remove(&mut self) -> Result<(), DetourError> {
+        if !self.patch.is_enabled() {
+            return Ok(());
+        }
+        self.patch.disable().context(PatchSnafu)?;
+        self.block.clone().release();
+        Ok(())
+    }
+}
+
+impl Block {
+    /// Allocate and fill a block for `target`. Freed again on errors, as no
+    /// thread has run through it yet.
+    ///
+    /// # Safety
+    ///
+    /// See [`Detour::install`].
+    unsafe fn build(target: usize, hook: Arc<Hook>) -> Result<Block, DetourError> {
+        let addr = exec::alloc_near(target, BLOCK_SIZE).context(ExecSnafu)?;
+        let data = Box::new(HookData {
+            target,
+            trampoline: addr + TRAMPOLINE_OFFSET,
+            hook: RwLock::new(hook),
+        });
+        match Block::fill(addr, &data) {
+            Ok(len) => Ok(Block {
+                addr,
+                prologue: std::slice::from_raw_parts(target as *const u8, len).to_vec(),
+                // leaked with the block, see `exec::alloc_near`
+                data: Box::leak(data),
+            }),
+            Err(e) => {
+                exec::free(addr, BLOCK_SIZE);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write the trampoline and the thunk of `data` into the block at `addr`,
+    /// returns the length of the relocated prologue.
+    unsafe fn fill(addr: usize, data: &HookData) -> Result<usize, DetourError> {
+        let target = data.target;
+        let (prologue, prologue_len) = decode_prologue(target, jump_len(target, addr))?;
+        let mut code = relocate(target, &prologue, data.trampoline)?;
+        code.extend(jmp_abs(target + prologue_len));
+        if code.len() > BLOCK_SIZE - TRAMPOLINE_OFFSET {
+            return RelocateSnafu {
+                addr: target,
+                message: "trampoline too large",
+            }
+            .fail();
+        }
+        std::ptr::copy_nonoverlapping(code.as_ptr(), data.trampoline as *mut u8, code.len());
+
+        let code = entry_thunk(addr, data).map_err(|e| DetourError::Relocate {
+            addr: target,
+            message: e.to_string(),
+        })?;
+        std::ptr::copy_nonoverlapping(code.as_ptr(), addr as *mut u8, code.len());
+
+        Ok(prologue_len)
+    }
+
+    /// A pooled block of `target`, if the instructions it relocated are still
+    /// the first ones of the target.
+    unsafe fn take(target: usize) -> Option<Block> {
+        let mut pool = POOL.lock().unwrap();
+        let blocks = pool.get_mut(&target)?;
+        let i = blocks.iter().position(|block| {
+            is_readable(target, block.prologue.len())
+                && std::slice::from_raw_parts(target as *const u8, block.prologue.len())
+                    == block.prologue
+        })?;
+        Some(blocks.swap_remove(i))
+    }
+
+    /// Drop the handler and put the block in the pool. Calls already in the
+    /// thunk go to the original function.
+    fn release(self) {
+        let mut hook = self.data.hook.write().unwrap();
+        *hook = Arc::new(Hook {
+            args: hook.args.clone(),
+            ret: hook.ret,
+            handler: None,
+        });
+        drop(hook);
+        POOL.lock()
+            .unwrap()
+            .entry(self.data.target)
+            .or_default()
+            .push(self);
+    }
+
+    /// Jump from the target to the thunk, padded to the prologue.
+    fn jump(&self) -> Vec<u8> {
+        let target = self.data.target;
+        let mut jump = match jump_len(target, self.addr) {
+            JMP_REL32_LEN => jmp_rel32(target, self.addr),
+            _ => jmp_abs(self.addr),
+        };
+        jump.resize(self.prologue.len(), NOP);
+        jump
+    }
+}
+
+/// Length of the jump from `target` to a thunk at `thunk`.
+fn jump_len(target: usize, thunk: usize) -> usize {
+    if exec::is_near(target, thunk) {
+        JMP_REL32_LEN
+    } else {
+        JMP_ABS_LEN
+    }
+}
+
+/// Decode whole instructions at `addr` covering at least `min_len` bytes.
+unsafe fn decode_prologue(
+    addr: usize,
+    min_len: usize,
+) -> Result<(Vec<Instruction>, usize), DetourError> {
+    ensure!(is_readable(addr, MAX_PROLOGUE), UnreadableSnafu { addr });
+    let bytes = std::slice::from_raw_parts(addr as *const u8, MAX_PROLOGUE);
+    let mut decoder = Decoder::with_ip(64, bytes, addr as u64, DecoderOptions::NONE);
+    let mut instructions = Vec::new();
+    let mut len = 0;
+    while len < min_len {
+        let instruction = decoder.decode();
+        ensure!(
+            !instruction.is_invalid(),
+            InvalidInstructionSnafu {
+                addr: instruction.ip() as usize
+            }
+        );
+        len += instruction.len();
+        if len < min_len
+            && matches!(
+                instruction.flow_control(),
+                FlowControl::Return | FlowControl::UnconditionalBranch | FlowControl::Interrupt
+            )
+        {
+            return TooShortSnafu { addr }.fail();
+        }
+        instructions.push(instruction);
+    }
+    Ok((instructions, len))
+}
+
+/// Re-encode `instructions` at `ip`, fixing relative operands.
+fn relocate(addr: usize, instructions: &[Instruction], ip: usize) -> Result<Vec<u8>, DetourError> {
+    let block = InstructionBlock::new(instructions, ip as u64);
+    BlockEncoder::encode(64, block, BlockEncoderOptions::NONE)
+        .map(|result| result.code_buffer)
+        .map_err(|e| DetourError::Relocate {
+            addr,
+            message: e.to_string(),
+        })
+}
+
+fn jmp_rel32(from: usize, to: usize) -> Vec<u8> {
+    let rel = (to as i64 - (from + JMP_REL32_LEN) as i64) as i32;
+    let mut code = vec![0xE9];
+    code.extend(rel.to_le_bytes());
+    code
+}
+
+fn jmp_abs(to: usize) -> Vec<u8> {
+    let mut code = vec![0xFF, 0x25, 0, 0, 0, 0];
+    code.extend((to as u64).to_le_bytes());
+    code
+}
+
+/// Thunk spilling the Win64 register arguments and calling [`dispatch`].
+///
+/// The return value is put in both `rax` and `xmm0`.
+fn entry_thunk(ip: usize, data: &HookData) -> Result<Vec<u8>, IcedError> {
+    // 0x20 shadow space + 8 spilled registers; keeps rsp 16-byte aligned
+    const FRAME: i32 = 0x68;
+    // return address and shadow space of the caller
+    const STACK_ARGS: i32 = FRAME + 0x28;
+
+    let mut a = CodeAssembler::new(64)?;
+    a.sub(rsp, FRAME)?;
+    a.mov(qword_ptr(rsp + 0x20), rcx)?;
+    a.mov(qword_ptr(rsp + 0x28), rdx)?;
+    a.mov(qword_ptr(rsp + 0x30), r8)?;
+    a.mov(qword_ptr(rsp + 0x38), r9)?;
+    a.movq(qword_ptr(rsp + 0x40), xmm0)?;
+    a.movq(qword_ptr(rsp + 0x48), xmm1)?;
+    a.movq(qword_ptr(rsp + 0x50), xmm2)?;
+    a.movq(qword_ptr(rsp + 0x58), xmm3)?;
+    a.mov(rcx, data as *const HookData as u64)?;
+    a.lea(rdx, qword_ptr(rsp + 0x20))?;
+    a.lea(r8, qword_ptr(rsp + STACK_ARGS))?;
+    a.mov(rax, dispatch as *const () as u64)?;
+    a.call(rax)?;
+    a.movq(xmm0, rax)?;
+    a.add(rsp, FRAME)?;
+    a.ret()?;
+    a.assemble(ip as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hint::black_box;
+
+    #[inline(never)]
+    extern "win64" fn scale(a: i32, b: f32, c: i64, d: f64, e: i32, f: f32) -> f64 {
+        let mut total = black_box(a) as f64 * black_box(d);
+        total += b as f64 + c as f64;
+        total * e as f64 + f as f64
+    }
+
+    #[inline(never)]
+    extern "win64" fn count(n: u64) -> u64 {
+        let mut sum = 0u64;
+        for i in 0..black_box(n) {
+            sum = sum.wrapping_add(black_box(i));
+        }
+        sum
+    }
+
+    #[inline(never)]
+    extern "win64" fn square(n: u64) -> u64 {
+        black_box(n).wrapping_mul(black_box(n))
+    }
+
+    #[test]
+    fn test_detour() {
+        let f = black_box(scale as extern "win64" fn(i32, f32, i64, f64, i32, f32) -> f64);
+        let classes = vec![
+            Class::Int,
+            Class::Float,
+            Class::Int,
+            Class::Float,
+            Class::Int,
+            Class::Float,
+        ];
+        let expected = f(2, 0.5, 3, 1.5, 4, 0.25);
+
+        let mut detour = unsafe {
+            Detour::install(
+                scale as *const () as usize,
+                classes,
+                Class::Float,
+                |invocation| {
+                    let mut args = invocation.args();
+                    assert_eq!(args[0].1 as i32, 2);
+                    assert_eq!(f32::from_bits(args[5].1 as u32), 0.25);
+                    // e = 10
+                    args[4].1 = 10;
+                    let ret = f64::from_bits(invocation.call_original(&args));
+                    (ret + 1.0).to_bits()
+                },
+            )
+        }
+        .unwrap();
+        assert!(detour.is_installed());
+        assert_eq!(f(2, 0.5, 3, 1.5, 4, 0.25), (3.0 + 3.5) * 10.0 + 0.25 + 1.0);
+
+        unsafe { detour.remove() }.unwrap();
+        assert!(!detour.is_installed());
+        assert_eq!(f(2, 0.5, 3, 1.5, 4, 0.25), expected);
+    }
+
+    #[test]
+    fn test_detour_skip_original() {
+        let f = black_box(count as extern "win64" fn(u64) -> u64);
+        let mut detour = unsafe {
+            Detour::install(
+                count as *const () as usize,
+                vec![Class::Int],
+                Class::Int,
+                |invocation| invocation.arg(0) * 100,
+            )
+        }
+        .unwrap();
+        assert_eq!(f(5), 500);
+        // calls from handlers reach the original function
+        assert_eq!(passthrough(|| f(5)), 10);
+        unsafe { detour.remove() }.unwrap();
+        assert_eq!(f(5), 10);
+    }
+
+    #[test]
+    fn test_detour_reuse() {
+        let f = black_box(square as extern "win64" fn(u64) -> u64);
+        let install = |add: u64| unsafe {
+            Detour::install(
+                square as *const () as usize,
+                vec![Class::Int],
+                Class::Int,
+                move |invocation| invocation.call_original(&invocation.args()) + add,
+            )
+        };
+        let mut first = install(1).unwrap();
+        assert_eq!(f(3), 10);
+        unsafe { first.remove() }.unwrap();
+        let mut second = install(2).unwrap();
+        assert_eq!(second.block.addr, first.block.addr);
+        assert_eq!(f(3), 11);
+        unsafe { second.remove() }.unwrap();
+        assert_eq!(f(3), 9);
+    }
+
+    #[test]
+    fn test_detour_unreadable() {
+        let result = unsafe { Detour::install(0x10, vec![], Class::Int, |_| 0) };
+        assert!(matches!(
+            result,
+            Err(DetourError::Unreadable { addr: 0x10 })
+        ));
+    }
+}
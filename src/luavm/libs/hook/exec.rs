@@ -0,0 +1,109 @@
+use snafu::prelude::*;
+
+/// Step between allocation attempts near a target.
+const SEARCH_STEP: usize = 0x10_0000;
+/// Search range around a target, within reach of a `rel32` jump.
+const SEARCH_RANGE: usize = 0x7000_0000;
+
+#[derive(Debug, Snafu)]
+pub enum ExecError {
+    #[snafu(display("Failed to allocate {} bytes of executable memory", len))]
+    Alloc { len: usize },
+}
+
+/// Whether `to` can be reached from `from` with a `rel32` displacement.
+pub fn is_near(from: usize, to: usize) -> bool {
+    i32::try_from((to as i64).wrapping_sub(from as i64)).is_ok()
+}
+
+/// Allocate read-write-execute memory, preferably within `rel32` reach of `near`.
+///
+/// Memory a hook was installed with is never freed, a thread may still be
+/// running through a trampoline after its hook was removed.
+pub fn alloc_near(near: usize, len: usize) -> Result<usize, ExecError> {
+    let near = near & !(SEARCH_STEP - 1);
+    for i in 1..SEARCH_RANGE / SEARCH_STEP {
+        for hint in [
+            near.checked_sub(i * SEARCH_STEP),
+            near.checked_add(i * SEARCH_STEP),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if let Some(addr) = unsafe { alloc_at(hint, len) } {
+                if is_near(near, addr) && is_near(near, addr + len) {
+                    return Ok(addr);
+                }
+                unsafe { free(addr, len) };
+            }
+        }
+    }
+    unsafe { alloc_at(0, len) }.context(AllocSnafu { len })
+}
+
+#[cfg(windows)]
+unsafe fn alloc_at(hint: usize, len: usize) -> Option<usize> {
+    use winapi::um::memoryapi::VirtualAlloc;
+    use winapi::um::winnt::{MEM_COMMIT, MEM_RESERVE, PAGE_EXECUTE_READWRITE};
+
+    let addr = VirtualAlloc(
+        hint as _,
+        len,
+        MEM_COMMIT | MEM_RESERVE,
+        PAGE_EXECUTE_READWRITE,
+    );
+    if addr.is_null() {
+        None
+    } else {
+        Some(addr as usize)
+    }
+}
+
+/// Free memory of [`alloc_near`] which no thread has run through.
+#[cfg(windows)]
+pub unsafe fn free(addr: usize, _len: usize) {
+    use winapi::um::memoryapi::VirtualFree;
+    use winapi::um::winnt::MEM_RELEASE;
+
+    VirtualFree(addr as _, 0, MEM_RELEASE);
+}
+
+#[cfg(unix)]
+unsafe fn alloc_at(hint: usize, len: usize) -> Option<usize> {
+    // without MAP_FIXED the hint is used if free, otherwise any address
+    let addr = libc::mmap(
+        hint as *mut libc::c_void,
+        len,
+        libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if addr == libc::MAP_FAILED {
+        None
+    } else {
+        Some(addr as usize)
+    }
+}
+
+/// Free memory of [`alloc_near`] which no thread has run through.
+#[cfg(unix)]
+pub unsafe fn free(addr: usize, len: usize) {
+    libc::munmap(addr as *mut libc::c_void, len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc_near() {
+        let target = test_alloc_near as fn() as usize;
+        let addr = alloc_near(target, 4096).unwrap();
+        assert!(is_near(target, addr));
+        unsafe {
+            *(addr as *mut u8) = 0xC3;
+            free(addr, 4096);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+mod detour;
+mod exec;
+
+use std::sync::{Arc, Mutex};
+
+use log::{debug, error};
+use mlua::prelude::*;
+use mlua::UserData;
+
+use super::memory::resolve_target;
+use super::native::{from_native, Marshalled, NativeType, Signature};
+use super::print::target;
+use crate::luavm::WeakLuaVM;
+
+pub use detour::{passthrough, Detour, DetourError, Invocation};
+
+/// Hooks of arbitrary functions.
+///
+/// `Hook.install(addr, "i32(ptr, f32)", {before = fn, after = fn})` calls
+/// `before(ctx)` and `after(ctx)` around every call of the function, where
+/// `ctx.args` holds the arguments (1-based, writable in `before`). Setting
+/// `ctx.skip = true` in `before` skips the original function and returns
+/// `ctx.ret`; in `after`, `ctx.ret` holds the return value and can be replaced.
+pub struct Hook;
+
+impl UserData for Hook {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function(
+            "install",
+            |lua, (target, signature, callbacks): (LuaValue, String, LuaTable)| {
+                install(lua, resolve_target(&target)?, &signature, callbacks)
+            },
+        );
+    }
+}
+
+/// Lua callbacks of a hook.
+struct Callbacks {
+    before: Option<LuaRegistryKey>,
+    after: Option<LuaRegistryKey>,
+}
+
+/// Hooks installed by a Lua VM, stored as app data of the VM.
+#[derive(Default)]
+pub struct HookRegistry {
+    hooks: Vec<Arc<Mutex<Detour>>>,
+}
+
+impl HookRegistry {
+    /// Remove all hooks, latest first. Returns the number of removed hooks.
+    pub fn remove_all(&mut self) -> usize {
+        let mut count = 0;
+        for detour in self.hooks.drain(..).rev() {
+            let mut detour = detour.lock().unwrap();
+            if !detour.is_installed() {
+                continue;
+            }
+            match unsafe { detour.remove() } {
+                Ok(_) => count += 1,
+                Err(e) => error!("Failed to remove hook at 0x{:x}: {}", detour.target(), e),
+            }
+        }
+        count
+    }
+}
+
+/// Lua handle of an installed hook.
+#[derive(Clone)]
+pub struct LuaHook(Arc<Mutex<Detour>>);
+
+impl UserData for LuaHook {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("address", |_, this| Ok(this.0.lock().unwrap().target()));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("remove", |_, this, ()| {
+            unsafe { this.0.lock().unwrap().remove() }.map_err(|e| LuaError::runtime(e.to_string()))
+        });
+        methods.add_method("isInstalled", |_, this, ()| {
+            Ok(this.0.lock().unwrap().is_installed())
+        });
+    }
+}
+
+/// Install a hook owned by the VM of `lua`.
+pub fn install(lua: &Lua, addr: usize, signature: &str, callbacks: LuaTable) -> LuaResult<LuaHook> {
+    let signature = Signature::parse(signature).map_err(|e| LuaError::runtime(e.to_string()))?;
+    let before: Option<LuaFunction> = callbacks.get("before")?;
+    let after: Option<LuaFunction> = callbacks.get("after")?;
+    if before.is_none() && after.is_none() {
+        return Err(LuaError::runtime("Expect a `before` or `after` callback"));
+    }
+    let callbacks = Callbacks {
+        before: before.map(|f| lua.create_registry_value(f)).transpose()?,
+        after: after.map(|f| lua.create_registry_value(f)).transpose()?,
+    };
+    let luavm = lua
+        .app_data_ref::<WeakLuaVM>()
+        .ok_or(LuaError::runtime("Hooks are not available in this VM"))?
+        .clone();
+
+    let args = signature.arg_classes();
+    let ret = signature.ret.class();
+    let detour = unsafe {
+        Detour::install(addr, args, ret, move |invocation| {
+            handle(&luavm, &signature, &callbacks, invocation)
+        })
+    }
+    .map_err(|e| LuaError::runtime(e.to_string()))?;
+
+    let detour = Arc::new(Mutex::new(detour));
+    if lua.app_data_ref::<HookRegistry>().is_none() {
+        lua.set_app_data(HookRegistry::default());
+    }
+    lua.app_data_mut::<HookRegistry>()
+        .unwrap()
+        .hooks
+        .push(detour.clone());
+
+    Ok(LuaHook(detour))
+}
+
+/// Remove all hooks of the VM of `lua`.
+pub fn remove_all(lua: &Lua) {
+    let removed = lua
+        .app_data_mut::<HookRegistry>()
+        .map(|mut registry| registry.remove_all())
+        .unwrap_or(0);
+    if removed > 0 {
+        debug!("removed {} hooks", removed);
+    }
+}
+
+/// Handler of a call of a hooked function, on the calling (game) thread.
+fn handle(
+    luavm: &WeakLuaVM,
+    signature: &Signature,
+    callbacks: &Callbacks,
+    invocation: &Invocation,
+) -> u64 {
+    let original = || invocation.call_original(&invocation.args());
+    // called by a Lua task (e.g. through `Native.call`), the VM may be held by the caller
+    if tokio::runtime::Handle::try_current().is_ok() {
+        debug!(
+            "hook at 0x{:x} called from the runtime, callbacks skipped",
+            invocation.target()
+        );
+        return original();
+    }
+    let Some(luavm) = luavm.upgrade() else {
+        return original();
+    };
+    // never block the game thread on a VM busy with other work
+    let Ok(luavm) = luavm.try_lock() else {
+        debug!(
+            "hook at 0x{:x}: Lua VM is busy, callbacks skipped",
+            invocation.target()
+        );
+        return original();
+    };
+    if !luavm.is_running() {
+        return original();
+    }
+    match run_callbacks(&luavm.lua, signature, callbacks, invocation) {
+        Ok(ret) => ret,
+        Err(e) => {
+            error!(
+                target: &target(&luavm.lua),
+                "Error in hook at 0x{:x}: {}",
+                invocation.target(),
+                e
+            );
+            original()
+        }
+    }
+}
+
+fn run_callbacks(
+    lua: &Lua,
+    signature: &Signature,
+    callbacks: &Callbacks,
+    invocation: &Invocation,
+) -> LuaResult<u64> {
+    let ctx = lua.create_table()?;
+    let args = lua.create_table()?;
+    for (i, &ty) in signature.args.iter().enumerate() {
+        args.raw_set(i + 1, from_native(lua, ty, invocation.arg(i))?)?;
+    }
+    ctx.set("args", args.clone())?;
+    ctx.set("address", invocation.target())?;
+    ctx.set("skip", false)?;
+
+    let mut raw_args = invocation.args();
+    // keeps the memory of modified pointer arguments alive during the call
+    let mut _marshalled = None;
+    if let Some(before) = &callbacks.before {
+        let result = lua
+            .registry_value::<LuaFunction>(before)
+            .and_then(|f| f.call::<_, ()>(ctx.clone()));
+        match result {
+            Ok(()) if ctx.get::<_, bool>("skip")? => {
+                return return_bits(signature.ret, ctx.get("ret")?);
+            }
+            Ok(()) => {
+                let values = (1..=signature.args.len())
+                    .map(|i| args.raw_get::<_, LuaValue>(i))
+                    .collect::<LuaResult<Vec<_>>>()?;
+                let marshalled = Marshalled::new(&signature.args, LuaMultiValue::from_vec(values))?;
+                raw_args = marshalled.bits.clone();
+                _marshalled = Some(marshalled);
+            }
+            Err(e) => error!(target: &target(lua), "Error in hook before callback: {}", e),
+        }
+    }
+
+    let ret = invocation.call_original(&raw_args);
+    let Some(after) = &callbacks.after else {
+        return Ok(ret);
+    };
+    // the original function ran, so errors from here on keep its return value
+    let after = || -> LuaResult<u64> {
+        let ret_value = from_native(lua, signature.ret, ret)?;
+        ctx.set("ret", ret_value.clone())?;
+        lua.registry_value::<LuaFunction>(after)?
+            .call::<_, ()>(ctx.clone())?;
+        let new_value: LuaValue = ctx.get("ret")?;
+        if new_value == ret_value {
+            return Ok(ret);
+        }
+        return_bits(signature.ret, new_value)
+    };
+    Ok(after().unwrap_or_else(|e| {
+        error!(target: &target(lua), "Error in hook after callback: {}", e);
+        ret
+    }))
+}
+
+/// Raw bits of a return value set by a script.
+fn return_bits(ty: NativeType, value: LuaValue) -> LuaResult<u64> {
+    if ty == NativeType::Void || value.is_nil() {
+        return Ok(0);
+    }
+    let marshalled = Marshalled::new(&[ty], LuaMultiValue::from_vec(vec![value]))?;
+    if marshalled.borrows_memory() {
+        return Err(LuaError::runtime(
+            "Strings and buffers can't be returned from hooks",
+        ));
+    }
+    Ok(marshalled.bits[0].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hint::black_box;
+
+    #[inline(never)]
+    extern "win64" fn damage(base: i32, scale: f32) -> i32 {
+        let mut total = black_box(base) as f32;
+        total *= black_box(scale);
+        total as i32
+    }
+
+    #[test]
+    fn test_run_callbacks() {
+        let lua = Lua::new();
+        let signature = Signature::parse("i32(i32, f32)").unwrap();
+        let f: LuaFunction = lua
+            .load(
+                r#"
+                return function(ctx)
+                    if ctx.args[1] == 0 then
+                        ctx.skip = true
+                        ctx.ret = -1
+                    else
+                        ctx.args[2] = ctx.args[2] * 2
+                    end
+                end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let callbacks = Callbacks {
+            before: Some(lua.create_registry_value(f).unwrap()),
+            after: Some(
+                lua.create_registry_value(
+                    lua.load("return function(ctx) ctx.ret = ctx.ret + 1 end")
+                        .eval::<LuaFunction>()
+                        .unwrap(),
+                )
+                .unwrap(),
+            ),
+        };
+        let lua = Arc::new(Mutex::new(lua));
+        let callbacks = Arc::new(callbacks);
+        let (lua_, callbacks_) = (lua.clone(), callbacks.clone());
+        let mut detour = unsafe {
+            Detour::install(
+                damage as *const () as usize,
+                signature.arg_classes(),
+                signature.ret.class(),
+                move |invocation| {
+                    let lua = lua_.lock().unwrap();
+                    run_callbacks(&lua, &signature, &callbacks_, invocation).unwrap()
+                },
+            )
+        }
+        .unwrap();
+
+        let f = black_box(damage as extern "win64" fn(i32, f32) -> i32);
+        assert_eq!(f(10, 1.5), 31);
+        assert_eq!(f(0, 1.5), -1);
+        unsafe { detour.remove() }.unwrap();
+        assert_eq!(f(10, 1.5), 15);
+    }
+}
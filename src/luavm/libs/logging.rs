@@ -0,0 +1,105 @@
+use std::str::FromStr;
+
+use log::{log, Level, LevelFilter};
+use mlua::prelude::*;
+use mlua::UserData;
+
+use super::print::{display_value, target};
+use crate::logger::{self, LogQuery};
+
+/// Logging with structured fields, tagged with the script name.
+///
+/// `Log.info("hit", {dmg = 120, part = 2})` logs `hit dmg=120 part=2`, with
+/// the fields sorted by name. `Log.recent(n, {script, level})` returns the
+/// latest records of the engine and the scripts.
+pub struct Log;
+
+impl UserData for Log {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        for (name, level) in [
+            ("trace", Level::Trace),
+            ("debug", Level::Debug),
+            ("info", Level::Info),
+            ("warn", Level::Warn),
+            ("error", Level::Error),
+        ] {
+            methods.add_function(
+                name,
+                move |lua, (message, fields): (LuaValue, Option<LuaTable>)| {
+                    let line = format_record(&message, fields)?;
+                    log!(target: &target(lua), level, "{}", line);
+                    Ok(())
+                },
+            );
+        }
+        methods.add_function(
+            "recent",
+            |lua, (n, filter): (Option<usize>, Option<LuaTable>)| {
+                let mut query = LogQuery {
+                    n: n.unwrap_or(usize::MAX),
+                    ..Default::default()
+                };
+                if let Some(filter) = filter {
+                    query.script = filter.get("script")?;
+                    if let Some(level) = filter.get::<_, Option<String>>("level")? {
+                        query.level = LevelFilter::from_str(&level).map_err(|_| {
+                            LuaError::runtime(format!("Unknown log level: {}", level))
+                        })?;
+                    }
+                }
+                let records = lua.create_table()?;
+                for entry in logger::recent(&query) {
+                    let record = lua.create_table()?;
+                    record.set("time", entry.time)?;
+                    record.set("level", entry.level.as_str().to_lowercase())?;
+                    record.set("script", entry.script)?;
+                    record.set("message", entry.message)?;
+                    records.push(record)?;
+                }
+                Ok(records)
+            },
+        );
+    }
+}
+
+/// `message` followed by the `key=value` pairs of `fields`.
+fn format_record(message: &LuaValue, fields: Option<LuaTable>) -> LuaResult<String> {
+    let mut line = display_value(message);
+    let Some(fields) = fields else {
+        return Ok(line);
+    };
+    let mut pairs = fields
+        .pairs::<LuaValue, LuaValue>()
+        .map(|pair| pair.map(|(k, v)| (display_value(&k), display_value(&v))))
+        .collect::<LuaResult<Vec<_>>>()?;
+    pairs.sort();
+    for (key, value) in pairs {
+        // quoted if it would be ambiguous
+        if value.is_empty() || value.contains([' ', '=', '"']) {
+            line.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        let lua = Lua::new();
+        let message = LuaValue::String(lua.create_string("hit").unwrap());
+        let fields = lua
+            .load(r#"{ part = 2, dmg = 120.5, who = "Great Jagras", crit = true }"#)
+            .eval()
+            .unwrap();
+        assert_eq!(
+            format_record(&message, Some(fields)).unwrap(),
+            r#"hit crit=true dmg=120.5 part=2 who="Great Jagras""#
+        );
+        assert_eq!(format_record(&message, None).unwrap(), "hit");
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt::Write;
+
+use mlua::prelude::*;
+use mlua::UserData;
+
+use super::{is_readable, parse_type_name, TypeName};
+
+const HEX_LINE_WIDTH: usize = 16;
+/// Max size of a buffer and of a bulk read, 64 MiB.
+pub const MAX_SIZE: usize = 64 << 20;
+
+/// A snapshot of raw bytes, indexed with 0-based byte offsets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Buffer(pub Vec<u8>);
+
+impl Buffer {
+    /// A buffer of `size` zero bytes.
+    pub fn zeroed(size: usize) -> LuaResult<Buffer> {
+        check_size(size)?;
+        Ok(Buffer(vec![0; size]))
+    }
+
+    /// Copy `len` bytes from `addr`, fails if they are not all readable.
+    pub fn read(addr: usize, len: usize) -> LuaResult<Buffer> {
+        check_size(len)?;
+        if !is_readable(addr, len) {
+            return Err(LuaError::runtime(format!(
+                "Memory at 0x{:x} is not readable for {} bytes",
+                addr, len
+            )));
+        }
+        Ok(Buffer(
+            unsafe { std::slice::from_raw_parts(addr as *const u8, len) }.to_vec(),
+        ))
+    }
+
+    fn range(&self, offset: usize, len: usize) -> LuaResult<std::ops::Range<usize>> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.0.len() => Ok(offset..end),
+            _ => Err(LuaError::runtime(format!(
+                "Out of range: offset {} length {} in buffer of {} bytes",
+                offset,
+                len,
+                self.0.len()
+            ))),
+        }
+    }
+
+    fn type_size(type_name: TypeName) -> LuaResult<usize> {
+        type_name.size().ok_or(LuaError::runtime(format!(
+            "typeName `{}` is not supported in buffers",
+            type_name.as_str()
+        )))
+    }
+
+    pub fn get<'lua>(&self, offset: usize, type_name: TypeName) -> LuaResult<LuaValue<'lua>> {
+        let range = self.range(offset, Buffer::type_size(type_name)?)?;
+        Ok(type_name.decode(&self.0[range]))
+    }
+
+    pub fn set(&mut self, offset: usize, type_name: TypeName, value: &LuaValue) -> LuaResult<()> {
+        let range = self.range(offset, Buffer::type_size(type_name)?)?;
+        self.0[range].copy_from_slice(&type_name.encode(value)?);
+        Ok(())
+    }
+
+    /// Offsets of all bytes which differ from `other`.
+    ///
+    /// Bytes beyond the shorter buffer count as different.
+    pub fn diff(&self, other: &Buffer) -> Vec<usize> {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .filter(|&i| self.0.get(i) != other.0.get(i))
+            .collect()
+    }
+
+    /// Hex dump with offsets and printable ASCII, 16 bytes per line.
+    pub fn hex_dump(&self) -> String {
+        let mut out = String::new();
+        for (line, chunk) in self.0.chunks(HEX_LINE_WIDTH).enumerate() {
+            let _ = write!(out, "{:04x}: ", line * HEX_LINE_WIDTH);
+            for i in 0..HEX_LINE_WIDTH {
+                match chunk.get(i) {
+                    Some(b) => {
+                        let _ = write!(out, "{:02x} ", b);
+                    }
+                    None => out.push_str("   "),
+                }
+            }
+            out.push('|');
+            out.extend(chunk.iter().map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            }));
+            out.push_str("|\n");
+        }
+        out.pop();
+        out
+    }
+}
+
+/// Accept a byte string, a `Buffer` or a sequence table of integers.
+pub fn bytes_from_lua(value: LuaValue) -> LuaResult<Vec<u8>> {
+    match value {
+        LuaValue::String(s) => Ok(s.as_bytes().to_vec()),
+        LuaValue::UserData(ud) => Ok(ud.borrow::<Buffer>()?.0.clone()),
+        LuaValue::Table(t) => t
+            .sequence_values::<i64>()
+            .map(|v| {
+                let v = v?;
+                u8::try_from(v).map_err(|_| LuaError::runtime(format!("Invalid byte: {}", v)))
+            })
+            .collect(),
+        _ => Err(LuaError::runtime(format!(
+            "Expect a string, a Buffer or a table of bytes, got {}",
+            value.type_name()
+        ))),
+    }
+}
+
+impl UserData for Buffer {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("size", |_, this| Ok(this.0.len()));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("get", |_, this, (offset, type_name): (usize, String)| {
+            this.get(offset, parse_type_name(&type_name)?)
+        });
+        methods.add_method_mut(
+            "set",
+            |_, this, (offset, type_name, value): (usize, String, LuaValue)| {
+                this.set(offset, parse_type_name(&type_name)?, &value)
+            },
+        );
+        methods.add_method("slice", |_, this, (offset, len): (usize, Option<usize>)| {
+            let len = len.unwrap_or(this.0.len().saturating_sub(offset));
+            let range = this.range(offset, len)?;
+            Ok(Buffer(this.0[range].to_vec()))
+        });
+        methods.add_method("bytes", |lua, this, ()| lua.create_string(&this.0));
+        methods.add_method("hex", |_, this, ()| Ok(this.hex_dump()));
+        methods.add_method("diff", |_, this, other: LuaUserDataRef<Buffer>| {
+            Ok(this.diff(&other))
+        });
+        methods.add_method("equals", |_, this, other: LuaUserDataRef<Buffer>| {
+            Ok(this.0 == other.0)
+        });
+
+        methods.add_meta_method(
+            LuaMetaMethod::Eq,
+            |_, this, other: LuaUserDataRef<Buffer>| Ok(this.0 == other.0),
+        );
+        methods.add_meta_method(LuaMetaMethod::Len, |_, this, ()| Ok(this.0.len()));
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(format!("Buffer({} bytes)", this.0.len()))
+        });
+    }
+}
+
+fn check_size(size: usize) -> LuaResult<()> {
+    if size > MAX_SIZE {
+        return Err(LuaError::runtime(format!(
+            "Size {} is larger than the max buffer size of {} bytes",
+            size, MAX_SIZE
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_access() {
+        let mut buf = Buffer(vec![0; 8]);
+        buf.set(4, TypeName::F32, &LuaValue::Number(2.5)).unwrap();
+        buf.set(0, TypeName::I16, &LuaValue::Integer(-2)).unwrap();
+        assert_eq!(buf.get(4, TypeName::F32).unwrap(), LuaValue::Number(2.5));
+        assert_eq!(buf.get(0, TypeName::I16).unwrap(), LuaValue::Integer(-2));
+        assert!(buf.get(6, TypeName::I32).is_err());
+        assert!(buf
+            .set(usize::MAX, TypeName::I8, &LuaValue::Integer(1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_read() {
+        let bytes = [1u8, 2, 3, 4];
+        let buf = Buffer::read(bytes.as_ptr() as usize, 4).unwrap();
+        assert_eq!(buf.0, bytes);
+        assert!(Buffer::read(0x10, 4).is_err());
+        assert!(Buffer::read(bytes.as_ptr() as usize, MAX_SIZE + 1).is_err());
+        assert_eq!(Buffer::zeroed(3).unwrap().0, vec![0; 3]);
+        assert!(Buffer::zeroed(1 << 40).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = Buffer(vec![1, 2, 3, 4]);
+        let b = Buffer(vec![1, 9, 3, 4, 5]);
+        assert_eq!(a.diff(&b), vec![1, 4]);
+        assert!(a.diff(&a.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let buf = Buffer(b"Hello, world!\x00\x01\x02\xff".to_vec());
+        assert_eq!(
+            buf.hex_dump(),
+            "0000: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 00 01 02 |Hello, world!...|\n\
+             0010: ff                                              |.|"
+        );
+    }
+}
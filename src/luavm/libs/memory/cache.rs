@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Bumped by the engine every tick, invalidating all cached resolutions.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Invalidate the resolution caches of all pointers.
+pub fn next_tick() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cached {
+    addr: usize,
+    generation: u64,
+    at: Instant,
+}
+
+/// Resolved address of a pointer chain, valid until the next tick,
+/// an explicit invalidation or the optional time to live passes.
+#[derive(Debug)]
+pub struct ResolveCache {
+    ttl: Option<Duration>,
+    cached: Mutex<Option<Cached>>,
+}
+
+impl ResolveCache {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    pub fn get(&self) -> Option<usize> {
+        let cached = (*self.cached.lock().unwrap())?;
+        if cached.generation != GENERATION.load(Ordering::Relaxed) {
+            return None;
+        }
+        if self.ttl.is_some_and(|ttl| cached.at.elapsed() > ttl) {
+            return None;
+        }
+        Some(cached.addr)
+    }
+
+    pub fn set(&self, addr: usize) {
+        *self.cached.lock().unwrap() = Some(Cached {
+            addr,
+            generation: GENERATION.load(Ordering::Relaxed),
+            at: Instant::now(),
+        });
+    }
+
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+
+    /// Cached address, or resolve and cache it.
+    pub fn get_or_resolve(&self, resolve: impl FnOnce() -> Option<usize>) -> Option<usize> {
+        if let Some(addr) = self.get() {
+            return Some(addr);
+        }
+        let addr = resolve()?;
+        self.set(addr);
+        Some(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let cache = ResolveCache::new(None);
+        assert_eq!(cache.get_or_resolve(|| Some(1)), Some(1));
+        assert_eq!(cache.get_or_resolve(|| Some(2)), Some(1));
+        cache.invalidate();
+        assert_eq!(cache.get_or_resolve(|| None), None);
+        assert_eq!(cache.get_or_resolve(|| Some(3)), Some(3));
+        next_tick();
+        assert_eq!(cache.get(), None);
+    }
+
+    #[test]
+    fn test_cache_ttl() {
+        let cache = ResolveCache::new(Some(Duration::ZERO));
+        cache.set(1);
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(cache.get(), None);
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt;
+
+use mhw_toolkit::util;
+use mlua::prelude::*;
+use snafu::prelude::*;
+
+use super::protect;
+
+#[derive(Debug, Clone, PartialEq, Eq, Snafu)]
+pub enum ResolveError {
+    #[snafu(display("base address is null"))]
+    NullBase,
+    #[snafu(display(
+        "hop {} ({:+#x}): pointer at 0x{:x} is not readable",
+        hop,
+        offset,
+        addr
+    ))]
+    Unreadable {
+        hop: usize,
+        offset: isize,
+        addr: usize,
+    },
+    #[snafu(display("hop {} ({:+#x}): null pointer at 0x{:x}", hop, offset, addr))]
+    NullPointer {
+        hop: usize,
+        offset: isize,
+        addr: usize,
+    },
+}
+
+impl ResolveError {
+    /// 0-based index of the failing hop, `None` for the base.
+    pub fn hop(&self) -> Option<usize> {
+        match self {
+            ResolveError::NullBase => None,
+            ResolveError::Unreadable { hop, .. } | ResolveError::NullPointer { hop, .. } => {
+                Some(*hop)
+            }
+        }
+    }
+}
+
+/// Hop by hop resolution of a `base` + `offsets` pointer chain.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub base: usize,
+    /// (offset, address after applying the offset) of every resolved hop
+    pub hops: Vec<(isize, usize)>,
+    pub error: Option<ResolveError>,
+}
+
+impl Resolution {
+    /// Resolve the chain with the same rules as `util::get_ptr_with_offset`,
+    /// checking that every dereferenced pointer is readable first.
+    pub fn resolve(base: usize, offsets: &[isize]) -> Resolution {
+        let mut resolution = Resolution {
+            base,
+            hops: Vec::with_capacity(offsets.len()),
+            error: None,
+        };
+        if base == 0 {
+            resolution.error = Some(ResolveError::NullBase);
+            return resolution;
+        }
+        let mut prev = base;
+        for (hop, &offset) in offsets.iter().enumerate() {
+            if !protect::is_readable(prev, std::mem::size_of::<usize>()) {
+                resolution.error = Some(ResolveError::Unreadable {
+                    hop,
+                    offset,
+                    addr: prev,
+                });
+                break;
+            }
+            // one hop from the previous address, with the walking rules of `util`
+            match util::get_ptr_with_offset(prev as *const u8, &[offset]) {
+                Some(ptr) => {
+                    prev = ptr as usize;
+                    resolution.hops.push((offset, prev));
+                }
+                None => {
+                    resolution.error = Some(ResolveError::NullPointer {
+                        hop,
+                        offset,
+                        addr: prev,
+                    });
+                    break;
+                }
+            }
+        }
+
+        resolution
+    }
+
+    /// Final address, if all hops resolved.
+    pub fn address(&self) -> Option<usize> {
+        if self.error.is_some() {
+            return None;
+        }
+        Some(self.hops.last().map(|&(_, addr)| addr).unwrap_or(self.base))
+    }
+
+    pub fn to_lua_table<'lua>(&self, lua: &'lua Lua) -> LuaResult<LuaTable<'lua>> {
+        let table = lua.create_table()?;
+        table.set("base", self.base)?;
+        let hops = lua.create_table()?;
+        for (offset, address) in self.hops.iter() {
+            let hop = lua.create_table()?;
+            hop.set("offset", *offset)?;
+            hop.set("address", *address)?;
+            hops.push(hop)?;
+        }
+        table.set("hops", hops)?;
+        table.set("address", self.address())?;
+        if let Some(error) = &self.error {
+            // 1-based, like the Lua `hops` table
+            table.set("failedHop", error.hop().map(|hop| hop + 1))?;
+            table.set("reason", error.to_string())?;
+        }
+        Ok(table)
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:x}", self.base)?;
+        for (offset, addr) in self.hops.iter() {
+            write!(f, " -> [{:+#x}] 0x{:x}", offset, addr)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, " -> {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let value = [0u64, 42];
+        let inner = [0usize, value.as_ptr() as usize];
+        let outer = [inner.as_ptr() as usize];
+        let base = outer.as_ptr() as usize;
+
+        let resolution = Resolution::resolve(base, &[8, 8]);
+        assert_eq!(resolution.error, None);
+        assert_eq!(resolution.hops.len(), 2);
+        assert_eq!(resolution.address(), Some(value.as_ptr() as usize + 8));
+
+        let resolution = Resolution::resolve(base, &[0, 8]);
+        assert_eq!(
+            resolution.error,
+            Some(ResolveError::NullPointer {
+                hop: 1,
+                offset: 8,
+                addr: inner.as_ptr() as usize
+            })
+        );
+        assert_eq!(resolution.address(), None);
+    }
+
+    #[test]
+    fn test_resolve_without_offsets() {
+        assert_eq!(Resolution::resolve(0x1000, &[]).address(), Some(0x1000));
+        assert_eq!(
+            Resolution::resolve(0, &[8]).error,
+            Some(ResolveError::NullBase)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_unreadable() {
+        let resolution = Resolution::resolve(0x10, &[8]);
+        assert_eq!(
+            resolution.error,
+            Some(ResolveError::Unreadable {
+                hop: 0,
+                offset: 8,
+                addr: 0x10
+            })
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use mlua::prelude::*;
+use mlua::UserData;
+
+use super::{protect, write_bytes, RawPtr, TypeName};
+
+/// Typed accessor of a value at `offset` from the address a `RawPtr` resolves to.
+///
+/// The pointer is copied when the field is created, with its own resolution cache.
+pub struct Field {
+    ptr: RawPtr,
+    offset: isize,
+    type_name: TypeName,
+}
+
+impl Field {
+    pub fn new(ptr: RawPtr, offset: isize, type_name: TypeName) -> Self {
+        Self {
+            ptr,
+            offset,
+            type_name,
+        }
+    }
+
+    pub fn address(&self) -> Option<usize> {
+        self.ptr
+            .address()
+            .map(|addr| addr.wrapping_add_signed(self.offset))
+    }
+
+    fn size(&self) -> LuaResult<usize> {
+        self.type_name.size().ok_or(LuaError::runtime(format!(
+            "typeName `{}` is not supported by fields",
+            self.type_name.as_str()
+        )))
+    }
+
+    fn try_read<'lua>(&self) -> LuaResult<Result<LuaValue<'lua>, String>> {
+        let size = self.size()?;
+        let Some(addr) = self.address() else {
+            let reason = match self.ptr.resolve().error {
+                Some(e) => e.to_string(),
+                None => "Failed to get reference to memory".to_string(),
+            };
+            return Ok(Err(reason));
+        };
+        if !protect::is_readable(addr, size) {
+            return Ok(Err(format!("value at 0x{:x} is not readable", addr)));
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(addr as *const u8, size) };
+        Ok(Ok(self.type_name.decode(bytes)))
+    }
+}
+
+impl UserData for Field {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("offset", |_, this| Ok(this.offset));
+        fields.add_field_method_get("type", |_, this| Ok(this.type_name.as_str()));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("address", |_, this, ()| Ok(this.address()));
+        methods.add_method("read", |_, this, ()| {
+            let size = this.size()?;
+            let addr = this.address().ok_or_else(|| this.ptr.value_error())?;
+            let bytes = unsafe { std::slice::from_raw_parts(addr as *const u8, size) };
+            Ok(this.type_name.decode(bytes))
+        });
+        methods.add_method("tryRead", |_, this, ()| match this.try_read()? {
+            Ok(value) => Ok((value, None)),
+            Err(reason) => Ok((LuaNil, Some(reason))),
+        });
+        methods.add_method("write", |lua, this, value: LuaValue| {
+            let bytes = this.type_name.encode(&value)?;
+            let addr = this
+                .address()
+                .ok_or(LuaError::runtime("Failed to get reference to memory"))?;
+            unsafe { write_bytes(lua, addr, &bytes) };
+            Ok(())
+        });
+    }
+}
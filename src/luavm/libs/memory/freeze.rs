@@ -0,0 +1,344 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+use mlua::UserData;
+use once_cell::sync::Lazy;
+
+use super::target::Target;
+use super::{is_readable, journal, TypeName};
+use crate::luavm::VmInfo;
+
+pub const DEFAULT_PERIOD_MS: u64 = 50;
+/// upper bound of the service sleep, when nothing is frozen
+const IDLE_PERIOD: Duration = Duration::from_millis(500);
+
+static SERVICE: Lazy<FreezeService> = Lazy::new(FreezeService::new);
+
+#[derive(Debug, Clone)]
+pub struct FreezeEntry {
+    pub id: u64,
+    pub owner: VmInfo,
+    pub target: Target,
+    type_name: TypeName,
+    bytes: Vec<u8>,
+    pub period: Duration,
+    next_due: Instant,
+}
+
+impl FreezeEntry {
+    /// Write the frozen value. Skipped while the target doesn't resolve or isn't
+    /// readable, like a chain to a freed object on the title screen.
+    fn apply(&self) {
+        let Some(addr) = self.target.resolve() else {
+            return;
+        };
+        if !is_readable(addr, self.bytes.len()) {
+            return;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.bytes.as_ptr(), addr as *mut u8, self.bytes.len())
+        };
+    }
+}
+
+impl fmt::Display for FreezeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self.type_name.decode(&self.bytes) {
+            LuaValue::Integer(v) => v.to_string(),
+            LuaValue::Number(v) => v.to_string(),
+            LuaValue::Boolean(v) => v.to_string(),
+            _ => "?".to_string(),
+        };
+        write!(
+            f,
+            "#{} {} = {}: {} every {}ms",
+            self.id,
+            self.target,
+            self.type_name.as_str(),
+            value,
+            self.period.as_millis()
+        )
+    }
+}
+
+/// Engine side enforcement of frozen memory values, without entering Lua.
+struct FreezeService {
+    entries: Mutex<Vec<FreezeEntry>>,
+    wakeup: Condvar,
+    started: Once,
+    next_id: AtomicU64,
+}
+
+impl FreezeService {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(Vec::new()),
+            wakeup: Condvar::new(),
+            started: Once::new(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn run(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let mut next_due = now + IDLE_PERIOD;
+            for entry in entries.iter_mut() {
+                if entry.next_due <= now {
+                    entry.apply();
+                    entry.next_due = now + entry.period;
+                }
+                next_due = next_due.min(entry.next_due);
+            }
+            let timeout = next_due.saturating_duration_since(Instant::now());
+            entries = self.wakeup.wait_timeout(entries, timeout).unwrap().0;
+        }
+    }
+}
+
+/// Start enforcing `value` at `target`. Returns the id of the entry.
+pub fn add(
+    owner: VmInfo,
+    target: Target,
+    type_name: TypeName,
+    bytes: Vec<u8>,
+    period: Duration,
+) -> u64 {
+    SERVICE.started.call_once(|| {
+        thread::spawn(|| SERVICE.run());
+    });
+    let id = SERVICE.next_id.fetch_add(1, Ordering::Relaxed);
+    SERVICE.entries.lock().unwrap().push(FreezeEntry {
+        id,
+        owner,
+        target,
+        type_name,
+        bytes,
+        period,
+        next_due: Instant::now(),
+    });
+    SERVICE.wakeup.notify_one();
+
+    id
+}
+
+/// Remove an entry of the VM `vm_id`. Entries of other VMs are kept.
+pub fn remove(id: u64, vm_id: u64) -> bool {
+    let mut entries = SERVICE.entries.lock().unwrap();
+    let len = entries.len();
+    entries.retain(|entry| entry.id != id || entry.owner.id != vm_id);
+    len != entries.len()
+}
+
+pub fn owner_of(id: u64) -> Option<VmInfo> {
+    SERVICE
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|entry| entry.id == id)
+        .map(|entry| entry.owner.clone())
+}
+
+/// Remove all entries of a VM. Returns the number of removed entries.
+pub fn remove_owner(vm_id: u64) -> usize {
+    let mut entries = SERVICE.entries.lock().unwrap();
+    let len = entries.len();
+    entries.retain(|entry| entry.owner.id != vm_id);
+    len - entries.len()
+}
+
+pub fn is_active(id: u64) -> bool {
+    SERVICE
+        .entries
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|entry| entry.id == id)
+}
+
+pub fn list() -> Vec<FreezeEntry> {
+    SERVICE.entries.lock().unwrap().clone()
+}
+
+/// Lua handle of a freeze entry
+pub struct FreezeHandle {
+    pub id: u64,
+}
+
+impl UserData for FreezeHandle {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_, this| Ok(this.id));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("unfreeze", |lua, this, ()| unfreeze(lua, this.id));
+        methods.add_method("isActive", |_, this, ()| Ok(is_active(this.id)));
+    }
+}
+
+/// `Memory.unfreeze(handle)`, only for entries of the calling VM.
+pub fn unfreeze(lua: &Lua, id: u64) -> LuaResult<bool> {
+    let vm = VmInfo::of(lua).ok_or(LuaError::runtime("Unknown Lua VM"))?;
+    match owner_of(id) {
+        Some(owner) if owner.id != vm.id => Err(LuaError::runtime(format!(
+            "Freeze #{} is owned by `{}`",
+            id, owner.name
+        ))),
+        _ => Ok(remove(id, vm.id)),
+    }
+}
+
+/// `Memory.freeze(target, typeName, value, periodMs)`
+pub fn freeze(
+    lua: &Lua,
+    target: LuaValue,
+    type_name: TypeName,
+    value: LuaValue,
+    period_ms: Option<u64>,
+) -> LuaResult<FreezeHandle> {
+    let owner = VmInfo::of(lua).ok_or(LuaError::runtime("Unknown Lua VM"))?;
+    let target = Target::from_lua(&target)?;
+    let bytes = type_name.encode(&value)?;
+    if let Some(addr) = target
+        .resolve()
+        .filter(|&addr| is_readable(addr, bytes.len()))
+    {
+        unsafe { journal::record(lua, addr, bytes.len()) };
+    }
+    let period = Duration::from_millis(period_ms.unwrap_or(DEFAULT_PERIOD_MS).max(1));
+
+    Ok(FreezeHandle {
+        id: add(owner, target, type_name, bytes, period),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI32, AtomicUsize};
+
+    use super::*;
+
+    /// VM ids far from the ones of real VMs, the service is shared by all tests
+    fn owner(id: u64) -> VmInfo {
+        VmInfo {
+            id: u64::MAX - id,
+            name: format!("test{}.lua", id),
+        }
+    }
+
+    fn add_i32(owner: VmInfo, value: &AtomicI32, frozen: i32) -> u64 {
+        let target = Target::Address(value.as_ptr() as usize);
+        let bytes = frozen.to_le_bytes().to_vec();
+        add(
+            owner,
+            target,
+            TypeName::I32,
+            bytes,
+            Duration::from_millis(1),
+        )
+    }
+
+    #[test]
+    fn test_add_remove() {
+        let value = Box::leak(Box::new(AtomicI32::new(0)));
+        let (a, b) = (owner(1), owner(2));
+        let id = add_i32(a.clone(), value, 1);
+        assert!(is_active(id));
+        assert_eq!(owner_of(id), Some(a.clone()));
+        assert!(list().iter().any(|entry| entry.id == id));
+
+        assert!(!remove(id, b.id));
+        assert!(is_active(id));
+        assert!(remove(id, a.id));
+        assert!(!is_active(id));
+        assert!(!remove(id, a.id));
+    }
+
+    #[test]
+    fn test_remove_owner() {
+        let value = Box::leak(Box::new(AtomicI32::new(0)));
+        let (a, b) = (owner(3), owner(4));
+        let ids = [add_i32(a.clone(), value, 1), add_i32(a.clone(), value, 2)];
+        let other = add_i32(b.clone(), value, 3);
+
+        assert_eq!(remove_owner(a.id), 2);
+        assert!(ids.iter().all(|id| !is_active(*id)));
+        assert!(is_active(other));
+        assert_eq!(remove_owner(b.id), 1);
+    }
+
+    #[test]
+    fn test_enforce() {
+        let value = Box::leak(Box::new(AtomicI32::new(0)));
+        let vm = owner(5);
+        let id = add_i32(vm.clone(), value, 42);
+        let wait_for = |expected: i32| {
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while value.load(Ordering::SeqCst) != expected && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            value.load(Ordering::SeqCst)
+        };
+        assert_eq!(wait_for(42), 42);
+        value.store(7, Ordering::SeqCst);
+        assert_eq!(wait_for(42), 42);
+
+        assert!(remove(id, vm.id));
+        // the service may be in the middle of applying the entry
+        thread::sleep(Duration::from_millis(20));
+        value.store(7, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(value.load(Ordering::SeqCst), 7);
+    }
+
+    #[test]
+    fn test_stale_chain() {
+        let value = Box::leak(Box::new(AtomicI32::new(0)));
+        // pointer to the value, null while the object doesn't exist
+        let slot = Box::leak(Box::new(AtomicUsize::new(0)));
+        let vm = owner(8);
+        let target = Target::Pointer {
+            base: slot.as_ptr() as usize,
+            offsets: vec![0],
+        };
+        let id = add(
+            vm.clone(),
+            target,
+            TypeName::I32,
+            42i32.to_le_bytes().to_vec(),
+            Duration::from_millis(1),
+        );
+        // ticks of the unresolved chain are skipped
+        thread::sleep(Duration::from_millis(20));
+        assert!(is_active(id));
+        assert_eq!(value.load(Ordering::SeqCst), 0);
+
+        slot.store(value.as_ptr() as usize, Ordering::SeqCst);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while value.load(Ordering::SeqCst) != 42 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(value.load(Ordering::SeqCst), 42);
+        assert!(remove(id, vm.id));
+    }
+
+    #[test]
+    fn test_unfreeze_foreign() {
+        let value = Box::leak(Box::new(AtomicI32::new(0)));
+        let a = owner(6);
+        let id = add_i32(a.clone(), value, 1);
+        let lua = Lua::new();
+        lua.set_app_data(owner(7));
+        let err = unfreeze(&lua, id).unwrap_err();
+        assert!(err.to_string().contains("test6.lua"), "{}", err);
+        assert!(is_active(id));
+        lua.set_app_data(a);
+        assert!(unfreeze(&lua, id).unwrap());
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use mlua::prelude::*;
+
+/// Original bytes of the memory written by a Lua VM.
+///
+/// Stored as app data of the VM, so that every write made through `Memory`
+/// or `RawPtr` can be reverted with `Memory.restoreAll()` or when the script stops.
+#[derive(Debug, Default)]
+pub struct MemoryJournal {
+    enabled: bool,
+    /// key: address, value: the byte before the first journaled write
+    original: BTreeMap<usize, u8>,
+}
+
+impl MemoryJournal {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            original: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Save the bytes in `[addr, addr + len)` which are not journaled yet.
+    ///
+    /// # Safety
+    ///
+    /// Must be called before writing, and the range must be readable.
+    pub unsafe fn record(&mut self, addr: usize, len: usize) {
+        if !self.enabled {
+            return;
+        }
+        for a in addr..addr + len {
+            self.original.entry(a).or_insert_with(|| *(a as *const u8));
+        }
+    }
+
+    /// Write back all journaled bytes and clear the journal.
+    ///
+    /// Contiguous bytes are written back together. Returns the number of restored bytes.
+    ///
+    /// # Safety
+    ///
+    /// All journaled addresses must still be writable.
+    pub unsafe fn restore_all(&mut self) -> usize {
+        let count = self.original.len();
+        let mut run: Vec<u8> = Vec::new();
+        let mut run_start = 0;
+        for (&addr, &byte) in self.original.iter() {
+            if !run.is_empty() && run_start + run.len() != addr {
+                std::ptr::copy_nonoverlapping(run.as_ptr(), run_start as *mut u8, run.len());
+                run.clear();
+            }
+            if run.is_empty() {
+                run_start = addr;
+            }
+            run.push(byte);
+        }
+        if !run.is_empty() {
+            std::ptr::copy_nonoverlapping(run.as_ptr(), run_start as *mut u8, run.len());
+        }
+        self.original.clear();
+
+        count
+    }
+}
+
+/// Journal `[addr, addr + len)` for the VM owning `lua`, if journaling is enabled.
+///
+/// # Safety
+///
+/// See [`MemoryJournal::record`].
+pub unsafe fn record(lua: &Lua, addr: usize, len: usize) {
+    if let Some(mut journal) = lua.app_data_mut::<MemoryJournal>() {
+        journal.record(addr, len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_overlapping() {
+        let mut buf = [1u8, 2, 3, 4, 5, 6, 7, 8];
+        let base = buf.as_mut_ptr() as usize;
+        let mut journal = MemoryJournal::new(true);
+        unsafe {
+            journal.record(base + 1, 4);
+            ((base + 1) as *mut u32).write_unaligned(0xFFFF_FFFF);
+            journal.record(base + 3, 4);
+            ((base + 3) as *mut u32).write_unaligned(0);
+            journal.record(base + 1, 4);
+            ((base + 1) as *mut u32).write_unaligned(0xAAAA_AAAA);
+        }
+        assert_eq!(journal.original.len(), 6);
+        assert_eq!(unsafe { journal.restore_all() }, 6);
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(journal.original.is_empty());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut buf = [0u8; 4];
+        let mut journal = MemoryJournal::new(false);
+        unsafe { journal.record(buf.as_mut_ptr() as usize, 4) };
+        assert!(journal.original.is_empty());
+    }
+}
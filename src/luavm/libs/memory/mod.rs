@@ -0,0 +1,723 @@
+mod buffer;
+mod cache;
+mod chain;
+mod field;
+mod freeze;
+mod journal;
+mod modules;
+mod patch;
+mod protect;
+mod target;
+mod watch;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use cache::ResolveCache;
+use field::Field;
+use log::debug;
+use mhw_toolkit::util;
+use mlua::prelude::*;
+use mlua::UserData;
+
+use crate::config;
+use crate::luavm::VmInfo;
+
+pub use buffer::Buffer;
+pub use cache::next_tick;
+pub use chain::Resolution;
+pub use freeze::FreezeEntry;
+pub use journal::MemoryJournal;
+pub use modules::{DefaultModules, Modules};
+pub use patch::{Patch, PatchError, NOP};
+pub use protect::{is_readable, DefaultProtection};
+
+pub struct Memory;
+
+impl UserData for Memory {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function("newPtr", |_, ()| Ok(RawPtr::new()));
+        methods.add_function("invalidateCache", |_, ()| {
+            cache::next_tick();
+            Ok(())
+        });
+        methods.add_function("read", |_, (addr, type_name): (usize, String)| {
+            let type_name =
+                TypeName::from_str(&type_name).ok_or(LuaError::RuntimeError(format!(
+                    "Invalid typeName: {}, consider using i32, i64, f32, etc.",
+                    type_name
+                )))?;
+            unsafe {
+                Ok(match type_name {
+                    TypeName::I8 => LuaValue::Integer(*(addr as *const i8) as i64),
+                    TypeName::I16 => LuaValue::Integer(*(addr as *const i16) as i64),
+                    TypeName::I32 => LuaValue::Integer(*(addr as *const i32) as i64),
+                    TypeName::I64 => LuaValue::Integer(*(addr as *const i64)),
+                    TypeName::F32 => LuaValue::Number(*(addr as *const f32) as f64),
+                    TypeName::F64 => LuaValue::Number(*(addr as *const f64)),
+                    TypeName::Bool => LuaValue::Boolean(*(addr as *const bool)),
+                    TypeName::String => todo!(),
+                })
+            }
+        });
+        methods.add_function(
+            "write",
+            |lua, (addr, value, type_name): (usize, LuaValue, String)| {
+                let type_name =
+                    TypeName::from_str(&type_name).ok_or(LuaError::RuntimeError(format!(
+                        "Invalid typeName: {}, consider using i32, i64, f32, etc.",
+                        type_name
+                    )))?;
+                unsafe {
+                    match type_name {
+                        TypeName::I8 => write_value(lua, addr, value.as_i32().unwrap() as i8),
+                        TypeName::I16 => write_value(lua, addr, value.as_i32().unwrap() as i16),
+                        TypeName::I32 => write_value(lua, addr, value.as_i32().unwrap()),
+                        TypeName::I64 => write_value(lua, addr, value.as_i64().unwrap()),
+                        TypeName::F32 => write_value(lua, addr, value.as_f32().unwrap()),
+                        TypeName::F64 => write_value(lua, addr, value.as_f64().unwrap()),
+                        TypeName::Bool => write_value(lua, addr, value.as_boolean().unwrap()),
+                        TypeName::String => todo!(),
+                    };
+                }
+                Ok(())
+            },
+        );
+        methods.add_function("offset", |_, (base, offsets): (usize, Vec<isize>)| {
+            util::get_ptr_with_offset(base as *const u8, &offsets)
+                .map(|ptr| ptr as usize)
+                .ok_or(LuaError::runtime(
+                    "Failed to get reference to memory".to_string(),
+                ))
+        });
+        methods.add_function("moduleBase", |_, name: Option<String>| {
+            Ok(DefaultModules::default()
+                .find(name.as_deref())
+                .map(|module| module.base))
+        });
+        methods.add_function("moduleSize", |_, name: Option<String>| {
+            Ok(DefaultModules::default()
+                .find(name.as_deref())
+                .map(|module| module.size))
+        });
+        methods.add_function(
+            "exportAddress",
+            |_, (module, symbol): (Option<String>, String)| {
+                Ok(DefaultModules::default().export(module.as_deref(), &symbol))
+            },
+        );
+        methods.add_function("rva", |_, offset: isize| {
+            DefaultModules::default()
+                .find(None)
+                .map(|module| module.base.wrapping_add_signed(offset))
+                .ok_or(LuaError::runtime("Failed to find the main module"))
+        });
+        methods.add_function("readBytes", |lua, (target, len): (LuaValue, usize)| {
+            let addr = resolve_target(&target)?;
+            lua.create_string(Buffer::read(addr, len)?.0)
+        });
+        methods.add_function(
+            "writeBytes",
+            |lua, (target, bytes): (LuaValue, LuaValue)| {
+                let addr = resolve_target(&target)?;
+                let bytes = buffer::bytes_from_lua(bytes)?;
+                unsafe { write_bytes(lua, addr, &bytes) };
+                Ok(())
+            },
+        );
+        methods.add_function("readBuffer", |_, (target, len): (LuaValue, usize)| {
+            let addr = resolve_target(&target)?;
+            Buffer::read(addr, len)
+        });
+        methods.add_function("newBuffer", |_, init: LuaValue| match init {
+            LuaValue::Integer(size) => Buffer::zeroed(size.max(0) as usize),
+            init => Ok(Buffer(buffer::bytes_from_lua(init)?)),
+        });
+        methods.add_function(
+            "patch",
+            |lua, (addr, bytes, expected): (usize, LuaValue, Option<LuaValue>)| {
+                let bytes = buffer::bytes_from_lua(bytes)?;
+                let expected = expected.map(buffer::bytes_from_lua).transpose()?;
+                patch::new_patch(lua, addr, bytes, expected)
+            },
+        );
+        methods.add_function(
+            "nop",
+            |lua, (addr, len, expected): (usize, usize, Option<LuaValue>)| {
+                let expected = expected.map(buffer::bytes_from_lua).transpose()?;
+                patch::new_patch(lua, addr, vec![patch::NOP; len], expected)
+            },
+        );
+        methods.add_function(
+            "freeze",
+            |lua, (target, type_name, value, period_ms): (LuaValue, String, LuaValue, Option<u64>)| {
+                freeze::freeze(lua, target, parse_type_name(&type_name)?, value, period_ms)
+            },
+        );
+        methods.add_function("unfreeze", |lua, handle: LuaValue| match handle {
+            LuaValue::Integer(id) => freeze::unfreeze(lua, id as u64),
+            LuaValue::UserData(ud) => {
+                freeze::unfreeze(lua, ud.borrow::<freeze::FreezeHandle>()?.id)
+            }
+            _ => Err(LuaError::runtime(format!(
+                "Expect a freeze handle or id, got {}",
+                handle.type_name()
+            ))),
+        });
+        methods.add_function(
+            "watch",
+            |lua,
+             (target, type_name, callback, opts): (
+                LuaValue,
+                String,
+                LuaFunction,
+                Option<LuaTable>,
+            )| {
+                watch::watch(lua, target, parse_type_name(&type_name)?, callback, opts)
+            },
+        );
+        methods.add_function(
+            "unwatch",
+            |_, handle: LuaUserDataRef<watch::WatchHandle>| Ok(handle.unwatch()),
+        );
+        methods.add_function("setJournaling", |lua, enabled: bool| {
+            if let Some(mut journal) = lua.app_data_mut::<MemoryJournal>() {
+                journal.set_enabled(enabled);
+            }
+            Ok(())
+        });
+        methods.add_function("isJournaling", |lua, ()| {
+            Ok(lua
+                .app_data_ref::<MemoryJournal>()
+                .map(|journal| journal.is_enabled())
+                .unwrap_or(false))
+        });
+        methods.add_function("restoreAll", |lua, ()| {
+            Ok(lua
+                .app_data_mut::<MemoryJournal>()
+                .map(|mut journal| unsafe { journal.restore_all() })
+                .unwrap_or(0))
+        });
+    }
+}
+
+/// Set up the per-VM memory journal.
+pub fn init_journal(lua: &Lua) {
+    lua.set_app_data(MemoryJournal::new(config::get().memory.journal));
+}
+
+/// Stop phase of the memory library.
+///
+/// Removes all freezes and watches and reverts all code patches,
+/// and restores all journaled writes if `memory.restore_on_unload` is enabled.
+pub fn on_stop(lua: &Lua) {
+    let removed = watch::clear(lua);
+    if removed > 0 {
+        debug!("removed {} watches", removed);
+    }
+    if let Some(vm) = VmInfo::of(lua) {
+        let removed = freeze::remove_owner(vm.id);
+        if removed > 0 {
+            debug!("removed {} freezes", removed);
+        }
+    }
+    let reverted = patch::disable_all(lua);
+    if reverted > 0 {
+        debug!("reverted {} patches", reverted);
+    }
+    if !config::get().memory.restore_on_unload {
+        return;
+    }
+    let restored = lua
+        .app_data_mut::<MemoryJournal>()
+        .map(|mut journal| unsafe { journal.restore_all() })
+        .unwrap_or(0);
+    if restored > 0 {
+        debug!("restored {} bytes of journaled memory", restored);
+    }
+}
+
+/// All active freezes of all VMs.
+pub fn list_freezes() -> Vec<FreezeEntry> {
+    freeze::list()
+}
+
+/// Resolve an address or a `RawPtr` argument.
+pub fn resolve_target(target: &LuaValue) -> LuaResult<usize> {
+    target::Target::from_lua(target)?
+        .resolve()
+        .ok_or(LuaError::runtime(
+            "Failed to get reference to memory".to_string(),
+        ))
+}
+
+fn parse_type_name(type_name: &str) -> LuaResult<TypeName> {
+    TypeName::from_str(type_name).ok_or(LuaError::RuntimeError(format!(
+        "Invalid typeName: {}, consider using i32, i64, f32, etc.",
+        type_name
+    )))
+}
+
+/// Write `bytes` to `addr`, journaling the original bytes first.
+unsafe fn write_bytes(lua: &Lua, addr: usize, bytes: &[u8]) {
+    journal::record(lua, addr, bytes.len());
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len());
+}
+
+/// Write `value` to `addr`, journaling the original bytes first.
+unsafe fn write_value<T>(lua: &Lua, addr: usize, value: T) {
+    journal::record(lua, addr, std::mem::size_of::<T>());
+    *(addr as *mut T) = value;
+}
+
+/// RawPtr provides a reference of a specified memory
+///
+/// The builder methods `withBase` and `withOffset` return new pointers,
+/// `setBase` and `addOffset` modify the pointer in place.
+#[derive(Debug, Clone)]
+pub struct RawPtr {
+    base: usize,
+    offsets: Vec<isize>,
+    /// resolution cache, clones and fields get their own one
+    cache: Option<Arc<ResolveCache>>,
+}
+
+impl UserData for RawPtr {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("base", |_, this| Ok(this.base));
+        fields.add_field_method_get("offsets", |_, this| Ok(this.offsets.clone()));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| Ok(this.to_string()));
+        methods.add_method("withBase", |_, this, base: usize| {
+            let mut ptr = this.derive();
+            ptr.set_base(base);
+            Ok(ptr)
+        });
+        methods.add_method("withOffset", |_, this, offsets: mlua::Variadic<isize>| {
+            let mut ptr = this.derive();
+            ptr.offsets(&offsets);
+            Ok(ptr)
+        });
+        methods.add_function("setBase", |_, (ud, base): (LuaAnyUserData, usize)| {
+            ud.borrow_mut::<RawPtr>()?.set_base(base);
+            Ok(ud)
+        });
+        methods.add_function(
+            "addOffset",
+            |_, (ud, offsets): (LuaAnyUserData, mlua::Variadic<isize>)| {
+                ud.borrow_mut::<RawPtr>()?.offsets(&offsets);
+                Ok(ud)
+            },
+        );
+        methods.add_method("cached", |_, this, ttl_ms: Option<u64>| {
+            let mut ptr = this.clone();
+            ptr.cache = Some(Arc::new(ResolveCache::new(
+                ttl_ms.map(Duration::from_millis),
+            )));
+            Ok(ptr)
+        });
+        methods.add_method("invalidate", |_, this, ()| {
+            if let Some(cache) = &this.cache {
+                cache.invalidate();
+            }
+            Ok(())
+        });
+        methods.add_method("field", |_, this, (offset, type_name): (isize, String)| {
+            Ok(Field::new(
+                this.derive(),
+                offset,
+                parse_type_name(&type_name)?,
+            ))
+        });
+        methods.add_method("read", |_, this, type_name: String| {
+            let type_name =
+                TypeName::from_str(&type_name).ok_or(LuaError::RuntimeError(format!(
+                    "Invalid typeName: {}, consider using i32, i64, f32, etc.",
+                    type_name
+                )))?;
+            match type_name {
+                TypeName::I8 => this
+                    .get_copy::<i8>()
+                    .map(|v| v as i64)
+                    .map(mlua::Value::Integer),
+                TypeName::I16 => this
+                    .get_copy::<i16>()
+                    .map(|v| v as i64)
+                    .map(mlua::Value::Integer),
+                TypeName::I32 => this
+                    .get_copy::<i32>()
+                    .map(|v| v as i64)
+                    .map(mlua::Value::Integer),
+                TypeName::I64 => this.get_copy::<i64>().map(mlua::Value::Integer),
+                TypeName::F32 => this
+                    .get_copy::<f32>()
+                    .map(|v| v as f64)
+                    .map(mlua::Value::Number),
+                TypeName::F64 => this.get_copy::<f64>().map(mlua::Value::Number),
+                TypeName::Bool => this.get_copy::<bool>().map(mlua::Value::Boolean),
+                TypeName::String => todo!(),
+            }
+            .ok_or_else(|| this.value_error())
+        });
+        methods.add_method("tryRead", |_, this, type_name: String| {
+            let type_name = parse_type_name(&type_name)?;
+            let size = type_name.size().ok_or(LuaError::runtime(format!(
+                "typeName `{}` is not supported by tryRead",
+                type_name.as_str()
+            )))?;
+            let resolution = this.resolve();
+            let Some(addr) = resolution.address() else {
+                return Ok((LuaNil, resolution.error.map(|e| e.to_string())));
+            };
+            if !protect::is_readable(addr, size) {
+                return Ok((
+                    LuaNil,
+                    Some(format!("value at 0x{:x} is not readable", addr)),
+                ));
+            }
+            let bytes = unsafe { std::slice::from_raw_parts(addr as *const u8, size) };
+            Ok((type_name.decode(bytes), None))
+        });
+        methods.add_method("resolve", |_, this, ()| {
+            let resolution = this.resolve();
+            Ok((
+                resolution.address(),
+                resolution.error.map(|e| e.to_string()),
+            ))
+        });
+        methods.add_method("explain", |lua, this, ()| this.resolve().to_lua_table(lua));
+        methods.add_method(
+            "readMulti",
+            |_, this, (type_name, count): (String, usize)| {
+                let type_name =
+                    TypeName::from_str(&type_name).ok_or(LuaError::RuntimeError(format!(
+                        "Invalid typeName: {}, consider using i32, i64, f32, etc.",
+                        type_name
+                    )))?;
+                match type_name {
+                    TypeName::I8 => this.get_multi_copy::<i8>(count).map(|v| {
+                        v.into_iter()
+                            .map(|v| v as i64)
+                            .map(mlua::Value::Integer)
+                            .collect::<Vec<_>>()
+                    }),
+                    TypeName::I16 => this.get_multi_copy::<i16>(count).map(|v| {
+                        v.into_iter()
+                            .map(|v| v as i64)
+                            .map(mlua::Value::Integer)
+                            .collect::<Vec<_>>()
+                    }),
+                    TypeName::I32 => this.get_multi_copy::<i32>(count).map(|v| {
+                        v.into_iter()
+                            .map(|v| v as i64)
+                            .map(mlua::Value::Integer)
+                            .collect::<Vec<_>>()
+                    }),
+                    TypeName::I64 => this
+                        .get_multi_copy::<i64>(count)
+                        .map(|v| v.into_iter().map(mlua::Value::Integer).collect::<Vec<_>>()),
+                    TypeName::F32 => this.get_multi_copy::<f32>(count).map(|v| {
+                        v.into_iter()
+                            .map(|v| v as f64)
+                            .map(mlua::Value::Number)
+                            .collect::<Vec<_>>()
+                    }),
+                    TypeName::F64 => this
+                        .get_multi_copy::<f64>(count)
+                        .map(|v| v.into_iter().map(mlua::Value::Number).collect::<Vec<_>>()),
+                    TypeName::Bool => this
+                        .get_multi_copy::<bool>(count)
+                        .map(|v| v.into_iter().map(mlua::Value::Boolean).collect::<Vec<_>>()),
+                    TypeName::String => todo!(),
+                }
+                .ok_or_else(|| this.value_error())
+            },
+        );
+        methods.add_method("write", |lua, this, (value, type_name): (mlua::Value, Option<String>)| {
+            match type_name {
+                Some(type_name) => {
+                    let type_sig = TypeName::from_str(&type_name).ok_or(LuaError::RuntimeError(format!("Invalid type name: {}", type_name)))?;
+                    match value {
+                        LuaValue::Boolean(v) => {
+                            this.set_value::<bool>(lua, v).map_err(|e| LuaError::RuntimeError(e.to_string()))
+                        },
+                        // Integer values support only integers, while Number values support both integers and floats.
+                        LuaValue::Integer(v) => {
+                            match type_sig {
+                                TypeName::I8 => this.set_value(lua, v as i8).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::I16 => this.set_value(lua, v as i16).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::I32 => this.set_value(lua, v as i32).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::I64 => this.set_value(lua, v).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                _ => Err(LuaError::RuntimeError(format!("The type of the value is {:?}, while `typeName` is {:?}, does not match", value ,type_name))),
+                            }
+                        },
+                        LuaValue::Number(v) => {
+                            match type_sig {
+                                TypeName::I8 => this.set_value(lua, v as i8).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::I16 => this.set_value(lua, v as i16).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::I32 => this.set_value(lua, v as i32).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::I64 => this.set_value(lua, v as i64).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::F32 => this.set_value(lua, v as f32).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                TypeName::F64 => this.set_value(lua, v).map_err(|e| LuaError::RuntimeError(e.to_string())),
+                                _ => Err(LuaError::RuntimeError(format!("The type of the value is {:?}, while `typeName` is {:?}, does not match", value ,type_name))),
+                            }
+                        },
+                        LuaValue::String(_) => unimplemented!(),
+                        _ => Err(LuaError::RuntimeError(format!("Unsupported value type: {}", value.type_name())))
+                    }
+                }
+                None => {
+                    match value {
+                        LuaValue::Boolean(v) => {
+                            this.set_value(lua, v).map_err(|e| LuaError::RuntimeError(e.to_string()))
+                        },
+                        // Integer values support only integers, while Number values support both integers and floats.
+                        LuaValue::Integer(_) => Err(LuaError::RuntimeError("Integer value must provide `typeName` argument, such as i32, i64, etc.".to_string())),
+                        LuaValue::Number(_) => Err(LuaError::RuntimeError("Number value must provide `typeName` argument, such as i32, f32, etc.".to_string())),
+                        LuaValue::String(_) => todo!(),
+                        _ => Err(LuaError::RuntimeError(format!("Unsupported value type: {}", value.type_name())))
+                    }
+                }
+            }
+        });
+        methods.add_method("clone", |_, this, ()| Ok(this.derive()));
+    }
+}
+
+impl Default for RawPtr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for RawPtr {
+    /// `RawPtr(0x1400000 + 0x10 -> -0x8)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RawPtr(0x{:x}", self.base)?;
+        for (i, offset) in self.offsets.iter().enumerate() {
+            let sep = if i == 0 { " + " } else { " -> " };
+            match *offset < 0 {
+                true => write!(f, "{}-0x{:x}", sep, offset.unsigned_abs())?,
+                false => write!(f, "{}0x{:x}", sep, offset)?,
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+impl RawPtr {
+    pub fn new() -> RawPtr {
+        RawPtr {
+            base: 0,
+            offsets: Vec::new(),
+            cache: None,
+        }
+    }
+
+    /// Copy of this pointer to be changed, with its own cache if caching is enabled.
+    fn derive(&self) -> RawPtr {
+        RawPtr {
+            base: self.base,
+            offsets: self.offsets.clone(),
+            cache: self
+                .cache
+                .as_ref()
+                .map(|cache| Arc::new(ResolveCache::new(cache.ttl()))),
+        }
+    }
+
+    fn invalidate(&self) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate();
+        }
+    }
+
+    pub fn set_base(&mut self, base: usize) {
+        self.base = base;
+        self.invalidate();
+    }
+
+    pub fn offset(&mut self, offset: isize) {
+        self.offsets.push(offset);
+        self.invalidate();
+    }
+
+    pub fn offsets(&mut self, offsets: &[isize]) {
+        self.offsets.extend_from_slice(offsets);
+        self.invalidate();
+    }
+
+    /// Resolved address, from the cache if enabled.
+    pub fn address(&self) -> Option<usize> {
+        let resolve =
+            || util::get_ptr_with_offset(self.base as *const u8, &self.offsets).map(|p| p as usize);
+        match &self.cache {
+            Some(cache) => cache.get_or_resolve(resolve),
+            None => resolve(),
+        }
+    }
+
+    /// Resolve the chain hop by hop, stopping at null or unreadable pointers.
+    pub fn resolve(&self) -> Resolution {
+        Resolution::resolve(self.base, &self.offsets)
+    }
+
+    /// Error for a failed read, explaining which hop failed.
+    fn value_error(&self) -> LuaError {
+        let resolution = self.resolve();
+        match resolution.error {
+            Some(_) => LuaError::RuntimeError(format!("Failed to get value: {}", resolution)),
+            None => LuaError::RuntimeError("Failed to get value".to_string()),
+        }
+    }
+
+    pub fn get_copy<T>(&self) -> Option<T>
+    where
+        T: Copy,
+    {
+        self.get_ptr::<T>().map(|ptr| unsafe { *ptr })
+    }
+
+    pub fn get_ptr<T>(&self) -> Option<*const T> {
+        self.address().map(|addr| addr as *const T)
+    }
+
+    pub fn get_multi_copy<T>(&self, count: usize) -> Option<Vec<T>>
+    where
+        T: Copy,
+    {
+        let mut result = Vec::with_capacity(count as usize);
+        let ptr = self.get_ptr::<T>()?;
+        unsafe {
+            for i in 0..count {
+                result.push(*(ptr.byte_add(std::mem::size_of::<T>() * i)))
+            }
+        }
+        Some(result)
+    }
+
+    pub fn set_value<T>(&self, lua: &Lua, value: T) -> Result<(), String> {
+        let ptr = self
+            .get_ptr::<T>()
+            .ok_or("Failed to get reference to memory".to_string())?;
+        unsafe {
+            write_value(lua, ptr as usize, value);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TypeName {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    String,
+}
+
+impl TypeName {
+    pub fn from_str(type_name: &str) -> Option<TypeName> {
+        match type_name {
+            "i8" => Some(TypeName::I8),
+            "i16" => Some(TypeName::I16),
+            "i32" => Some(TypeName::I32),
+            "i64" => Some(TypeName::I64),
+            "f32" => Some(TypeName::F32),
+            "f64" => Some(TypeName::F64),
+            "bool" => Some(TypeName::Bool),
+            "string" => Some(TypeName::String),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TypeName::I8 => "i8",
+            TypeName::I16 => "i16",
+            TypeName::I32 => "i32",
+            TypeName::I64 => "i64",
+            TypeName::F32 => "f32",
+            TypeName::F64 => "f64",
+            TypeName::Bool => "bool",
+            TypeName::String => "string",
+        }
+    }
+
+    /// Size in bytes, `None` for variable-sized types.
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            TypeName::I8 | TypeName::Bool => Some(1),
+            TypeName::I16 => Some(2),
+            TypeName::I32 | TypeName::F32 => Some(4),
+            TypeName::I64 | TypeName::F64 => Some(8),
+            TypeName::String => None,
+        }
+    }
+
+    /// Convert a Lua value to the in-memory bytes of this type.
+    pub fn encode(&self, value: &LuaValue) -> LuaResult<Vec<u8>> {
+        let mismatch = || {
+            LuaError::RuntimeError(format!(
+                "The type of the value is {}, while `typeName` is {}, does not match",
+                value.type_name(),
+                self.as_str()
+            ))
+        };
+        let int = || match value {
+            LuaValue::Integer(v) => Ok(*v),
+            LuaValue::Number(v) => Ok(*v as i64),
+            _ => Err(mismatch()),
+        };
+        let num = || match value {
+            LuaValue::Integer(v) => Ok(*v as f64),
+            LuaValue::Number(v) => Ok(*v),
+            _ => Err(mismatch()),
+        };
+        Ok(match self {
+            TypeName::I8 => (int()? as i8).to_ne_bytes().to_vec(),
+            TypeName::I16 => (int()? as i16).to_ne_bytes().to_vec(),
+            TypeName::I32 => (int()? as i32).to_ne_bytes().to_vec(),
+            TypeName::I64 => int()?.to_ne_bytes().to_vec(),
+            TypeName::F32 => (num()? as f32).to_ne_bytes().to_vec(),
+            TypeName::F64 => num()?.to_ne_bytes().to_vec(),
+            TypeName::Bool => vec![value.as_boolean().ok_or_else(mismatch)? as u8],
+            TypeName::String => {
+                return Err(LuaError::RuntimeError(
+                    "typeName `string` is not supported here".to_string(),
+                ))
+            }
+        })
+    }
+
+    /// Convert in-memory bytes of this type to a Lua value.
+    ///
+    /// `bytes` must be at least [`TypeName::size`] long.
+    pub fn decode<'lua>(&self, bytes: &[u8]) -> LuaValue<'lua> {
+        let mut buf = [0u8; 8];
+        if let Some(size) = self.size() {
+            buf[..size].copy_from_slice(&bytes[..size]);
+        }
+        match self {
+            TypeName::I8 => LuaValue::Integer(buf[0] as i8 as i64),
+            TypeName::I16 => LuaValue::Integer(i16::from_ne_bytes([buf[0], buf[1]]) as i64),
+            TypeName::I32 => {
+                LuaValue::Integer(i32::from_ne_bytes(buf[..4].try_into().unwrap()) as i64)
+            }
+            TypeName::I64 => LuaValue::Integer(i64::from_ne_bytes(buf)),
+            TypeName::F32 => {
+                LuaValue::Number(f32::from_ne_bytes(buf[..4].try_into().unwrap()) as f64)
+            }
+            TypeName::F64 => LuaValue::Number(f64::from_ne_bytes(buf)),
+            TypeName::Bool => LuaValue::Boolean(buf[0] != 0),
+            TypeName::String => LuaValue::Nil,
+        }
+    }
+}
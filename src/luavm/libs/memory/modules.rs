@@ -0,0 +1,222 @@
+#[cfg(unix)]
+use std::path::Path;
+
+/// A module (executable or shared library) loaded in this process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub base: usize,
+    pub size: usize,
+}
+
+/// Backend to look up loaded modules and their exports.
+pub trait Modules {
+    /// Find a module by file name, or the main executable if `name` is `None`.
+    fn find(&self, name: Option<&str>) -> Option<ModuleInfo>;
+
+    /// Address of an exported symbol of a module.
+    fn export(&self, module: Option<&str>, symbol: &str) -> Option<usize>;
+}
+
+/// Compare the file name of a module path with `name`.
+#[cfg(unix)]
+fn file_name_matches(path: &str, name: &str) -> bool {
+    Path::new(path)
+        .file_name()
+        .is_some_and(|file_name| file_name.to_string_lossy() == name)
+}
+
+#[cfg(windows)]
+pub type DefaultModules = WindowsModules;
+#[cfg(unix)]
+pub type DefaultModules = ElfModules;
+
+/// Module lookup with `GetModuleHandleW`, reading the image size from the PE headers.
+#[cfg(windows)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WindowsModules;
+
+#[cfg(windows)]
+impl WindowsModules {
+    fn handle(name: Option<&str>) -> Option<usize> {
+        use std::os::windows::ffi::OsStrExt;
+        use winapi::um::libloaderapi::GetModuleHandleW;
+
+        let handle = match name {
+            Some(name) => {
+                let wide = std::ffi::OsStr::new(name)
+                    .encode_wide()
+                    .chain(std::iter::once(0))
+                    .collect::<Vec<u16>>();
+                unsafe { GetModuleHandleW(wide.as_ptr()) }
+            }
+            None => unsafe { GetModuleHandleW(std::ptr::null()) },
+        };
+        if handle.is_null() {
+            None
+        } else {
+            Some(handle as usize)
+        }
+    }
+
+    /// `SizeOfImage` of the loaded PE image at `base`.
+    unsafe fn image_size(base: usize) -> usize {
+        use winapi::um::winnt::{IMAGE_DOS_HEADER, IMAGE_NT_HEADERS};
+
+        let dos = &*(base as *const IMAGE_DOS_HEADER);
+        let nt = &*((base + dos.e_lfanew as usize) as *const IMAGE_NT_HEADERS);
+        nt.OptionalHeader.SizeOfImage as usize
+    }
+}
+
+#[cfg(windows)]
+impl Modules for WindowsModules {
+    fn find(&self, name: Option<&str>) -> Option<ModuleInfo> {
+        let base = WindowsModules::handle(name)?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => std::env::current_exe()
+                .ok()
+                .and_then(|p| p.file_name().map(|f| f.to_string_lossy().to_string()))
+                .unwrap_or_default(),
+        };
+        Some(ModuleInfo {
+            name,
+            base,
+            size: unsafe { WindowsModules::image_size(base) },
+        })
+    }
+
+    fn export(&self, module: Option<&str>, symbol: &str) -> Option<usize> {
+        use winapi::um::libloaderapi::GetProcAddress;
+
+        let handle = WindowsModules::handle(module)?;
+        let symbol = std::ffi::CString::new(symbol).ok()?;
+        let addr = unsafe { GetProcAddress(handle as _, symbol.as_ptr()) };
+        if addr.is_null() {
+            None
+        } else {
+            Some(addr as usize)
+        }
+    }
+}
+
+/// Module lookup over the loaded ELF objects with `dl_iterate_phdr`.
+#[cfg(unix)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ElfModules;
+
+#[cfg(unix)]
+impl ElfModules {
+    /// All loaded objects, the main executable first.
+    pub fn list() -> Vec<ModuleInfo> {
+        unsafe extern "C" fn callback(
+            info: *mut libc::dl_phdr_info,
+            _size: libc::size_t,
+            data: *mut libc::c_void,
+        ) -> libc::c_int {
+            let modules = &mut *(data as *mut Vec<ModuleInfo>);
+            let info = &*info;
+            let headers = std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+            let loads = headers.iter().filter(|h| h.p_type == libc::PT_LOAD);
+            let start = loads.clone().map(|h| h.p_vaddr).min();
+            let end = loads.map(|h| h.p_vaddr + h.p_memsz).max();
+            if let (Some(start), Some(end)) = (start, end) {
+                let name = if info.dlpi_name.is_null() || *info.dlpi_name == 0 {
+                    String::new()
+                } else {
+                    std::ffi::CStr::from_ptr(info.dlpi_name)
+                        .to_string_lossy()
+                        .to_string()
+                };
+                modules.push(ModuleInfo {
+                    name,
+                    base: info.dlpi_addr as usize + start as usize,
+                    size: (end - start) as usize,
+                });
+            }
+            0
+        }
+
+        let mut modules: Vec<ModuleInfo> = Vec::new();
+        unsafe {
+            libc::dl_iterate_phdr(Some(callback), &mut modules as *mut _ as *mut libc::c_void);
+        }
+        // the main executable is reported without a name
+        if let Some(main) = modules.iter_mut().find(|m| m.name.is_empty()) {
+            main.name = std::env::current_exe()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+        }
+        modules
+    }
+}
+
+#[cfg(unix)]
+impl Modules for ElfModules {
+    fn find(&self, name: Option<&str>) -> Option<ModuleInfo> {
+        let modules = ElfModules::list();
+        match name {
+            Some(name) => modules
+                .into_iter()
+                .find(|m| file_name_matches(&m.name, name)),
+            None => modules.into_iter().next(),
+        }
+    }
+
+    fn export(&self, module: Option<&str>, symbol: &str) -> Option<usize> {
+        let path = match module {
+            Some(name) => Some(std::ffi::CString::new(self.find(Some(name))?.name).ok()?),
+            None => None,
+        };
+        let symbol = std::ffi::CString::new(symbol).ok()?;
+        unsafe {
+            let handle = libc::dlopen(
+                path.as_ref().map_or(std::ptr::null(), |p| p.as_ptr()),
+                libc::RTLD_LAZY | libc::RTLD_NOLOAD,
+            );
+            if handle.is_null() {
+                return None;
+            }
+            let addr = libc::dlsym(handle, symbol.as_ptr());
+            libc::dlclose(handle);
+            if addr.is_null() {
+                None
+            } else {
+                Some(addr as usize)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn contains(module: &ModuleInfo, addr: usize) -> bool {
+        (module.base..module.base + module.size).contains(&addr)
+    }
+
+    #[test]
+    fn test_main_module() {
+        let main = ElfModules.find(None).unwrap();
+        assert!(main.size > 0);
+        assert!(contains(&main, test_main_module as fn() as usize));
+        let exe = std::env::current_exe().unwrap();
+        let exe_name = exe.file_name().unwrap().to_str().unwrap();
+        assert_eq!(ElfModules.find(Some(exe_name)), Some(main));
+    }
+
+    #[test]
+    fn test_export() {
+        let libc = ElfModules::list()
+            .into_iter()
+            .find(|m| m.name.contains("libc.so"))
+            .unwrap();
+        let libc_name = Path::new(&libc.name).file_name().unwrap().to_str().unwrap();
+        let malloc = ElfModules.export(Some(libc_name), "malloc").unwrap();
+        assert!(contains(&libc, malloc));
+        assert_eq!(ElfModules.export(Some(libc_name), "no_such_symbol"), None);
+        assert_eq!(ElfModules.find(Some("no_such_module.so")), None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::sync::{Arc, Mutex};
+
+use mlua::prelude::*;
+use mlua::UserData;
+use snafu::prelude::*;
+
+use super::protect::{is_readable, DefaultProtection, ProtectError, Protection};
+
+pub const NOP: u8 = 0x90;
+
+#[derive(Debug, Snafu)]
+pub enum PatchError {
+    #[snafu(display("{}", source))]
+    Protect { source: ProtectError },
+    #[snafu(display(
+        "Original bytes at 0x{:x} do not match, expected {:02X?}, found {:02X?}",
+        addr,
+        expected,
+        found
+    ))]
+    OriginalMismatch {
+        addr: usize,
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+    #[snafu(display(
+        "Length of expected bytes ({}) does not match the patch ({})",
+        expected,
+        patch
+    ))]
+    LengthMismatch { expected: usize, patch: usize },
+    #[snafu(display("Memory at 0x{:x} is not readable", addr))]
+    Unreadable { addr: usize },
+}
+
+/// A byte patch of (usually executable) memory, which can be toggled.
+#[derive(Debug)]
+pub struct Patch<P: Protection = DefaultProtection> {
+    addr: usize,
+    bytes: Vec<u8>,
+    /// bytes required at `addr` before applying
+    expected: Option<Vec<u8>>,
+    /// bytes replaced by the patch, saved when enabled
+    original: Option<Vec<u8>>,
+    protection: P,
+}
+
+impl<P: Protection> Patch<P> {
+    pub fn new(
+        protection: P,
+        addr: usize,
+        bytes: Vec<u8>,
+        expected: Option<Vec<u8>>,
+    ) -> Result<Self, PatchError> {
+        if let Some(expected) = &expected {
+            ensure!(
+                expected.len() == bytes.len(),
+                LengthMismatchSnafu {
+                    expected: expected.len(),
+                    patch: bytes.len()
+                }
+            );
+        }
+        Ok(Self {
+            addr,
+            bytes,
+            expected,
+            original: None,
+            protection,
+        })
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.original.is_some()
+    }
+
+    /// Apply the patch, verifying the expected original bytes first.
+    ///
+    /// # Safety
+    ///
+    /// The patched range must be mapped memory of this process.
+    pub unsafe fn enable(&mut self) -> Result<(), PatchError> {
+        if self.is_enabled() {
+            return Ok(());
+        }
+        ensure!(
+            is_readable(self.addr, self.bytes.len()),
+            UnreadableSnafu { addr: self.addr }
+        );
+        let current = std::slice::from_raw_parts(self.addr as *const u8, self.bytes.len()).to_vec();
+        if let Some(expected) = &self.expected {
+            ensure!(
+                *expected == current,
+                OriginalMismatchSnafu {
+                    addr: self.addr,
+                    expected: expected.clone(),
+                    found: current
+                }
+            );
+        }
+        let old = self
+            .protection
+            .make_writable(self.addr, self.bytes.len())
+            .context(ProtectSnafu)?;
+        std::ptr::copy_nonoverlapping(self.bytes.as_ptr(), self.addr as *mut u8, self.bytes.len());
+        // the patch is applied from here on, even if restoring the protection fails
+        self.original = Some(current);
+        self.protection
+            .restore(self.addr, self.bytes.len(), old)
+            .context(ProtectSnafu)
+    }
+
+    /// Write back the original bytes.
+    ///
+    /// # Safety
+    ///
+    /// The patched range must be mapped memory of this process.
+    pub unsafe fn disable(&mut self) -> Result<(), PatchError> {
+        let Some(original) = &self.original else {
+            return Ok(());
+        };
+        let len = original.len();
+        // unmapped since it was enabled, like code of an unloaded module
+        ensure!(
+            is_readable(self.addr, len),
+            UnreadableSnafu { addr: self.addr }
+        );
+        let old = self
+            .protection
+            .make_writable(self.addr, len)
+            .context(ProtectSnafu)?;
+        std::ptr::copy_nonoverlapping(original.as_ptr(), self.addr as *mut u8, len);
+        self.original = None;
+        self.protection
+            .restore(self.addr, len, old)
+            .context(ProtectSnafu)
+    }
+}
+
+/// Patches created by a Lua VM, stored as app data of the VM.
+#[derive(Default)]
+pub struct PatchRegistry {
+    patches: Vec<Arc<Mutex<Patch>>>,
+}
+
+impl PatchRegistry {
+    /// Disable all patches, latest first. Returns the number of reverted patches.
+    pub fn disable_all(&mut self) -> usize {
+        let mut count = 0;
+        for patch in self.patches.drain(..).rev() {
+            let mut patch = patch.lock().unwrap();
+            if !patch.is_enabled() {
+                continue;
+            }
+            match unsafe { patch.disable() } {
+                Ok(_) => count += 1,
+                Err(e) => log::error!("Failed to revert patch at 0x{:x}: {}", patch.addr(), e),
+            }
+        }
+        count
+    }
+}
+
+/// Lua handle of a [`Patch`]
+#[derive(Clone)]
+pub struct LuaPatch(Arc<Mutex<Patch>>);
+
+impl UserData for LuaPatch {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("address", |_, this| Ok(this.0.lock().unwrap().addr()));
+        fields.add_field_method_get("size", |_, this| Ok(this.0.lock().unwrap().len()));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("enable", |_, this, ()| {
+            unsafe { this.0.lock().unwrap().enable() }.map_err(|e| LuaError::runtime(e.to_string()))
+        });
+        methods.add_method("disable", |_, this, ()| {
+            unsafe { this.0.lock().unwrap().disable() }
+                .map_err(|e| LuaError::runtime(e.to_string()))
+        });
+        methods.add_method("isEnabled", |_, this, ()| {
+            Ok(this.0.lock().unwrap().is_enabled())
+        });
+    }
+}
+
+/// Create and enable a patch owned by the VM of `lua`.
+pub fn new_patch(
+    lua: &Lua,
+    addr: usize,
+    bytes: Vec<u8>,
+    expected: Option<Vec<u8>>,
+) -> LuaResult<LuaPatch> {
+    let mut patch = Patch::new(DefaultProtection::default(), addr, bytes, expected)
+        .map_err(|e| LuaError::runtime(e.to_string()))?;
+    unsafe { patch.enable() }.map_err(|e| LuaError::runtime(e.to_string()))?;
+
+    let patch = Arc::new(Mutex::new(patch));
+    if lua.app_data_ref::<PatchRegistry>().is_none() {
+        lua.set_app_data(PatchRegistry::default());
+    }
+    lua.app_data_mut::<PatchRegistry>()
+        .unwrap()
+        .patches
+        .push(patch.clone());
+
+    Ok(LuaPatch(patch))
+}
+
+/// Disable all patches of the VM of `lua`.
+pub fn disable_all(lua: &Lua) -> usize {
+    lua.app_data_mut::<PatchRegistry>()
+        .map(|mut registry| registry.disable_all())
+        .unwrap_or(0)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::luavm::libs::memory::protect::Mprotect;
+
+    /// Map a read-only executable page, like a code page of the game.
+    fn map_code_page(content: &[u8]) -> usize {
+        unsafe {
+            let page = libc::mmap(
+                std::ptr::null_mut(),
+                4096,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(page, libc::MAP_FAILED);
+            std::ptr::copy_nonoverlapping(content.as_ptr(), page as *mut u8, content.len());
+            assert_eq!(
+                libc::mprotect(page, 4096, libc::PROT_READ | libc::PROT_EXEC),
+                0
+            );
+            page as usize
+        }
+    }
+
+    fn unmap(page: usize) {
+        assert_eq!(unsafe { libc::munmap(page as *mut libc::c_void, 4096) }, 0);
+    }
+
+    fn read(addr: usize, len: usize) -> Vec<u8> {
+        unsafe { std::slice::from_raw_parts(addr as *const u8, len).to_vec() }
+    }
+
+    #[test]
+    fn test_patch_toggle() {
+        let page = map_code_page(&[0x55, 0x48, 0x89, 0xE5, 0xC3]);
+        let mut patch = Patch::new(
+            Mprotect,
+            page + 1,
+            vec![NOP; 3],
+            Some(vec![0x48, 0x89, 0xE5]),
+        )
+        .unwrap();
+
+        unsafe { patch.enable() }.unwrap();
+        assert!(patch.is_enabled());
+        assert_eq!(read(page, 5), [0x55, NOP, NOP, NOP, 0xC3]);
+        assert_eq!(
+            Mprotect::query(page).unwrap(),
+            (libc::PROT_READ | libc::PROT_EXEC) as u32
+        );
+
+        unsafe { patch.disable() }.unwrap();
+        assert!(!patch.is_enabled());
+        assert_eq!(read(page, 5), [0x55, 0x48, 0x89, 0xE5, 0xC3]);
+        unmap(page);
+    }
+
+    #[test]
+    fn test_patch_mismatch() {
+        let page = map_code_page(&[0x55, 0x48, 0x89, 0xE5, 0xC3]);
+        let mut patch = Patch::new(Mprotect, page, vec![NOP; 2], Some(vec![0x90, 0x90])).unwrap();
+
+        let result = unsafe { patch.enable() };
+        assert!(matches!(result, Err(PatchError::OriginalMismatch { .. })));
+        assert!(!patch.is_enabled());
+        assert_eq!(read(page, 2), [0x55, 0x48]);
+        unmap(page);
+    }
+
+    #[test]
+    fn test_patch_unreadable() {
+        let page = map_code_page(&[0x55, 0x48, 0x89, 0xE5, 0xC3]);
+        let mut patch = Patch::new(Mprotect, page, vec![NOP; 2], None).unwrap();
+        unsafe { patch.enable() }.unwrap();
+        unmap(page);
+
+        let result = unsafe { patch.disable() };
+        assert!(matches!(result, Err(PatchError::Unreadable { .. })));
+        let mut patch = Patch::new(Mprotect, page, vec![NOP; 2], None).unwrap();
+        let result = unsafe { patch.enable() };
+        assert!(matches!(result, Err(PatchError::Unreadable { .. })));
+        assert!(!patch.is_enabled());
+    }
+
+    #[test]
+    fn test_patch_length_mismatch() {
+        let result = Patch::new(Mprotect, 0, vec![NOP; 2], Some(vec![0x90]));
+        assert!(matches!(result, Err(PatchError::LengthMismatch { .. })));
+    }
+}
@@ -0,0 +1,180 @@
+use snafu::prelude::*;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum ProtectError {
+    #[snafu(display("Failed to change protection of 0x{:x}: {}", addr, source))]
+    Protect { addr: usize, source: std::io::Error },
+    #[snafu(display("Address 0x{:x} is not mapped", addr))]
+    NotMapped { addr: usize },
+}
+
+/// Backend to change the page protection of a memory range.
+pub trait Protection {
+    /// Make `[addr, addr + len)` writable, returning the previous protection.
+    ///
+    /// # Safety
+    ///
+    /// The range must be mapped memory of this process.
+    unsafe fn make_writable(&self, addr: usize, len: usize) -> Result<u32, ProtectError>;
+
+    /// Restore a protection previously returned by [`Protection::make_writable`].
+    ///
+    /// # Safety
+    ///
+    /// The range must be mapped memory of this process.
+    unsafe fn restore(&self, addr: usize, len: usize, old: u32) -> Result<(), ProtectError>;
+}
+
+/// Whether `[addr, addr + len)` is committed, readable memory of this process.
+#[cfg(windows)]
+pub fn is_readable(addr: usize, len: usize) -> bool {
+    use winapi::um::memoryapi::VirtualQuery;
+    use winapi::um::winnt::{MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_GUARD, PAGE_NOACCESS};
+
+    let end = addr.saturating_add(len);
+    let mut start = addr;
+    while start < end {
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<MEMORY_BASIC_INFORMATION>();
+        if unsafe { VirtualQuery(start as _, &mut info, size) } == 0 {
+            return false;
+        }
+        if info.State != MEM_COMMIT || info.Protect & (PAGE_NOACCESS | PAGE_GUARD) != 0 {
+            return false;
+        }
+        start = info.BaseAddress as usize + info.RegionSize;
+    }
+    true
+}
+
+/// Whether `[addr, addr + len)` is mapped, readable memory of this process.
+#[cfg(unix)]
+pub fn is_readable(addr: usize, len: usize) -> bool {
+    let Ok(maps) = std::fs::read_to_string("/proc/self/maps") else {
+        return false;
+    };
+    let readable =
+        |a| Mprotect::query_in(&maps, a).is_ok_and(|prot| prot & libc::PROT_READ as u32 != 0);
+    readable(addr) && (len <= 1 || readable(addr.saturating_add(len - 1)))
+}
+
+#[cfg(windows)]
+pub type DefaultProtection = VirtualProtection;
+#[cfg(unix)]
+pub type DefaultProtection = Mprotect;
+
+/// `VirtualProtect` based backend.
+#[cfg(windows)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VirtualProtection;
+
+#[cfg(windows)]
+impl Protection for VirtualProtection {
+    unsafe fn make_writable(&self, addr: usize, len: usize) -> Result<u32, ProtectError> {
+        use winapi::um::memoryapi::VirtualProtect;
+        use winapi::um::winnt::PAGE_EXECUTE_READWRITE;
+
+        let mut old = 0;
+        if VirtualProtect(addr as _, len, PAGE_EXECUTE_READWRITE, &mut old) == 0 {
+            return Err(std::io::Error::last_os_error()).context(ProtectSnafu { addr });
+        }
+        Ok(old)
+    }
+
+    unsafe fn restore(&self, addr: usize, len: usize, old: u32) -> Result<(), ProtectError> {
+        use winapi::um::memoryapi::VirtualProtect;
+        use winapi::um::processthreadsapi::{FlushInstructionCache, GetCurrentProcess};
+
+        let mut tmp = 0;
+        if VirtualProtect(addr as _, len, old, &mut tmp) == 0 {
+            return Err(std::io::Error::last_os_error()).context(ProtectSnafu { addr });
+        }
+        FlushInstructionCache(GetCurrentProcess(), addr as _, len);
+        Ok(())
+    }
+}
+
+/// `mprotect` based backend.
+///
+/// The previous protection is looked up in `/proc/self/maps`,
+/// using the protection of the page containing `addr`.
+#[cfg(unix)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Mprotect;
+
+#[cfg(unix)]
+impl Mprotect {
+    fn page_range(addr: usize, len: usize) -> (usize, usize) {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let start = addr & !(page_size - 1);
+        let end = (addr + len).div_ceil(page_size) * page_size;
+        (start, end - start)
+    }
+
+    /// Protection flags (`PROT_*`) of the mapping containing `addr`.
+    pub fn query(addr: usize) -> Result<u32, ProtectError> {
+        let maps = std::fs::read_to_string("/proc/self/maps").context(ProtectSnafu { addr })?;
+        Self::query_in(&maps, addr)
+    }
+
+    /// Protection flags of the mapping containing `addr`, in the content of `/proc/self/maps`.
+    fn query_in(maps: &str, addr: usize) -> Result<u32, ProtectError> {
+        for line in maps.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(range), Some(perms)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let Some((start, end)) = range.split_once('-') else {
+                continue;
+            };
+            let (Ok(start), Ok(end)) = (
+                usize::from_str_radix(start, 16),
+                usize::from_str_radix(end, 16),
+            ) else {
+                continue;
+            };
+            if (start..end).contains(&addr) {
+                let perms = perms.as_bytes();
+                let mut prot = libc::PROT_NONE;
+                if perms.first() == Some(&b'r') {
+                    prot |= libc::PROT_READ;
+                }
+                if perms.get(1) == Some(&b'w') {
+                    prot |= libc::PROT_WRITE;
+                }
+                if perms.get(2) == Some(&b'x') {
+                    prot |= libc::PROT_EXEC;
+                }
+                return Ok(prot as u32);
+            }
+        }
+
+        NotMappedSnafu { addr }.fail()
+    }
+}
+
+#[cfg(unix)]
+impl Protection for Mprotect {
+    unsafe fn make_writable(&self, addr: usize, len: usize) -> Result<u32, ProtectError> {
+        let old = Mprotect::query(addr)?;
+        let (start, size) = Mprotect::page_range(addr, len);
+        if libc::mprotect(
+            start as _,
+            size,
+            old as i32 | libc::PROT_READ | libc::PROT_WRITE,
+        ) != 0
+        {
+            return Err(std::io::Error::last_os_error()).context(ProtectSnafu { addr });
+        }
+        Ok(old)
+    }
+
+    unsafe fn restore(&self, addr: usize, len: usize, old: u32) -> Result<(), ProtectError> {
+        let (start, size) = Mprotect::page_range(addr, len);
+        if libc::mprotect(start as _, size, old as i32) != 0 {
+            return Err(std::io::Error::last_os_error()).context(ProtectSnafu { addr });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+
+use mlua::prelude::*;
+
+use super::{RawPtr, Resolution};
+
+/// Memory location given as an address or a `RawPtr`.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Address(usize),
+    /// resolved again on every access, so it follows moving objects
+    Pointer {
+        base: usize,
+        offsets: Vec<isize>,
+    },
+}
+
+impl Target {
+    pub fn from_lua(value: &LuaValue) -> LuaResult<Target> {
+        match value {
+            LuaValue::Integer(addr) => Ok(Target::Address(*addr as usize)),
+            LuaValue::UserData(ud) => {
+                let ptr = ud.borrow::<RawPtr>()?;
+                Ok(Target::Pointer {
+                    base: ptr.base,
+                    offsets: ptr.offsets.clone(),
+                })
+            }
+            _ => Err(LuaError::runtime(format!(
+                "Expect an address or a RawPtr, got {}",
+                value.type_name()
+            ))),
+        }
+    }
+
+    /// Address of the target, `None` if a pointer of the chain is null or not
+    /// readable, like a stale chain after its object was freed.
+    pub fn resolve(&self) -> Option<usize> {
+        match self {
+            Target::Address(addr) => Some(*addr),
+            Target::Pointer { base, offsets } => Resolution::resolve(*base, offsets).address(),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Address(addr) => write!(f, "0x{:x}", addr),
+            Target::Pointer { base, offsets } => {
+                write!(f, "0x{:x}", base)?;
+                for offset in offsets {
+                    write!(f, " -> {:+#x}", offset)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let value = 7i32;
+        let ptr = [&value as *const i32 as usize];
+        let target = Target::Pointer {
+            base: ptr.as_ptr() as usize,
+            offsets: vec![0],
+        };
+        assert_eq!(target.resolve(), Some(ptr[0]));
+
+        // the object of a stale chain was freed
+        let null = [0usize];
+        let stale = Target::Pointer {
+            base: null.as_ptr() as usize,
+            offsets: vec![0],
+        };
+        assert_eq!(stale.resolve(), None);
+    }
+}
@@ -0,0 +1,358 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::error;
+use mlua::prelude::*;
+use mlua::UserData;
+use tokio::runtime::Handle;
+
+use super::target::Target;
+use super::{is_readable, TypeName};
+use crate::luavm::libs::print::target;
+use crate::luavm::WeakLuaVM;
+
+pub const DEFAULT_INTERVAL_MS: u64 = 100;
+/// upper bound of the sampler sleep
+const MAX_SLEEP: Duration = Duration::from_millis(500);
+
+static NEXT_WATCH_ID: AtomicU64 = AtomicU64::new(1);
+
+struct Watch {
+    id: u64,
+    target: Target,
+    type_name: TypeName,
+    interval: Duration,
+    /// floats differing less than epsilon are considered unchanged
+    epsilon: Option<f64>,
+    next_due: Instant,
+    /// last sampled bytes, `None` if the target could not be resolved
+    last: Option<Vec<u8>>,
+    sampled: bool,
+    callback: LuaRegistryKey,
+}
+
+impl Watch {
+    /// Current bytes of the target, `None` if it doesn't resolve or isn't
+    /// readable, like a chain to a freed object.
+    fn sample(&self) -> Option<Vec<u8>> {
+        let addr = self.target.resolve()?;
+        let size = self.type_name.size()?;
+        if !is_readable(addr, size) {
+            return None;
+        }
+        Some(unsafe { std::slice::from_raw_parts(addr as *const u8, size) }.to_vec())
+    }
+
+    fn changed(&self, new: &Option<Vec<u8>>) -> bool {
+        match (&self.last, new) {
+            (Some(old), Some(new)) => match (self.epsilon, self.type_name) {
+                (Some(epsilon), TypeName::F32 | TypeName::F64) => {
+                    let old = self.type_name.decode(old).as_f64().unwrap_or_default();
+                    let new = self.type_name.decode(new).as_f64().unwrap_or_default();
+                    (old - new).abs() > epsilon
+                }
+                _ => old != new,
+            },
+            (None, None) => false,
+            _ => true,
+        }
+    }
+
+    /// Record a new sample, returns the change to report.
+    fn update(&mut self, new: Option<Vec<u8>>) -> Option<Change> {
+        let change = (self.sampled && self.changed(&new)).then(|| Change {
+            id: self.id,
+            old: self.last.clone(),
+            new: new.clone(),
+        });
+        // keep the last reported value while within epsilon, so that slow drift adds up
+        if change.is_some() || !self.sampled || self.epsilon.is_none() {
+            self.last = new;
+        }
+        self.sampled = true;
+        change
+    }
+}
+
+/// A value change found by the sampler.
+struct Change {
+    id: u64,
+    old: Option<Vec<u8>>,
+    new: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct WatchGroupInner {
+    watches: Vec<Watch>,
+    sampling: bool,
+}
+
+/// Watches of a Lua VM, stored as app data of the VM.
+#[derive(Clone, Default)]
+pub struct WatchGroup(Arc<Mutex<WatchGroupInner>>);
+
+impl WatchGroup {
+    /// Sample all due watches without entering Lua.
+    ///
+    /// Returns the changes and the time until the next sample,
+    /// or `None` (and stops sampling) if there is nothing left to watch.
+    fn sample(&self) -> Option<(Vec<Change>, Duration)> {
+        let mut inner = self.0.lock().unwrap();
+        if inner.watches.is_empty() {
+            inner.sampling = false;
+            return None;
+        }
+        let now = Instant::now();
+        let mut next_due = now + MAX_SLEEP;
+        let mut changes = Vec::new();
+        for watch in inner.watches.iter_mut() {
+            if watch.next_due <= now {
+                let new = watch.sample();
+                changes.extend(watch.update(new));
+                watch.next_due = now + watch.interval;
+            }
+            next_due = next_due.min(watch.next_due);
+        }
+
+        Some((changes, next_due.saturating_duration_since(Instant::now())))
+    }
+
+    /// Call the callbacks of a batch of changes, holding the VM lock once.
+    async fn dispatch(&self, luavm: &WeakLuaVM, changes: Vec<Change>) {
+        let Some(luavm) = luavm.upgrade() else {
+            return;
+        };
+        let luavm = luavm.lock().await;
+        if !luavm.is_running() {
+            return;
+        }
+        let lua = &luavm.lua;
+        for change in changes {
+            let found = {
+                let inner = self.0.lock().unwrap();
+                inner
+                    .watches
+                    .iter()
+                    .find(|watch| watch.id == change.id)
+                    .map(|watch| {
+                        (
+                            watch.type_name,
+                            lua.registry_value::<LuaFunction>(&watch.callback),
+                        )
+                    })
+            };
+            // removed by a previous callback of this batch
+            let Some((type_name, f)) = found else {
+                continue;
+            };
+            let old = change.old.map(|b| type_name.decode(&b)).unwrap_or(LuaNil);
+            let new = change.new.map(|b| type_name.decode(&b)).unwrap_or(LuaNil);
+            let result = match f {
+                Ok(f) => f.call_async::<_, ()>((old, new)).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!(target: &target(lua), "Error in watch callback: {}", e);
+            }
+        }
+    }
+
+    fn remove(&self, id: u64) -> bool {
+        let mut inner = self.0.lock().unwrap();
+        let len = inner.watches.len();
+        inner.watches.retain(|watch| watch.id != id);
+        len != inner.watches.len()
+    }
+
+    fn contains(&self, id: u64) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .watches
+            .iter()
+            .any(|watch| watch.id == id)
+    }
+
+    pub fn clear(&self) -> usize {
+        let mut inner = self.0.lock().unwrap();
+        let len = inner.watches.len();
+        inner.watches.clear();
+        len
+    }
+}
+
+fn start_sampler(group: WatchGroup, luavm: WeakLuaVM, handle: Handle) {
+    thread::spawn(move || {
+        handle.block_on(async {
+            while luavm.strong_count() > 0 {
+                let Some((changes, sleep)) = group.sample() else {
+                    return;
+                };
+                if !changes.is_empty() {
+                    group.dispatch(&luavm, changes).await;
+                }
+                tokio::time::sleep(sleep).await;
+            }
+            group.0.lock().unwrap().sampling = false;
+        })
+    });
+}
+
+/// Lua handle of a watch
+pub struct WatchHandle {
+    id: u64,
+    group: WatchGroup,
+}
+
+impl WatchHandle {
+    pub fn unwatch(&self) -> bool {
+        self.group.remove(self.id)
+    }
+}
+
+impl UserData for WatchHandle {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_, this| Ok(this.id));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("unwatch", |_, this, ()| Ok(this.unwatch()));
+        methods.add_method("isActive", |_, this, ()| Ok(this.group.contains(this.id)));
+    }
+}
+
+/// `Memory.watch(target, typeName, callback, opts)`
+///
+/// opts: `{ interval = ms, epsilon = number }`
+pub fn watch(
+    lua: &Lua,
+    target: LuaValue,
+    type_name: TypeName,
+    callback: LuaFunction,
+    opts: Option<LuaTable>,
+) -> LuaResult<WatchHandle> {
+    let target = Target::from_lua(&target)?;
+    if type_name.size().is_none() {
+        return Err(LuaError::runtime(format!(
+            "typeName `{}` can not be watched",
+            type_name.as_str()
+        )));
+    }
+    let (interval, epsilon) = match opts {
+        Some(opts) => (
+            opts.get::<_, Option<u64>>("interval")?,
+            opts.get::<_, Option<f64>>("epsilon")?,
+        ),
+        None => (None, None),
+    };
+    let luavm = lua
+        .app_data_ref::<WeakLuaVM>()
+        .map(|luavm| luavm.clone())
+        .ok_or(LuaError::runtime("Unknown Lua VM"))?;
+    let handle = Handle::try_current().map_err(|e| LuaError::runtime(e.to_string()))?;
+    if lua.app_data_ref::<WatchGroup>().is_none() {
+        lua.set_app_data(WatchGroup::default());
+    }
+    let group = lua.app_data_ref::<WatchGroup>().unwrap().clone();
+
+    let id = NEXT_WATCH_ID.fetch_add(1, Ordering::Relaxed);
+    let start = {
+        let mut inner = group.0.lock().unwrap();
+        inner.watches.push(Watch {
+            id,
+            target,
+            type_name,
+            interval: Duration::from_millis(interval.unwrap_or(DEFAULT_INTERVAL_MS).max(1)),
+            epsilon,
+            next_due: Instant::now(),
+            last: None,
+            sampled: false,
+            callback: lua.create_registry_value(callback)?,
+        });
+        !std::mem::replace(&mut inner.sampling, true)
+    };
+    if start {
+        start_sampler(group.clone(), luavm, handle);
+    }
+
+    Ok(WatchHandle { id, group })
+}
+
+/// Remove all watches of the VM of `lua`.
+pub fn clear(lua: &Lua) -> usize {
+    lua.app_data_ref::<WatchGroup>()
+        .map(|group| group.clear())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_watch(lua: &Lua, type_name: TypeName, epsilon: Option<f64>, last: &[u8]) -> Watch {
+        Watch {
+            id: 0,
+            target: Target::Address(0),
+            type_name,
+            interval: Duration::from_millis(DEFAULT_INTERVAL_MS),
+            epsilon,
+            next_due: Instant::now(),
+            last: Some(last.to_vec()),
+            sampled: true,
+            callback: lua
+                .create_registry_value(lua.create_function(|_, ()| Ok(())).unwrap())
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_changed_epsilon() {
+        let lua = Lua::new();
+        let watch = new_watch(&lua, TypeName::F32, Some(0.5), &1.0f32.to_ne_bytes());
+        assert!(!watch.changed(&Some(1.25f32.to_ne_bytes().to_vec())));
+        assert!(watch.changed(&Some(1.75f32.to_ne_bytes().to_vec())));
+        assert!(watch.changed(&None));
+    }
+
+    #[test]
+    fn test_changed_exact() {
+        let lua = Lua::new();
+        let watch = new_watch(&lua, TypeName::I32, Some(10.0), &5i32.to_ne_bytes());
+        assert!(!watch.changed(&Some(5i32.to_ne_bytes().to_vec())));
+        assert!(watch.changed(&Some(6i32.to_ne_bytes().to_vec())));
+    }
+
+    #[test]
+    fn test_sample_stale_chain() {
+        let lua = Lua::new();
+        let value = 5i32;
+        let mut slot = [&value as *const i32 as usize];
+        let mut watch = new_watch(&lua, TypeName::I32, None, &5i32.to_ne_bytes());
+        watch.target = Target::Pointer {
+            base: slot.as_ptr() as usize,
+            offsets: vec![0],
+        };
+        assert_eq!(watch.sample(), Some(5i32.to_ne_bytes().to_vec()));
+
+        // the object was freed
+        slot[0] = 0;
+        let change = watch.update(watch.sample()).unwrap();
+        assert_eq!(change.new, None);
+    }
+
+    #[test]
+    fn test_update_slow_drift() {
+        let lua = Lua::new();
+        let mut watch = new_watch(&lua, TypeName::F32, Some(0.5), &1.0f32.to_ne_bytes());
+        let sample = |v: f32| Some(v.to_ne_bytes().to_vec());
+        assert!(watch.update(sample(1.2)).is_none());
+        assert!(watch.update(sample(1.4)).is_none());
+        let change = watch.update(sample(1.6)).unwrap();
+        assert_eq!(change.old, sample(1.0));
+        assert_eq!(change.new, sample(1.6));
+        assert!(watch.update(sample(1.8)).is_none());
+        assert_eq!(watch.last, sample(1.6));
+    }
+}
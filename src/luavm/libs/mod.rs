@@ -1,5 +1,8 @@
 mod game;
+mod hook;
+mod logging;
 mod memory;
+mod native;
 mod plugin;
 mod print;
 mod util;
@@ -8,16 +11,27 @@ use mlua::prelude::*;
 
 use super::WeakLuaVM;
 
+pub use game::{
+    init_game_events, install_hooks, is_input_hooked, load_layout, show_engine_message,
+    show_script_error,
+};
+pub use memory::{list_freezes, FreezeEntry};
+pub use plugin::{commands, event, usage as vm_usage, VmUsage};
+pub use print::display_value;
+
 pub async fn load_libs(luavm: WeakLuaVM) -> LuaResult<()> {
     let luavm_ = luavm.upgrade().unwrap();
     let lua_ = &luavm_.lock().await.lua;
     let globals = lua_.globals();
+    // for libs which call back into the VM from other threads
+    lua_.set_app_data(luavm.clone());
     // override
     globals.set("Print", lua_.create_function(print::fn_info)?)?;
     globals.set("Info", lua_.create_function(print::fn_info)?)?;
     globals.set("Debug", lua_.create_function(print::fn_debug)?)?;
     globals.set("Warn", lua_.create_function(print::fn_warn)?)?;
     globals.set("Error", lua_.create_function(print::fn_error)?)?;
+    globals.set("Log", lua_.create_userdata(logging::Log)?)?;
     {
         let package: LuaTable = globals.get("package")?;
         let path: String = package.get("path")?;
@@ -27,11 +41,24 @@ pub async fn load_libs(luavm: WeakLuaVM) -> LuaResult<()> {
 
     // plugin system
     let module_plugin = plugin::Plugin::new(luavm.clone());
+    lua_.set_app_data(module_plugin.clone());
     globals.set("Plugin", lua_.create_userdata(module_plugin.clone())?)?;
     // memory
     globals.set("Memory", lua_.create_userdata(memory::Memory)?)?;
+    memory::init_journal(lua_);
+    // native calls
+    globals.set("Native", lua_.create_userdata(native::Native)?)?;
+    globals.set("Hook", lua_.create_userdata(hook::Hook)?)?;
     // game
     globals.set("Game", lua_.create_userdata(game::Game)?)?;
 
     Ok(())
 }
+
+/// Stop phase of the libs, called before a VM is unloaded or reloaded.
+pub fn unload_libs(lua: &Lua) {
+    plugin::on_stop(lua);
+    hook::remove_all(lua);
+    memory::on_stop(lua);
+    game::on_stop(lua);
+}
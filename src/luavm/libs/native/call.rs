@@ -0,0 +1,355 @@
+//! Calls of native functions with a signature only known at runtime.
+//!
+//! Every argument is either an integer (passed in a general purpose register)
+//! or a float (passed in an SSE register), so it is enough to call the target
+//! through a fixed set of function pointer types covering all register
+//! layouts, passing 64-bit integers and doubles. `f32` values are carried in
+//! the low 32 bits of a double, which is where the callee reads them.
+
+use snafu::prelude::*;
+
+/// Register class of an argument or return value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Int,
+    Float,
+}
+
+/// x64 calling convention of the called function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    /// Microsoft x64, used by the game
+    Win64,
+    /// System V AMD64, `extern "C"` on Linux
+    SysV,
+}
+
+impl Abi {
+    /// Convention of `extern "C"` functions on this platform.
+    pub const NATIVE: Abi = if cfg!(windows) { Abi::Win64 } else { Abi::SysV };
+}
+
+/// Maximum number of arguments of Win64 calls (4 registers + 8 stack slots).
+pub const WIN64_MAX_ARGS: usize = 12;
+/// Maximum integer arguments of System V calls (register only).
+pub const SYSV_MAX_INTS: usize = 6;
+/// Maximum float arguments of System V calls (register only).
+pub const SYSV_MAX_FLOATS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Snafu)]
+pub enum CallError {
+    #[snafu(display("Too many arguments: {} (at most {})", count, max))]
+    TooManyArgs { count: usize, max: usize },
+    #[snafu(display("Too many {} arguments: {} (at most {})", kind, count, max))]
+    TooManyClassArgs {
+        kind: &'static str,
+        count: usize,
+        max: usize,
+    },
+}
+
+/// Check that arguments of the given classes can be passed with `abi`.
+pub fn check(abi: Abi, args: &[Class]) -> Result<(), CallError> {
+    match abi {
+        Abi::Win64 => ensure!(
+            args.len() <= WIN64_MAX_ARGS,
+            TooManyArgsSnafu {
+                count: args.len(),
+                max: WIN64_MAX_ARGS
+            }
+        ),
+        Abi::SysV => {
+            let floats = args.iter().filter(|&&c| c == Class::Float).count();
+            let ints = args.len() - floats;
+            ensure!(
+                ints <= SYSV_MAX_INTS,
+                TooManyClassArgsSnafu {
+                    kind: "integer",
+                    count: ints,
+                    max: SYSV_MAX_INTS
+                }
+            );
+            ensure!(
+                floats <= SYSV_MAX_FLOATS,
+                TooManyClassArgsSnafu {
+                    kind: "float",
+                    count: floats,
+                    max: SYSV_MAX_FLOATS
+                }
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Call the function at `addr` with raw argument bits, returning the raw bits
+/// of the return register (`rax` or `xmm0`).
+///
+/// # Safety
+///
+/// `addr` must be a function of `abi` taking arguments of the given classes,
+/// which must pass [`check`].
+pub unsafe fn call(abi: Abi, addr: usize, args: &[(Class, u64)], ret: Class) -> u64 {
+    match abi {
+        Abi::Win64 => {
+            let mut classes = [Class::Int; 4];
+            let mut regs = [0u64; 4];
+            let mut stack = [0u64; WIN64_MAX_ARGS - 4];
+            for (i, &(class, bits)) in args.iter().enumerate() {
+                if i < 4 {
+                    classes[i] = class;
+                    regs[i] = bits;
+                } else {
+                    // stack slots hold the bits of both classes
+                    stack[i - 4] = bits;
+                }
+            }
+            win64_0(addr, classes, regs, stack, ret)
+        }
+        Abi::SysV => {
+            let mut ints = [0u64; SYSV_MAX_INTS];
+            let mut floats = [0u64; SYSV_MAX_FLOATS];
+            let (mut n_int, mut n_float) = (0, 0);
+            for &(class, bits) in args {
+                match class {
+                    Class::Int => {
+                        ints[n_int] = bits;
+                        n_int += 1;
+                    }
+                    Class::Float => {
+                        floats[n_float] = bits;
+                        n_float += 1;
+                    }
+                }
+            }
+            match ret {
+                Class::Int => sysv::<u64>(addr, ints, floats),
+                Class::Float => sysv::<f64>(addr, ints, floats),
+            }
+        }
+    }
+}
+
+trait Reg: Copy {
+    fn from_bits(bits: u64) -> Self;
+    fn to_bits(self) -> u64;
+}
+
+impl Reg for u64 {
+    fn from_bits(bits: u64) -> Self {
+        bits
+    }
+
+    fn to_bits(self) -> u64 {
+        self
+    }
+}
+
+impl Reg for f64 {
+    fn from_bits(bits: u64) -> Self {
+        f64::from_bits(bits)
+    }
+
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
+}
+
+type Stack = [u64; WIN64_MAX_ARGS - 4];
+
+unsafe fn win64_0(
+    addr: usize,
+    classes: [Class; 4],
+    regs: [u64; 4],
+    stack: Stack,
+    ret: Class,
+) -> u64 {
+    match classes[0] {
+        Class::Int => win64_1::<u64>(addr, classes, regs, stack, ret),
+        Class::Float => win64_1::<f64>(addr, classes, regs, stack, ret),
+    }
+}
+
+unsafe fn win64_1<A: Reg>(
+    addr: usize,
+    classes: [Class; 4],
+    regs: [u64; 4],
+    stack: Stack,
+    ret: Class,
+) -> u64 {
+    match classes[1] {
+        Class::Int => win64_2::<A, u64>(addr, classes, regs, stack, ret),
+        Class::Float => win64_2::<A, f64>(addr, classes, regs, stack, ret),
+    }
+}
+
+unsafe fn win64_2<A: Reg, B: Reg>(
+    addr: usize,
+    classes: [Class; 4],
+    regs: [u64; 4],
+    stack: Stack,
+    ret: Class,
+) -> u64 {
+    match classes[2] {
+        Class::Int => win64_3::<A, B, u64>(addr, classes, regs, stack, ret),
+        Class::Float => win64_3::<A, B, f64>(addr, classes, regs, stack, ret),
+    }
+}
+
+unsafe fn win64_3<A: Reg, B: Reg, C: Reg>(
+    addr: usize,
+    classes: [Class; 4],
+    regs: [u64; 4],
+    stack: Stack,
+    ret: Class,
+) -> u64 {
+    match (classes[3], ret) {
+        (Class::Int, Class::Int) => win64::<A, B, C, u64, u64>(addr, regs, stack),
+        (Class::Int, Class::Float) => win64::<A, B, C, u64, f64>(addr, regs, stack),
+        (Class::Float, Class::Int) => win64::<A, B, C, f64, u64>(addr, regs, stack),
+        (Class::Float, Class::Float) => win64::<A, B, C, f64, f64>(addr, regs, stack),
+    }
+}
+
+type Win64Fn<A, B, C, D, R> =
+    extern "win64" fn(A, B, C, D, u64, u64, u64, u64, u64, u64, u64, u64) -> R;
+
+unsafe fn win64<A: Reg, B: Reg, C: Reg, D: Reg, R: Reg>(
+    addr: usize,
+    regs: [u64; 4],
+    stack: Stack,
+) -> u64 {
+    let f: Win64Fn<A, B, C, D, R> = std::mem::transmute(addr);
+    f(
+        A::from_bits(regs[0]),
+        B::from_bits(regs[1]),
+        C::from_bits(regs[2]),
+        D::from_bits(regs[3]),
+        stack[0],
+        stack[1],
+        stack[2],
+        stack[3],
+        stack[4],
+        stack[5],
+        stack[6],
+        stack[7],
+    )
+    .to_bits()
+}
+
+type SysVFn<R> =
+    extern "sysv64" fn(u64, u64, u64, u64, u64, u64, f64, f64, f64, f64, f64, f64, f64, f64) -> R;
+
+unsafe fn sysv<R: Reg>(
+    addr: usize,
+    ints: [u64; SYSV_MAX_INTS],
+    floats: [u64; SYSV_MAX_FLOATS],
+) -> u64 {
+    let f: SysVFn<R> = std::mem::transmute(addr);
+    let x = floats.map(f64::from_bits);
+    f(
+        ints[0], ints[1], ints[2], ints[3], ints[4], ints[5], x[0], x[1], x[2], x[3], x[4], x[5],
+        x[6], x[7],
+    )
+    .to_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn mixed(a: i32, b: f32, c: i64, d: f64, p: *const u8) -> f64 {
+        a as f64 + b as f64 + c as f64 + d + unsafe { *p } as f64
+    }
+
+    extern "C" fn ret_f32(a: f32, b: f32) -> f32 {
+        a * b
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    extern "win64" fn win64_mixed(a: f32, b: i32, c: f64, d: u8, e: i64, f: f32) -> i64 {
+        (a as f64 * 1000.0) as i64 + b as i64 + (c * 10.0) as i64 + d as i64 + e + f as i64
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    extern "win64" fn win64_ret_f32(a: i32, b: f32) -> f32 {
+        a as f32 + b
+    }
+
+    fn f32_bits(v: f32) -> u64 {
+        v.to_bits() as u64
+    }
+
+    #[test]
+    fn test_call_native() {
+        let byte = 7u8;
+        let args = [
+            (Class::Int, (-1i32) as i64 as u64),
+            (Class::Float, f32_bits(2.5)),
+            (Class::Int, 10),
+            (Class::Float, 0.25f64.to_bits()),
+            (Class::Int, &byte as *const u8 as u64),
+        ];
+        let ret = unsafe {
+            call(
+                Abi::NATIVE,
+                mixed as *const () as usize,
+                &args,
+                Class::Float,
+            )
+        };
+        assert_eq!(f64::from_bits(ret), -1.0 + 2.5 + 10.0 + 0.25 + 7.0);
+
+        let args = [(Class::Float, f32_bits(1.5)), (Class::Float, f32_bits(4.0))];
+        let ret = unsafe {
+            call(
+                Abi::NATIVE,
+                ret_f32 as *const () as usize,
+                &args,
+                Class::Float,
+            )
+        };
+        assert_eq!(f32::from_bits(ret as u32), 6.0);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_call_win64() {
+        let args = [
+            (Class::Float, f32_bits(1.5)),
+            (Class::Int, (-2i32) as i64 as u64),
+            (Class::Float, 3.0f64.to_bits()),
+            (Class::Int, 4),
+            (Class::Int, 50),
+            (Class::Float, f32_bits(600.0)),
+        ];
+        let ret = unsafe {
+            call(
+                Abi::Win64,
+                win64_mixed as *const () as usize,
+                &args,
+                Class::Int,
+            )
+        };
+        assert_eq!(ret as i64, 1500 - 2 + 30 + 4 + 50 + 600);
+
+        let args = [(Class::Int, 2), (Class::Float, f32_bits(0.5))];
+        let ret = unsafe {
+            call(
+                Abi::Win64,
+                win64_ret_f32 as *const () as usize,
+                &args,
+                Class::Float,
+            )
+        };
+        assert_eq!(f32::from_bits(ret as u32), 2.5);
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check(Abi::Win64, &[Class::Int; 12]).is_ok());
+        assert!(check(Abi::Win64, &[Class::Float; 13]).is_err());
+        assert!(check(Abi::SysV, &[[Class::Int; 6], [Class::Float; 6]].concat()).is_ok());
+        assert!(check(Abi::SysV, &[Class::Int; 7]).is_err());
+    }
+}
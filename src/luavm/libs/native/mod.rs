@@ -0,0 +1,308 @@
+pub mod call;
+mod signature;
+
+use mlua::prelude::*;
+use mlua::UserData;
+
+use super::hook;
+use super::memory::{resolve_target, Buffer, RawPtr};
+
+pub use call::{Abi, Class};
+pub use signature::{NativeType, Signature};
+
+pub struct Native;
+
+impl UserData for Native {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_function("func", |_, (target, signature): (LuaValue, String)| {
+            NativeFunction::new(resolve_target(&target)?, &signature, Abi::NATIVE)
+        });
+    }
+}
+
+/// A native function callable from Lua.
+#[derive(Debug, Clone)]
+pub struct NativeFunction {
+    addr: usize,
+    signature: Signature,
+    abi: Abi,
+}
+
+impl NativeFunction {
+    pub fn new(addr: usize, signature: &str, abi: Abi) -> LuaResult<NativeFunction> {
+        if addr == 0 {
+            return Err(LuaError::runtime("Native function address is null"));
+        }
+        let signature =
+            Signature::parse(signature).map_err(|e| LuaError::runtime(e.to_string()))?;
+        call::check(abi, &signature.arg_classes()).map_err(|e| LuaError::runtime(e.to_string()))?;
+        Ok(NativeFunction {
+            addr,
+            signature,
+            abi,
+        })
+    }
+
+    /// Marshal `args`, call the function and convert its return value.
+    ///
+    /// The call runs on the current thread, bypassing hooks of the function,
+    /// whose callbacks would need the VM of the caller.
+    pub fn call<'lua>(
+        &self,
+        lua: &'lua Lua,
+        args: LuaMultiValue<'lua>,
+    ) -> LuaResult<LuaValue<'lua>> {
+        let args = Marshalled::new(&self.signature.args, args)?;
+        let bits = hook::passthrough(|| unsafe {
+            call::call(self.abi, self.addr, &args.bits, self.signature.ret.class())
+        });
+        drop(args);
+        from_native(lua, self.signature.ret, bits)
+    }
+}
+
+impl UserData for NativeFunction {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("address", |_, this| Ok(this.addr));
+        fields.add_field_method_get("signature", |_, this| Ok(this.signature.to_string()));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("call", |lua, this, args: LuaMultiValue| {
+            this.call(lua, args)
+        });
+        methods.add_meta_method(LuaMetaMethod::Call, |lua, this, args: LuaMultiValue| {
+            this.call(lua, args)
+        });
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, ()| {
+            Ok(format!(
+                "NativeFunction(0x{:x}: {})",
+                this.addr, this.signature
+            ))
+        });
+    }
+}
+
+/// Raw arguments of a call, keeping the memory of pointer arguments
+/// (strings and buffers) alive until the call returns.
+pub struct Marshalled<'lua> {
+    pub bits: Vec<(Class, u64)>,
+    strings: Vec<LuaString<'lua>>,
+    buffers: Vec<LuaAnyUserData<'lua>>,
+}
+
+impl<'lua> Marshalled<'lua> {
+    pub fn new(types: &[NativeType], args: LuaMultiValue<'lua>) -> LuaResult<Marshalled<'lua>> {
+        if args.len() != types.len() {
+            return Err(LuaError::runtime(format!(
+                "Expect {} arguments, got {}",
+                types.len(),
+                args.len()
+            )));
+        }
+        let mut marshalled = Marshalled {
+            bits: Vec::with_capacity(types.len()),
+            strings: Vec::new(),
+            buffers: Vec::new(),
+        };
+        for (i, (&ty, value)) in types.iter().zip(args).enumerate() {
+            let bits = marshalled.marshal(ty, value).map_err(|e| {
+                LuaError::runtime(format!("Bad argument #{} ({}): {}", i + 1, ty.as_str(), e))
+            })?;
+            marshalled.bits.push((ty.class(), bits));
+        }
+        Ok(marshalled)
+    }
+
+    /// Whether an argument points into Lua owned memory.
+    pub fn borrows_memory(&self) -> bool {
+        !self.strings.is_empty() || !self.buffers.is_empty()
+    }
+
+    fn marshal(&mut self, ty: NativeType, value: LuaValue<'lua>) -> LuaResult<u64> {
+        let type_error = |value: &LuaValue| {
+            Err(LuaError::runtime(format!(
+                "unexpected {}",
+                value.type_name()
+            )))
+        };
+        match ty {
+            NativeType::Void => unreachable!("void arguments are rejected by the parser"),
+            NativeType::Bool => match value {
+                LuaValue::Boolean(b) => Ok(b as u64),
+                LuaValue::Nil => Ok(0),
+                value => type_error(&value),
+            },
+            NativeType::F32 => match value {
+                LuaValue::Integer(i) => Ok((i as f32).to_bits() as u64),
+                LuaValue::Number(n) => Ok((n as f32).to_bits() as u64),
+                value => type_error(&value),
+            },
+            NativeType::F64 => match value {
+                LuaValue::Integer(i) => Ok((i as f64).to_bits()),
+                LuaValue::Number(n) => Ok(n.to_bits()),
+                value => type_error(&value),
+            },
+            NativeType::Ptr | NativeType::Str => self.pointer(value),
+            // sign or zero extended to 64 bits, the callee reads the low bits
+            _ => match value {
+                LuaValue::Integer(i) => Ok(i as u64),
+                LuaValue::Number(n) if n.fract() == 0.0 => Ok(n as i64 as u64),
+                LuaValue::Boolean(b) => Ok(b as u64),
+                value => type_error(&value),
+            },
+        }
+    }
+
+    fn pointer(&mut self, value: LuaValue<'lua>) -> LuaResult<u64> {
+        match value {
+            LuaValue::Nil => Ok(0),
+            LuaValue::Integer(i) => Ok(i as u64),
+            LuaValue::LightUserData(p) => Ok(p.0 as u64),
+            LuaValue::String(s) => {
+                // Lua strings are NUL-terminated
+                let addr = s.as_bytes_with_nul().as_ptr() as u64;
+                self.strings.push(s);
+                Ok(addr)
+            }
+            LuaValue::UserData(ud) => {
+                if let Ok(ptr) = ud.borrow::<RawPtr>() {
+                    return ptr.address().map(|addr| addr as u64).ok_or_else(|| {
+                        LuaError::runtime(format!("Failed to resolve pointer: {}", ptr.resolve()))
+                    });
+                }
+                let addr = ud.borrow_mut::<Buffer>()?.0.as_mut_ptr() as u64;
+                self.buffers.push(ud);
+                Ok(addr)
+            }
+            value => Err(LuaError::runtime(format!(
+                "unexpected {}",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+/// Convert the raw bits of a return register.
+pub fn from_native<'lua>(lua: &'lua Lua, ty: NativeType, bits: u64) -> LuaResult<LuaValue<'lua>> {
+    Ok(match ty {
+        NativeType::Void => LuaNil,
+        NativeType::Bool => LuaValue::Boolean(bits as u8 != 0),
+        NativeType::I8 => LuaValue::Integer(bits as i8 as i64),
+        NativeType::I16 => LuaValue::Integer(bits as i16 as i64),
+        NativeType::I32 => LuaValue::Integer(bits as i32 as i64),
+        NativeType::I64 | NativeType::U64 | NativeType::Ptr => LuaValue::Integer(bits as i64),
+        NativeType::U8 => LuaValue::Integer(bits as u8 as i64),
+        NativeType::U16 => LuaValue::Integer(bits as u16 as i64),
+        NativeType::U32 => LuaValue::Integer(bits as u32 as i64),
+        NativeType::F32 => LuaValue::Number(f32::from_bits(bits as u32) as f64),
+        NativeType::F64 => LuaValue::Number(f64::from_bits(bits)),
+        NativeType::Str if bits == 0 => LuaNil,
+        NativeType::Str => {
+            let s = unsafe { std::ffi::CStr::from_ptr(bits as *const std::ffi::c_char) };
+            LuaValue::String(lua.create_string(s.to_bytes())?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn sum(a: i32, b: f32, c: i64) -> f64 {
+        a as f64 + b as f64 + c as f64
+    }
+
+    extern "C" fn fill(buf: *mut u8, len: u64, value: u8) -> u64 {
+        unsafe { std::slice::from_raw_parts_mut(buf, len as usize).fill(value) };
+        len
+    }
+
+    extern "C" fn c_strlen(s: *const std::ffi::c_char) -> u64 {
+        if s.is_null() {
+            return u64::MAX;
+        }
+        unsafe { std::ffi::CStr::from_ptr(s).to_bytes().len() as u64 }
+    }
+
+    extern "C" fn greet() -> *const std::ffi::c_char {
+        c"hello".as_ptr()
+    }
+
+    extern "C" fn negate(v: i32) -> i32 {
+        -v
+    }
+
+    fn native(lua: &Lua, name: &str, addr: usize, signature: &str) {
+        let f = NativeFunction::new(addr, signature, Abi::NATIVE).unwrap();
+        lua.globals().set(name, f).unwrap();
+    }
+
+    #[test]
+    fn test_call_from_lua() {
+        let lua = Lua::new();
+        lua.globals()
+            .set("Memory", super::super::memory::Memory)
+            .unwrap();
+        native(&lua, "sum", sum as *const () as usize, "f64(i32, f32, i64)");
+        native(
+            &lua,
+            "fill",
+            fill as *const () as usize,
+            "u64(ptr, u64, u8)",
+        );
+        native(&lua, "strlen", c_strlen as *const () as usize, "u64(str)");
+        native(&lua, "greet", greet as *const () as usize, "str()");
+        native(&lua, "negate", negate as *const () as usize, "i32(i32)");
+
+        lua.load(
+            r#"
+            assert(sum(1, 2.5, 3) == 6.5)
+            assert(negate(5) == -5)
+
+            local buf = Memory.newBuffer(4)
+            assert(fill(buf, 4, 0xAB) == 4)
+            assert(buf:get(0, "i32") == -0x54545455)
+
+            assert(strlen("four") == 4)
+            assert(strlen(nil) == -1)
+            assert(greet() == "hello")
+            assert(tostring(negate):find("i32%(i32%)"))
+            "#,
+        )
+        .exec()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_marshal_raw_ptr() {
+        let lua = Lua::new();
+        let value = 42u8;
+        let mut ptr = RawPtr::new();
+        ptr.set_base(&value as *const u8 as usize);
+        let args =
+            LuaMultiValue::from_vec(vec![LuaValue::UserData(lua.create_userdata(ptr).unwrap())]);
+        let marshalled = Marshalled::new(&[NativeType::Ptr], args).unwrap();
+        assert_eq!(
+            marshalled.bits,
+            vec![(Class::Int, &value as *const u8 as u64)]
+        );
+    }
+
+    #[test]
+    fn test_marshal_errors() {
+        let lua = Lua::new();
+        let f = NativeFunction::new(negate as *const () as usize, "i32(i32)", Abi::NATIVE).unwrap();
+        let err = f
+            .call(&lua, LuaMultiValue::from_vec(vec![]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Expect 1 arguments, got 0"));
+        let args = LuaMultiValue::from_vec(vec![LuaValue::Boolean(true)]);
+        assert!(Marshalled::new(&[NativeType::F32], args).is_err());
+        assert!(
+            NativeFunction::new(negate as *const () as usize, "i32(int)", Abi::NATIVE).is_err()
+        );
+        assert!(NativeFunction::new(0, "i32(i32)", Abi::NATIVE).is_err());
+    }
+}
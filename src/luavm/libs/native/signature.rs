@@ -0,0 +1,186 @@
+use std::fmt;
+
+use snafu::prelude::*;
+
+use super::call::Class;
+
+#[derive(Debug, Clone, PartialEq, Eq, Snafu)]
+pub enum SignatureError {
+    #[snafu(display("Invalid signature `{}`, expect `ret(arg, ...)`", signature))]
+    Syntax { signature: String },
+    #[snafu(display("Unknown type `{}` in signature `{}`", type_name, signature))]
+    UnknownType {
+        type_name: String,
+        signature: String,
+    },
+    #[snafu(display("`void` is only allowed as return type in signature `{}`", signature))]
+    VoidArgument { signature: String },
+}
+
+/// Type of a native argument or return value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeType {
+    Void,
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    /// any pointer, passed as an address
+    Ptr,
+    /// NUL-terminated C string
+    Str,
+}
+
+impl NativeType {
+    pub fn from_str(type_name: &str) -> Option<NativeType> {
+        match type_name {
+            "void" => Some(NativeType::Void),
+            "bool" => Some(NativeType::Bool),
+            "i8" => Some(NativeType::I8),
+            "i16" => Some(NativeType::I16),
+            "i32" => Some(NativeType::I32),
+            "i64" => Some(NativeType::I64),
+            "u8" => Some(NativeType::U8),
+            "u16" => Some(NativeType::U16),
+            "u32" => Some(NativeType::U32),
+            "u64" => Some(NativeType::U64),
+            "f32" => Some(NativeType::F32),
+            "f64" => Some(NativeType::F64),
+            "ptr" => Some(NativeType::Ptr),
+            "str" => Some(NativeType::Str),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NativeType::Void => "void",
+            NativeType::Bool => "bool",
+            NativeType::I8 => "i8",
+            NativeType::I16 => "i16",
+            NativeType::I32 => "i32",
+            NativeType::I64 => "i64",
+            NativeType::U8 => "u8",
+            NativeType::U16 => "u16",
+            NativeType::U32 => "u32",
+            NativeType::U64 => "u64",
+            NativeType::F32 => "f32",
+            NativeType::F64 => "f64",
+            NativeType::Ptr => "ptr",
+            NativeType::Str => "str",
+        }
+    }
+
+    /// Register class the value is passed in.
+    pub fn class(&self) -> Class {
+        match self {
+            NativeType::F32 | NativeType::F64 => Class::Float,
+            _ => Class::Int,
+        }
+    }
+}
+
+/// Parsed signature like `i32(ptr, f32, i64)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub ret: NativeType,
+    pub args: Vec<NativeType>,
+}
+
+impl Signature {
+    pub fn parse(signature: &str) -> Result<Signature, SignatureError> {
+        let syntax = || SignatureError::Syntax {
+            signature: signature.to_string(),
+        };
+        let parse_type = |type_name: &str| {
+            NativeType::from_str(type_name).context(UnknownTypeSnafu {
+                type_name,
+                signature,
+            })
+        };
+
+        let (ret, rest) = signature.split_once('(').ok_or_else(syntax)?;
+        let args = rest.trim_end().strip_suffix(')').ok_or_else(syntax)?;
+        let ret = parse_type(ret.trim())?;
+        let args = match args.trim() {
+            "" | "void" => Vec::new(),
+            args => args
+                .split(',')
+                .map(|arg| parse_type(arg.trim()))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        ensure!(
+            !args.contains(&NativeType::Void),
+            VoidArgumentSnafu { signature }
+        );
+
+        Ok(Signature { ret, args })
+    }
+
+    pub fn arg_classes(&self) -> Vec<Class> {
+        self.args.iter().map(|arg| arg.class()).collect()
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{}({})", self.ret.as_str(), args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let signature = Signature::parse("i32(ptr, f32, i64)").unwrap();
+        assert_eq!(signature.ret, NativeType::I32);
+        assert_eq!(
+            signature.args,
+            vec![NativeType::Ptr, NativeType::F32, NativeType::I64]
+        );
+        assert_eq!(signature.to_string(), "i32(ptr, f32, i64)");
+
+        let signature = Signature::parse(" void ( void ) ").unwrap();
+        assert_eq!(signature.ret, NativeType::Void);
+        assert!(signature.args.is_empty());
+        assert_eq!(Signature::parse("str()").unwrap().ret, NativeType::Str);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            Signature::parse("i32"),
+            Err(SignatureError::Syntax { .. })
+        ));
+        assert!(matches!(
+            Signature::parse("i32(ptr"),
+            Err(SignatureError::Syntax { .. })
+        ));
+        assert_eq!(
+            Signature::parse("i32(ptr, int)"),
+            Err(SignatureError::UnknownType {
+                type_name: "int".to_string(),
+                signature: "i32(ptr, int)".to_string()
+            })
+        );
+        assert!(matches!(
+            Signature::parse("i32(ptr, void)"),
+            Err(SignatureError::VoidArgument { .. })
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use mlua::prelude::*;
+use once_cell::sync::Lazy;
+use serde_json::Map;
+
+use super::event;
+use super::payload::{self, Payload};
+use crate::config;
+use crate::logger::script_target;
+use crate::luavm::{show_engine_message, LuaVM, VmInfo, WeakLuaVM};
+
+/// Commands handled by the engine itself.
+const RESERVED: &[&str] = &["/lua"];
+
+static COMMANDS: Lazy<Mutex<BTreeMap<String, Command>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    /// the rest of the input, as a string
+    Rest,
+}
+
+impl ArgType {
+    pub fn from_str(type_name: &str) -> Option<ArgType> {
+        match type_name {
+            "string" => Some(ArgType::String),
+            "number" => Some(ArgType::Number),
+            "integer" => Some(ArgType::Integer),
+            "boolean" => Some(ArgType::Boolean),
+            "rest" => Some(ArgType::Rest),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArgType::String => "string",
+            ArgType::Number => "number",
+            ArgType::Integer => "integer",
+            ArgType::Boolean => "boolean",
+            ArgType::Rest => "rest",
+        }
+    }
+
+    fn parse(&self, token: &str) -> Option<Payload> {
+        match self {
+            ArgType::String | ArgType::Rest => Some(Payload::from(token)),
+            ArgType::Number => token.parse::<f64>().ok().map(Payload::from),
+            ArgType::Integer => token.parse::<i64>().ok().map(Payload::from),
+            ArgType::Boolean => match token.to_lowercase().as_str() {
+                "true" | "on" | "yes" | "1" => Some(Payload::Bool(true)),
+                "false" | "off" | "no" | "0" => Some(Payload::Bool(false)),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgSpec {
+    pub name: String,
+    pub ty: ArgType,
+    pub optional: bool,
+}
+
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ty = match self.ty {
+            ArgType::String => String::new(),
+            ArgType::Rest => "...".to_string(),
+            ty => format!(":{}", ty.as_str()),
+        };
+        if self.optional {
+            write!(f, "[{}{}]", self.name, ty)
+        } else {
+            write!(f, "<{}{}>", self.name, ty)
+        }
+    }
+}
+
+/// Parse `args = { {name = "value", type = "number", optional = true}, ... }`.
+///
+/// The type defaults to `string`. Optional arguments must follow the required
+/// ones, and a `rest` argument must be the last one.
+pub fn parse_args(args: Option<LuaTable>) -> LuaResult<Vec<ArgSpec>> {
+    let Some(args) = args else {
+        return Ok(Vec::new());
+    };
+    let mut specs: Vec<ArgSpec> = Vec::new();
+    for arg in args.sequence_values::<LuaTable>() {
+        let arg = arg?;
+        let name: String = arg.get("name")?;
+        let type_name = arg
+            .get::<_, Option<String>>("type")?
+            .unwrap_or("string".to_string());
+        let ty = ArgType::from_str(&type_name).ok_or(LuaError::runtime(format!(
+            "Unknown type `{}` of argument `{}`, expect string, number, integer, boolean or rest",
+            type_name, name
+        )))?;
+        let optional = arg.get::<_, Option<bool>>("optional")?.unwrap_or(false);
+        if let Some(last) = specs.last() {
+            if last.ty == ArgType::Rest {
+                return Err(LuaError::runtime(format!(
+                    "Argument `{}` follows the rest argument `{}`",
+                    name, last.name
+                )));
+            }
+            if last.optional && !optional {
+                return Err(LuaError::runtime(format!(
+                    "Required argument `{}` follows the optional argument `{}`",
+                    name, last.name
+                )));
+            }
+        }
+        if specs.iter().any(|spec| spec.name == name) {
+            return Err(LuaError::runtime(format!("Duplicate argument `{}`", name)));
+        }
+        specs.push(ArgSpec { name, ty, optional });
+    }
+
+    Ok(specs)
+}
+
+/// A chat command registered by a script.
+#[derive(Clone)]
+struct Command {
+    name: String,
+    description: String,
+    args: Vec<ArgSpec>,
+    owner: VmInfo,
+    luavm: WeakLuaVM,
+    handler: Arc<LuaRegistryKey>,
+}
+
+impl Command {
+    fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for arg in &self.args {
+            usage.push(' ');
+            usage.push_str(&arg.to_string());
+        }
+        usage
+    }
+
+    /// Parse the arguments of an input to a table by argument name.
+    fn parse(&self, input: &str) -> Result<Payload, String> {
+        // skip the command name
+        let mut rest = input.trim_start();
+        rest = rest
+            .split_once(char::is_whitespace)
+            .map(|(_, rest)| rest)
+            .unwrap_or("");
+        let mut values = Map::new();
+        for arg in &self.args {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                if arg.optional {
+                    continue;
+                }
+                return Err(format!("Missing argument `{}`", arg.name));
+            }
+            let token = if arg.ty == ArgType::Rest {
+                std::mem::take(&mut rest).trim_end()
+            } else {
+                let (token, next) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                rest = next;
+                token
+            };
+            let value = arg.ty.parse(token).ok_or(format!(
+                "Invalid {} `{}` for argument `{}`",
+                arg.ty.as_str(),
+                token,
+                arg.name
+            ))?;
+            values.insert(arg.name.clone(), value);
+        }
+        if !rest.trim().is_empty() {
+            return Err(format!("Unexpected argument `{}`", rest.trim()));
+        }
+
+        Ok(Payload::Object(values))
+    }
+}
+
+/// Register a chat command owned by the VM of `lua`.
+///
+/// A script can replace its own commands, but not commands of other scripts or
+/// of the engine.
+pub fn register(lua: &Lua, name: &str, spec: LuaTable) -> LuaResult<()> {
+    let name = name.to_lowercase();
+    if name.len() < 2 || !name.starts_with('/') || name.contains(char::is_whitespace) {
+        return Err(LuaError::runtime(format!(
+            "Invalid command name: `{}`, expect `/name`",
+            name
+        )));
+    }
+    if RESERVED.contains(&name.as_str()) {
+        return Err(LuaError::runtime(format!(
+            "`{}` is reserved by the engine",
+            name
+        )));
+    }
+    let owner = VmInfo::of(lua).ok_or(LuaError::runtime("Unknown VM"))?;
+    let luavm = lua
+        .app_data_ref::<WeakLuaVM>()
+        .ok_or(LuaError::runtime("Commands are not available in this VM"))?
+        .clone();
+    let handler: LuaFunction = spec.get("handler")?;
+    let command = Command {
+        name: name.clone(),
+        description: spec
+            .get::<_, Option<String>>("description")?
+            .unwrap_or_default(),
+        args: parse_args(spec.get("args")?)?,
+        owner,
+        luavm,
+        handler: Arc::new(lua.create_registry_value(handler)?),
+    };
+
+    let mut commands = COMMANDS.lock().unwrap();
+    if let Some(other) = commands.get(&name) {
+        if other.owner.id != command.owner.id {
+            return Err(LuaError::runtime(format!(
+                "Command `{}` is already registered by {}",
+                name, other.owner.name
+            )));
+        }
+    }
+    commands.insert(name, command);
+
+    Ok(())
+}
+
+/// Remove command `name` if it is registered by the VM of `lua`.
+pub fn unregister(lua: &Lua, name: &str) -> bool {
+    let Some(owner) = VmInfo::of(lua) else {
+        return false;
+    };
+    let name = name.to_lowercase();
+    let mut commands = COMMANDS.lock().unwrap();
+    if commands.get(&name).map(|c| c.owner.id) != Some(owner.id) {
+        return false;
+    }
+    commands.remove(&name).is_some()
+}
+
+/// Remove all commands of a VM. Returns the number of removed commands.
+pub fn remove_owner(vm_id: u64) -> usize {
+    let mut commands = COMMANDS.lock().unwrap();
+    let len = commands.len();
+    commands.retain(|_, c| c.owner.id != vm_id);
+    len - commands.len()
+}
+
+/// Names of the commands of a VM, sorted.
+pub fn names_of(vm_id: u64) -> Vec<String> {
+    COMMANDS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|c| c.owner.id == vm_id)
+        .map(|c| c.name.clone())
+        .collect()
+}
+
+/// Help lines of the commands, grouped by script: `usage - description`.
+pub fn help(script: Option<&str>) -> BTreeMap<String, Vec<String>> {
+    let mut help: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for command in COMMANDS.lock().unwrap().values() {
+        if script.is_some_and(|s| s != command.owner.name) {
+            continue;
+        }
+        let line = if command.description.is_empty() {
+            command.usage()
+        } else {
+            format!("{} - {}", command.usage(), command.description)
+        };
+        help.entry(command.owner.name.clone())
+            .or_default()
+            .push(line);
+    }
+    help
+}
+
+/// Run the script command of a chat input, on the game thread.
+///
+/// Returns whether the input is a script command. Usage errors are shown in the
+/// chat, and so is a command of a script which is busy, as the game thread
+/// doesn't wait for it.
+pub fn execute_blocking(input: &str) -> bool {
+    let Some(name) = input.split_whitespace().next() else {
+        return false;
+    };
+    let Some(command) = COMMANDS.lock().unwrap().get(&name.to_lowercase()).cloned() else {
+        return false;
+    };
+    let args = match command.parse(input) {
+        Ok(args) => args,
+        Err(e) => {
+            show_engine_message(&format!("{}\nUsage: {}", e, command.usage()));
+            return true;
+        }
+    };
+    let (Some(handle), Some(luavm)) = (event::runtime(), command.luavm.upgrade()) else {
+        return true;
+    };
+    let Ok(luavm) = luavm.try_lock() else {
+        show_engine_message(&format!(
+            "{} is busy, try {} again",
+            command.owner.name, command.name
+        ));
+        return true;
+    };
+    if let Err(e) = handle.block_on(call_handler(&luavm, &command, &args, input)) {
+        error!(
+            target: &script_target(&command.owner.name),
+            "Error in command `{}` of {}: {}",
+            command.name, command.owner.name, e
+        );
+        // unless the logger shows the errors of scripts already
+        if !config::get().log.chat_errors {
+            show_engine_message(&format!("Error in {}: {}", command.name, e));
+        }
+    }
+
+    true
+}
+
+/// Call `handler(args, input)` in the locked VM of the command.
+async fn call_handler(
+    luavm: &LuaVM,
+    command: &Command,
+    args: &Payload,
+    input: &str,
+) -> LuaResult<()> {
+    if !luavm.is_running() {
+        return Ok(());
+    }
+    let lua = &luavm.lua;
+    let handler: LuaFunction = lua.registry_value(&command.handler)?;
+    handler
+        .call_async::<_, ()>((payload::into_lua(lua, args)?, input))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(lua: &Lua, args: &str) -> Command {
+        Command {
+            name: "/hp".to_string(),
+            description: String::new(),
+            args: parse_args(lua.load(args).eval().unwrap()).unwrap(),
+            owner: VmInfo {
+                id: 1,
+                name: "hp.lua".to_string(),
+            },
+            luavm: WeakLuaVM::new(),
+            handler: Arc::new(
+                lua.create_registry_value(lua.create_function(|_, ()| Ok(())).unwrap())
+                    .unwrap(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let lua = Lua::new();
+        let hp = command(
+            &lua,
+            r#"{ { name = "value", type = "number" }, { name = "target", optional = true } }"#,
+        );
+        assert_eq!(hp.usage(), "/hp <value:number> [target]");
+        assert_eq!(
+            hp.parse("/hp 10.5 self").unwrap(),
+            serde_json::json!({"value": 10.5, "target": "self"})
+        );
+        assert_eq!(
+            hp.parse("/HP  3 ").unwrap(),
+            serde_json::json!({"value": 3.0})
+        );
+        assert!(hp
+            .parse("/hp")
+            .unwrap_err()
+            .contains("Missing argument `value`"));
+        assert!(hp
+            .parse("/hp ten")
+            .unwrap_err()
+            .contains("Invalid number `ten`"));
+        assert!(hp
+            .parse("/hp 1 a b")
+            .unwrap_err()
+            .contains("Unexpected argument `b`"));
+
+        let say = command(
+            &lua,
+            r#"{ { name = "loud", type = "boolean" }, { name = "text", type = "rest" } }"#,
+        );
+        assert_eq!(
+            say.parse("/say on hello  world ").unwrap(),
+            serde_json::json!({"loud": true, "text": "hello  world"})
+        );
+
+        for invalid in [
+            r#"{ { name = "a", optional = true }, { name = "b" } }"#,
+            r#"{ { name = "a", type = "rest" }, { name = "b" } }"#,
+            r#"{ { name = "a" }, { name = "a" } }"#,
+            r#"{ { name = "a", type = "table" } }"#,
+        ] {
+            assert!(
+                parse_args(lua.load(invalid).eval().unwrap()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
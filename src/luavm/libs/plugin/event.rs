@@ -0,0 +1,735 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error};
+use mlua::prelude::*;
+use once_cell::sync::{Lazy, OnceCell};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+use super::payload::{self, Payload};
+use crate::logger::script_target;
+use crate::luavm::deadline::call_until;
+use crate::luavm::{LuaVM, VmInfo, WeakLuaVM};
+
+static BUS: Lazy<Mutex<EventBus>> = Lazy::new(|| Mutex::new(EventBus::default()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static RUNTIME: OnceCell<Handle> = OnceCell::new();
+/// Events emitted by scripts, dispatched in order by a single thread.
+static QUEUE: OnceCell<mpsc::UnboundedSender<(String, Payload)>> = OnceCell::new();
+
+/// Event names a listener subscribes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// `*`
+    All,
+    /// `quest.*`, matching `quest.start` and `quest.stage.change`, stored as `quest.`
+    Namespace(String),
+    Exact(String),
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Option<Pattern> {
+        if pattern == "*" {
+            return Some(Pattern::All);
+        }
+        let (name, namespace) = match pattern.strip_suffix(".*") {
+            Some(namespace) => (namespace, true),
+            None => (pattern, false),
+        };
+        if !is_valid_name(name) {
+            return None;
+        }
+        if namespace {
+            Some(Pattern::Namespace(format!("{}.", name)))
+        } else {
+            Some(Pattern::Exact(name.to_string()))
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Pattern::All => true,
+            Pattern::Namespace(prefix) => name.starts_with(prefix.as_str()),
+            Pattern::Exact(exact) => exact == name,
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::All => write!(f, "*"),
+            Pattern::Namespace(prefix) => write!(f, "{}*", prefix),
+            Pattern::Exact(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Whether `name` can be emitted, like `OnMonsterCreate` or `quest.start`.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .split('.')
+            .all(|part| !part.is_empty() && !part.contains(['*', ' ']))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ListenerOptions {
+    /// listeners with a higher priority are called first
+    pub priority: i64,
+    /// remove the listener after its first call
+    pub once: bool,
+    /// call the listener of a frame event only every n-th frame
+    pub every: u64,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            once: false,
+            every: 1,
+        }
+    }
+}
+
+impl ListenerOptions {
+    pub fn from_lua(opts: Option<LuaTable>, once: bool) -> LuaResult<ListenerOptions> {
+        let Some(opts) = opts else {
+            return Ok(ListenerOptions {
+                once,
+                ..Default::default()
+            });
+        };
+        let every = opts.get::<_, Option<u64>>("everyNFrames")?.unwrap_or(1);
+        if every == 0 {
+            return Err(LuaError::runtime("`everyNFrames` must be at least 1"));
+        }
+        Ok(ListenerOptions {
+            priority: opts.get::<_, Option<i64>>("priority")?.unwrap_or(0),
+            once: once || opts.get::<_, Option<bool>>("once")?.unwrap_or(false),
+            every,
+        })
+    }
+}
+
+struct Listener {
+    id: u64,
+    owner: VmInfo,
+    luavm: WeakLuaVM,
+    pattern: Pattern,
+    options: ListenerOptions,
+    func: Arc<LuaRegistryKey>,
+}
+
+/// A listener selected for one dispatch.
+struct Target {
+    id: u64,
+    once: bool,
+    every: u64,
+    owner: VmInfo,
+    luavm: WeakLuaVM,
+    func: Arc<LuaRegistryKey>,
+}
+
+impl Target {
+    /// Call the listener with a Lua copy of `payload` and the event name, then
+    /// `f` with the payload table and the returned value, in the listener VM.
+    ///
+    /// Returns `None` if the listener wasn't called: its VM is stopped, or it's
+    /// a `once` listener already called by another dispatch.
+    async fn call<R>(
+        &self,
+        name: &str,
+        payload: &Payload,
+        f: impl for<'lua> FnOnce(&'lua Lua, LuaValue<'lua>, LuaValue<'lua>) -> LuaResult<R>,
+    ) -> Option<LuaResult<R>> {
+        let luavm = self.luavm.upgrade()?;
+        let luavm = luavm.lock().await;
+        self.call_locked(&luavm, name, payload, None, f).await
+    }
+
+    /// [`Target::call`] in the already locked VM of the listener, aborted once
+    /// `deadline` has passed.
+    async fn call_locked<R>(
+        &self,
+        luavm: &LuaVM,
+        name: &str,
+        payload: &Payload,
+        deadline: Option<Instant>,
+        f: impl for<'lua> FnOnce(&'lua Lua, LuaValue<'lua>, LuaValue<'lua>) -> LuaResult<R>,
+    ) -> Option<LuaResult<R>> {
+        if !luavm.is_running() {
+            return None;
+        }
+        if self.once && !remove_listener(self.id, self.owner.id) {
+            return None;
+        }
+        let lua = &luavm.lua;
+        let result = async {
+            let func: LuaFunction = lua.registry_value(&self.func)?;
+            let value = payload::into_lua(lua, payload)?;
+            let args = (value.clone(), name);
+            let ret = match deadline {
+                Some(deadline) => {
+                    call_until(lua, func, args, deadline, "Frame budget exceeded").await?
+                }
+                None => func.call_async::<_, LuaValue>(args).await?,
+            };
+            f(lua, value, ret)
+        }
+        .await;
+
+        Some(result)
+    }
+}
+
+/// Result of a "before" event, dispatched with [`emit_before`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub cancelled: bool,
+    /// payload with the changes of all listeners
+    pub payload: Payload,
+}
+
+#[derive(Default)]
+struct EventBus {
+    /// sorted by descending priority, then by registration
+    listeners: Vec<Listener>,
+    /// name and description of the events emitted by the engine
+    builtins: BTreeMap<String, String>,
+}
+
+impl EventBus {
+    fn add(&mut self, listener: Listener) {
+        let index = self
+            .listeners
+            .iter()
+            .position(|l| l.options.priority < listener.options.priority)
+            .unwrap_or(self.listeners.len());
+        self.listeners.insert(index, listener);
+    }
+
+    fn remove(&mut self, id: u64, vm_id: u64) -> bool {
+        let len = self.listeners.len();
+        self.listeners.retain(|l| l.id != id || l.owner.id != vm_id);
+        self.listeners.len() != len
+    }
+
+    fn remove_owner(&mut self, vm_id: u64) -> usize {
+        let len = self.listeners.len();
+        self.listeners.retain(|l| l.owner.id != vm_id);
+        len - self.listeners.len()
+    }
+
+    fn has_listeners(&self, name: &str) -> bool {
+        self.listeners.iter().any(|l| l.pattern.matches(name))
+    }
+
+    /// Listeners of `name` in call order.
+    fn matching(&self, name: &str) -> Vec<Target> {
+        self.listeners
+            .iter()
+            .filter(|l| l.pattern.matches(name))
+            .map(|l| Target {
+                id: l.id,
+                once: l.options.once,
+                every: l.options.every,
+                owner: l.owner.clone(),
+                luavm: l.luavm.clone(),
+                func: l.func.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Start the dispatcher of script events on `handle`.
+pub fn init(handle: Handle) {
+    if RUNTIME.set(handle.clone()).is_err() {
+        return;
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel::<(String, Payload)>();
+    let _ = QUEUE.set(tx);
+    thread::spawn(move || {
+        handle.block_on(async {
+            while let Some((name, payload)) = rx.recv().await {
+                dispatch(&name, &payload).await;
+            }
+        })
+    });
+}
+
+/// Runtime of the engine, to run scripts from game threads.
+pub fn runtime() -> Option<&'static Handle> {
+    RUNTIME.get()
+}
+
+/// Register an event emitted by the engine. Scripts can't emit built-in events.
+pub fn register_builtin(name: &str, description: &str) {
+    BUS.lock()
+        .unwrap()
+        .builtins
+        .insert(name.to_string(), description.to_string());
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    BUS.lock().unwrap().builtins.contains_key(name)
+}
+
+/// Add a listener owned by the VM of `lua`, returns its id.
+pub fn add_listener(
+    lua: &Lua,
+    pattern: &str,
+    func: LuaFunction,
+    options: ListenerOptions,
+) -> LuaResult<u64> {
+    let pattern = Pattern::parse(pattern).ok_or(LuaError::runtime(format!(
+        "Invalid event name: `{}`, expect `name`, `namespace.*` or `*`",
+        pattern
+    )))?;
+    let owner = VmInfo::of(lua).ok_or(LuaError::runtime("Unknown VM"))?;
+    let luavm = lua
+        .app_data_ref::<WeakLuaVM>()
+        .ok_or(LuaError::runtime("Events are not available in this VM"))?
+        .clone();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    BUS.lock().unwrap().add(Listener {
+        id,
+        owner,
+        luavm,
+        pattern,
+        options,
+        func: Arc::new(lua.create_registry_value(func)?),
+    });
+
+    Ok(id)
+}
+
+/// Remove a listener of the VM `vm_id`.
+pub fn remove_listener(id: u64, vm_id: u64) -> bool {
+    BUS.lock().unwrap().remove(id, vm_id)
+}
+
+/// `plugin:removeEventListener(id)`, scripts may only remove their own listeners.
+pub fn unlisten(lua: &Lua, id: u64) -> LuaResult<bool> {
+    let vm = VmInfo::of(lua).ok_or(LuaError::runtime("Unknown Lua VM"))?;
+    let owner = BUS
+        .lock()
+        .unwrap()
+        .listeners
+        .iter()
+        .find(|l| l.id == id)
+        .map(|l| l.owner.clone());
+    match owner {
+        Some(owner) if owner.id != vm.id => Err(LuaError::runtime(format!(
+            "Listener #{} is owned by `{}`",
+            id, owner.name
+        ))),
+        _ => Ok(remove_listener(id, vm.id)),
+    }
+}
+
+/// Remove all listeners of a VM. Returns the number of removed listeners.
+pub fn remove_owner(vm_id: u64) -> usize {
+    BUS.lock().unwrap().remove_owner(vm_id)
+}
+
+/// Event patterns of the listeners of a VM, in call order.
+pub fn listeners_of(vm_id: u64) -> Vec<String> {
+    BUS.lock()
+        .unwrap()
+        .listeners
+        .iter()
+        .filter(|l| l.owner.id == vm_id)
+        .map(|l| l.pattern.to_string())
+        .collect()
+}
+
+/// Whether an event has listeners, to skip building its payload.
+pub fn has_listeners(name: &str) -> bool {
+    BUS.lock().unwrap().has_listeners(name)
+}
+
+/// Queue an event emitted by a script.
+///
+/// Dispatched later, so listeners of the emitting VM can run.
+pub fn emit(name: String, payload: Payload) {
+    if let Some(queue) = QUEUE.get() {
+        let _ = queue.send((name, payload));
+    }
+}
+
+/// Dispatch an event from a game thread, waiting for all listeners.
+///
+/// `payload` is only built if the event has listeners. When called from the
+/// runtime (e.g. by a script calling game code), the event is queued instead.
+pub fn emit_blocking(name: &str, payload: impl FnOnce() -> Payload) {
+    if !BUS.lock().unwrap().has_listeners(name) {
+        return;
+    }
+    if Handle::try_current().is_ok() {
+        emit(name.to_string(), payload());
+        return;
+    }
+    let Some(handle) = RUNTIME.get() else {
+        return;
+    };
+    handle.block_on(dispatch(name, &payload()));
+}
+
+/// Dispatch a "before" event from a game thread, waiting for all listeners.
+///
+/// Returns `None` without building `payload` if the event has no listeners,
+/// or if called from the runtime, where listeners can't be waited for.
+pub fn emit_before(name: &str, payload: impl FnOnce() -> Payload) -> Option<Outcome> {
+    if !BUS.lock().unwrap().has_listeners(name) {
+        return None;
+    }
+    if Handle::try_current().is_ok() {
+        debug!("`{}` emitted from the runtime, listeners skipped", name);
+        return None;
+    }
+    let handle = RUNTIME.get()?;
+    Some(handle.block_on(dispatch_before(name, payload())))
+}
+
+/// Call the listeners of an event in order, each with a fresh Lua copy of the
+/// payload and the event name. Returns the number of called listeners.
+pub async fn dispatch(name: &str, payload: &Payload) -> usize {
+    let targets = BUS.lock().unwrap().matching(name);
+    let mut called = 0;
+    for target in targets {
+        let Some(result) = target.call(name, payload, |_, _, _| Ok(())).await else {
+            continue;
+        };
+        called += 1;
+        if let Err(e) = result {
+            error!(
+                target: &script_target(&target.owner.name),
+                "Error in `{}` listener of {}: {}",
+                name, target.owner.name, e
+            );
+        }
+    }
+
+    called
+}
+
+/// Listeners of a per-frame event carried over to the next frame.
+#[derive(Debug, Default)]
+pub struct Carryover {
+    /// not reached in time or whose VM was busy, called first
+    deferred: Vec<u64>,
+    /// aborted at the end of the budget, called after the others so a listener
+    /// always exceeding the budget can't starve them
+    overrun: Vec<u64>,
+}
+
+impl Carryover {
+    fn contains(&self, id: u64) -> bool {
+        self.deferred.contains(&id) || self.overrun.contains(&id)
+    }
+
+    /// Carry a listener not called in this frame over to the next one, keeping
+    /// it last if it was `overrun`.
+    fn skip(&mut self, id: u64, overrun: bool) {
+        match overrun {
+            true => self.overrun.push(id),
+            false => self.deferred.push(id),
+        }
+    }
+}
+
+/// Call the listeners of a per-frame event until `budget` is spent, so slow
+/// listeners can't hold the game thread for long.
+///
+/// Listeners whose `everyNFrames` doesn't divide `frame` are skipped. Listeners
+/// not reached in time or whose VM is busy are called first in the next frame,
+/// listeners aborted at the end of the budget are called last in the next
+/// frames, until they finish in time. Returns the number of called listeners.
+pub async fn dispatch_frame(
+    name: &str,
+    payload: &Payload,
+    frame: u64,
+    budget: Duration,
+    carryover: &mut Carryover,
+) -> usize {
+    let targets = frame_order(BUS.lock().unwrap().matching(name), frame, carryover);
+    let overrun = std::mem::take(&mut carryover.overrun);
+    carryover.deferred.clear();
+    let deadline = Instant::now() + budget;
+    let mut called = 0;
+    for target in targets {
+        if Instant::now() >= deadline {
+            carryover.skip(target.id, overrun.contains(&target.id));
+            continue;
+        }
+        let Some(luavm) = target.luavm.upgrade() else {
+            continue;
+        };
+        let Ok(luavm) = luavm.try_lock() else {
+            carryover.skip(target.id, overrun.contains(&target.id));
+            continue;
+        };
+        let Some(result) = target
+            .call_locked(&luavm, name, payload, Some(deadline), |_, _, _| Ok(()))
+            .await
+        else {
+            continue;
+        };
+        called += 1;
+        // a listener failing after the deadline was aborted, it is called
+        // again after the others in the next frame
+        if result.is_err() && Instant::now() >= deadline {
+            debug!(
+                target: &script_target(&target.owner.name),
+                "`{}` listener of {} exceeded the frame budget",
+                name, target.owner.name
+            );
+            carryover.overrun.push(target.id);
+            continue;
+        }
+        if let Err(e) = result {
+            error!(
+                target: &script_target(&target.owner.name),
+                "Error in `{}` listener of {}: {}",
+                name, target.owner.name, e
+            );
+        }
+    }
+
+    called
+}
+
+/// Listeners due in `frame` or carried over: the deferred ones first, then
+/// the others in bus order, then the overrun ones.
+fn frame_order(targets: Vec<Target>, frame: u64, carryover: &Carryover) -> Vec<Target> {
+    let (mut first, rest): (Vec<_>, Vec<_>) = targets
+        .into_iter()
+        .filter(|t| carryover.contains(t.id) || frame.is_multiple_of(t.every))
+        .partition(|t| carryover.deferred.contains(&t.id));
+    let (last, rest): (Vec<_>, Vec<_>) = rest
+        .into_iter()
+        .partition(|t| carryover.overrun.contains(&t.id));
+    first.extend(rest);
+    first.extend(last);
+    first
+}
+
+/// Call the listeners of a "before" event in order, letting them change or
+/// cancel it.
+///
+/// Each listener sees the changes of the previous ones: its edits of the payload
+/// table are kept, and a returned table is merged into the payload. Returning
+/// `false` or `{cancel = true}` cancels the event and skips the remaining
+/// listeners, so the first cancel by priority wins. Changes of a failing
+/// listener are discarded.
+pub async fn dispatch_before(name: &str, payload: Payload) -> Outcome {
+    let targets = BUS.lock().unwrap().matching(name);
+    let mut outcome = Outcome {
+        cancelled: false,
+        payload,
+    };
+    for target in targets {
+        let result = target
+            .call(name, &outcome.payload, |lua, value, ret| {
+                let mut payload = payload::from_lua(lua, value)?;
+                let cancelled = apply_decision(lua, &mut payload, ret)?;
+                Ok((payload, cancelled))
+            })
+            .await;
+        match result {
+            Some(Ok((payload, cancelled))) => {
+                outcome.payload = payload;
+                if cancelled {
+                    outcome.cancelled = true;
+                    break;
+                }
+            }
+            Some(Err(e)) => error!(
+                target: &script_target(&target.owner.name),
+                "Error in `{}` listener of {}: {}",
+                name, target.owner.name, e
+            ),
+            None => (),
+        }
+    }
+
+    outcome
+}
+
+/// Apply the value returned by a listener of a "before" event to `payload`.
+/// Returns whether the listener cancelled the event.
+fn apply_decision(lua: &Lua, payload: &mut Payload, ret: LuaValue) -> LuaResult<bool> {
+    match ret {
+        LuaValue::Nil | LuaValue::Boolean(true) => Ok(false),
+        LuaValue::Boolean(false) => Ok(true),
+        LuaValue::Table(changes) => {
+            let cancel = changes.get::<_, Option<bool>>("cancel")?.unwrap_or(false);
+            changes.raw_set("cancel", LuaNil)?;
+            if let (Payload::Object(fields), Payload::Object(changes)) =
+                (payload, payload::from_lua(lua, LuaValue::Table(changes))?)
+            {
+                fields.extend(changes);
+            }
+            Ok(cancel)
+        }
+        other => Err(LuaError::runtime(format!(
+            "Expect nil, a boolean or a table from the listener, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let all = Pattern::parse("*").unwrap();
+        let quest = Pattern::parse("quest.*").unwrap();
+        let exact = Pattern::parse("quest.start").unwrap();
+        assert!(all.matches("OnMonsterCreate"));
+        assert!(quest.matches("quest.start"));
+        assert!(quest.matches("quest.stage.change"));
+        assert!(!quest.matches("questing"));
+        assert!(!quest.matches("quest"));
+        assert!(exact.matches("quest.start"));
+        assert!(!exact.matches("quest.end"));
+        for pattern in ["*", "quest.*", "quest.start"] {
+            assert_eq!(Pattern::parse(pattern).unwrap().to_string(), pattern);
+        }
+        for invalid in ["", "quest.", "quest*", "a..b", "*.start", "a b"] {
+            assert_eq!(Pattern::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    fn listener(lua: &Lua, id: u64, pattern: &str, priority: i64, once: bool) -> Listener {
+        Listener {
+            id,
+            owner: VmInfo {
+                id: 1,
+                name: "test.lua".to_string(),
+            },
+            luavm: WeakLuaVM::new(),
+            pattern: Pattern::parse(pattern).unwrap(),
+            options: ListenerOptions {
+                priority,
+                once,
+                every: 1,
+            },
+            func: Arc::new(
+                lua.create_registry_value(lua.create_function(|_, ()| Ok(())).unwrap())
+                    .unwrap(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_bus_order() {
+        let lua = Lua::new();
+        let mut bus = EventBus::default();
+        bus.add(listener(&lua, 1, "quest.start", 0, false));
+        bus.add(listener(&lua, 2, "quest.*", 10, true));
+        bus.add(listener(&lua, 3, "*", 0, false));
+        bus.add(listener(&lua, 4, "OnMonsterCreate", 5, false));
+        let order = |bus: &EventBus| bus.listeners.iter().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(order(&bus), vec![2, 4, 1, 3]);
+
+        let ids = |targets: Vec<Target>| targets.iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(ids(bus.matching("quest.start")), vec![2, 1, 3]);
+        assert!(bus.matching("quest.start")[0].once);
+        assert!(bus.remove(2, 1));
+        assert_eq!(ids(bus.matching("quest.start")), vec![1, 3]);
+        assert!(bus.has_listeners("anything"));
+        // only the owner removes a listener
+        assert!(!bus.remove(3, 2));
+        assert!(bus.remove(3, 1));
+        assert!(!bus.has_listeners("anything"));
+        assert_eq!(bus.remove_owner(1), 2);
+    }
+
+    #[test]
+    fn test_frame_order() {
+        let lua = Lua::new();
+        let mut bus = EventBus::default();
+        bus.add(listener(&lua, 1, "OnFrame", 0, false));
+        let mut every_3 = listener(&lua, 2, "OnFrame", 0, false);
+        every_3.options.every = 3;
+        bus.add(every_3);
+        bus.add(listener(&lua, 3, "OnFrame", 0, false));
+        let ids = |frame, deferred: &[u64]| {
+            let carryover = Carryover {
+                deferred: deferred.to_vec(),
+                overrun: vec![],
+            };
+            frame_order(bus.matching("OnFrame"), frame, &carryover)
+                .iter()
+                .map(|t| t.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(1, &[]), vec![1, 3]);
+        assert_eq!(ids(3, &[]), vec![1, 2, 3]);
+        assert_eq!(ids(4, &[3]), vec![3, 1]);
+        // a deferred listener runs even if it's not due
+        assert_eq!(ids(4, &[2]), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn test_frame_order_overrun() {
+        let lua = Lua::new();
+        let mut bus = EventBus::default();
+        for id in 1..=3 {
+            bus.add(listener(&lua, id, "OnFrame", 0, false));
+        }
+        let ids = |carryover: &Carryover| {
+            frame_order(bus.matching("OnFrame"), 1, carryover)
+                .iter()
+                .map(|t| t.id)
+                .collect::<Vec<_>>()
+        };
+        // 1 is aborted at the end of the budget, 2 and 3 aren't reached: they
+        // are called first in the next frame
+        let mut carryover = Carryover::default();
+        carryover.overrun.push(1);
+        carryover.skip(2, false);
+        carryover.skip(3, false);
+        assert_eq!(ids(&carryover), vec![2, 3, 1]);
+
+        // 1 always exceeds the budget, so 2 and 3 are always called before it
+        let carryover = Carryover {
+            deferred: vec![],
+            overrun: vec![1],
+        };
+        assert_eq!(ids(&carryover), vec![2, 3, 1]);
+
+        // not reached after overrunning, it stays last
+        let mut carryover = Carryover::default();
+        carryover.skip(1, true);
+        carryover.skip(3, false);
+        assert_eq!(ids(&carryover), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_apply_decision() {
+        let lua = Lua::new();
+        let mut payload = serde_json::json!({"text": "/hp", "channel": 1});
+        let ret = lua.load("{ text = '/hp 10' }").eval().unwrap();
+        assert!(!apply_decision(&lua, &mut payload, ret).unwrap());
+        assert_eq!(payload["text"], "/hp 10");
+        assert_eq!(payload["channel"], 1);
+
+        assert!(!apply_decision(&lua, &mut payload, LuaNil).unwrap());
+        assert!(apply_decision(&lua, &mut payload, LuaValue::Boolean(false)).unwrap());
+        let ret = lua.load("{ cancel = true, text = '' }").eval().unwrap();
+        assert!(apply_decision(&lua, &mut payload, ret).unwrap());
+        assert_eq!(payload, serde_json::json!({"text": "", "channel": 1}));
+        assert!(apply_decision(&lua, &mut payload, LuaValue::Integer(1)).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+pub mod commands;
+pub mod event;
+pub mod payload;
+mod service;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use event::ListenerOptions;
+use log::{debug, error};
+use mlua::prelude::*;
+use mlua::UserData;
+use rand::RngCore;
+use tokio::runtime::Handle;
+use tokio::sync::Mutex;
+
+use super::print::target;
+use crate::luavm::LuaVM;
+use crate::luavm::VmInfo;
+use crate::luavm::WeakLuaVM;
+
+type EventFuncs = Vec<(u64, mlua::RegistryKey)>;
+
+#[derive(Clone)]
+pub struct Plugin {
+    /// setInterval callback functions \
+    /// key: interval(ms), value: Vec<(id, func_reg_key)>
+    interval_listeners: Arc<Mutex<HashMap<u64, EventFuncs>>>,
+
+    luavm: WeakLuaVM,
+}
+
+impl UserData for Plugin {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("name", |_, _| Ok(env!("CARGO_PKG_NAME")));
+        fields.add_field_method_get("version", |_, _| Ok(env!("CARGO_PKG_VERSION")));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "addEventListener",
+            |lua, _, (name, f, opts): (String, LuaFunction, Option<LuaTable>)| {
+                event::add_listener(lua, &name, f, ListenerOptions::from_lua(opts, false)?)
+            },
+        );
+        methods.add_method(
+            "once",
+            |lua, _, (name, f, opts): (String, LuaFunction, Option<LuaTable>)| {
+                event::add_listener(lua, &name, f, ListenerOptions::from_lua(opts, true)?)
+            },
+        );
+        methods.add_method("removeEventListener", |lua, _, id: u64| {
+            event::unlisten(lua, id)
+        });
+        methods.add_method("emit", |lua, _, (name, payload): (String, LuaValue)| {
+            if !event::is_valid_name(&name) {
+                return Err(LuaError::runtime(format!("Invalid event name: `{}`", name)));
+            }
+            if event::is_builtin(&name) {
+                return Err(LuaError::runtime(format!(
+                    "`{}` is a built-in event and can't be emitted by scripts",
+                    name
+                )));
+            }
+            event::emit(name, payload::from_lua(lua, payload)?);
+            Ok(())
+        });
+        methods.add_method(
+            "registerCommand",
+            |lua, _, (name, spec): (String, LuaTable)| commands::register(lua, &name, spec),
+        );
+        methods.add_method("unregisterCommand", |lua, _, name: String| {
+            Ok(commands::unregister(lua, &name))
+        });
+        methods.add_method("provide", |lua, _, (name, table): (String, LuaTable)| {
+            service::provide(lua, &name, table)
+        });
+        methods.add_method("revoke", |lua, _, name: String| {
+            Ok(service::revoke(lua, &name))
+        });
+        methods.add_method("hasService", |_, _, name: String| {
+            Ok(service::is_provided(&name))
+        });
+        methods.add_async_method(
+            "call",
+            |lua, _, (name, method, args): (String, String, LuaMultiValue)| async move {
+                service::call(lua, &name, &method, args, service::DEFAULT_TIMEOUT).await
+            },
+        );
+        methods.add_async_method(
+            "callTimeout",
+            |lua, _, (timeout, name, method, args): (u64, String, String, LuaMultiValue)| async move {
+                service::call(lua, &name, &method, args, Duration::from_millis(timeout)).await
+            },
+        );
+        methods.add_async_method_mut(
+            "setInterval",
+            |lua, this, (f, interval): (mlua::Function, u64)| async move {
+                let func_reg_key = lua.create_registry_value(f)?;
+                let id = rand::thread_rng().next_u64();
+                let mut listeners = this.interval_listeners.lock().await;
+                if listeners.get(&interval).is_none() {
+                    start_set_interval(this.clone(), interval, target(lua));
+                };
+                listeners
+                    .entry(interval)
+                    .or_default()
+                    .push((id, func_reg_key));
+
+                Ok(id)
+            },
+        );
+    }
+}
+
+impl Plugin {
+    pub fn new(luavm: WeakLuaVM) -> Plugin {
+        Plugin {
+            interval_listeners: Arc::new(Mutex::new(HashMap::new())),
+            luavm,
+        }
+    }
+
+    pub fn get_luavm(&self) -> Option<Arc<Mutex<LuaVM>>> {
+        self.luavm.upgrade()
+    }
+
+    pub async fn dispatch_set_interval(&self, interval: u64) -> Result<(), mlua::Error> {
+        if let Some(interval_funcs) = self.interval_listeners.lock().await.get(&interval) {
+            if interval_funcs.is_empty() {
+                return Ok(());
+            }
+            if let Some(luavm) = self.get_luavm() {
+                let luavm_ = luavm.lock().await;
+                if !luavm_.is_running() {
+                    return Ok(());
+                }
+                for (_, func_reg_key) in interval_funcs {
+                    let f: mlua::Function = luavm_.lua.registry_value(func_reg_key)?;
+                    f.call_async::<_, ()>(()).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Call the `setInterval` functions of `interval`, errors are logged with `target`.
+pub fn start_set_interval(p: Plugin, interval: u64, target: String) {
+    let handle = Handle::current();
+    thread::spawn(move || {
+        handle.block_on(async {
+            while p.get_luavm().is_some() {
+                if let Err(e) = p.dispatch_set_interval(interval).await {
+                    error!(target: &target, "Error in setInterval: {}", e);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(interval)).await;
+            }
+        })
+    });
+}
+
+/// Resources held by a VM, for `/lua info`.
+#[derive(Debug, Clone, Default)]
+pub struct VmUsage {
+    /// bytes allocated by the Lua state
+    pub memory: usize,
+    /// event patterns of the listeners, in call order
+    pub listeners: Vec<String>,
+    /// number of `setInterval` callbacks by interval in milliseconds
+    pub intervals: BTreeMap<u64, usize>,
+    pub commands: Vec<String>,
+    pub services: Vec<String>,
+}
+
+/// Resources held by `luavm`.
+pub async fn usage(luavm: &Mutex<LuaVM>) -> VmUsage {
+    // released before locking the intervals, which are locked first by
+    // `dispatch_set_interval`
+    let (vm, memory, plugin) = {
+        let luavm = luavm.lock().await;
+        let plugin = luavm.lua.app_data_ref::<Plugin>().map(|p| p.clone());
+        (VmInfo::of(&luavm.lua), luavm.lua.used_memory(), plugin)
+    };
+    let mut usage = VmUsage {
+        memory,
+        ..Default::default()
+    };
+    if let Some(plugin) = plugin {
+        for (interval, funcs) in plugin.interval_listeners.lock().await.iter() {
+            if !funcs.is_empty() {
+                usage.intervals.insert(*interval, funcs.len());
+            }
+        }
+    }
+    if let Some(vm) = vm {
+        usage.listeners = event::listeners_of(vm.id);
+        usage.commands = commands::names_of(vm.id);
+        usage.services = service::names_of(vm.id);
+    }
+    usage
+}
+
+/// Stop phase of the plugin library, removing the event listeners, services and
+/// commands of the VM.
+pub fn on_stop(lua: &Lua) {
+    if let Some(vm) = VmInfo::of(lua) {
+        let removed = event::remove_owner(vm.id);
+        if removed > 0 {
+            debug!("removed {} event listeners", removed);
+        }
+        let removed = service::remove_owner(vm.id);
+        if removed > 0 {
+            debug!("removed {} services", removed);
+        }
+        let removed = commands::remove_owner(vm.id);
+        if removed > 0 {
+            debug!("removed {} commands", removed);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use mlua::prelude::*;
+use mlua::{DeserializeOptions, SerializeOptions};
+
+/// A Lua value detached from its VM, to pass it to other VMs.
+pub type Payload = serde_json::Value;
+
+/// Serialize a Lua value. Functions, userdata and cyclic tables are rejected.
+pub fn from_lua(lua: &Lua, value: LuaValue) -> LuaResult<Payload> {
+    lua.from_value_with(value, DeserializeOptions::new().sort_keys(true))
+}
+
+/// Build a fresh Lua value of a payload, with `nil` for null.
+pub fn into_lua<'lua>(lua: &'lua Lua, payload: &Payload) -> LuaResult<LuaValue<'lua>> {
+    lua.to_value_with(
+        payload,
+        SerializeOptions::new()
+            .set_array_metatable(false)
+            .serialize_none_to_null(false)
+            .serialize_unit_to_null(false),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let lua = Lua::new();
+        let value = lua
+            .load(r#"{ name = "Rathalos", hp = 1200, ratio = 0.5, parts = { "head", "tail" } }"#)
+            .eval()
+            .unwrap();
+        let payload = from_lua(&lua, value).unwrap();
+        assert_eq!(payload["hp"], 1200);
+        assert_eq!(payload["parts"][1], "tail");
+
+        let other = Lua::new();
+        other
+            .globals()
+            .set("p", into_lua(&other, &payload).unwrap())
+            .unwrap();
+        other
+            .load(r#"assert(p.name == "Rathalos" and p.ratio == 0.5 and p.parts[2] == "tail")"#)
+            .exec()
+            .unwrap();
+        assert_eq!(into_lua(&other, &Payload::Null).unwrap(), LuaNil);
+
+        let f = lua.create_function(|_, ()| Ok(())).unwrap();
+        assert!(from_lua(&lua, LuaValue::Function(f)).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mlua::prelude::*;
+use once_cell::sync::Lazy;
+
+use super::payload::{self, Payload};
+use crate::luavm::{VmInfo, WeakLuaVM};
+
+/// Timeout of `Plugin:call`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+static SERVICES: Lazy<Mutex<HashMap<String, Service>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// Pending calls between VMs: the providers each calling VM waits for, once per
+/// call, as coroutines of a VM can call concurrently. The caller keeps its VM
+/// locked while waiting for the provider.
+static WAITING: Lazy<Mutex<HashMap<u64, Vec<u64>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A named table of functions provided by a script to other scripts.
+#[derive(Clone)]
+struct Service {
+    owner: VmInfo,
+    luavm: WeakLuaVM,
+    table: Arc<LuaRegistryKey>,
+}
+
+/// Provide the functions of `table` as service `name`. A script can replace its
+/// own services, but not services of other scripts.
+pub fn provide(lua: &Lua, name: &str, table: LuaTable) -> LuaResult<()> {
+    if name.is_empty() {
+        return Err(LuaError::runtime("Service name can't be empty"));
+    }
+    let owner = VmInfo::of(lua).ok_or(LuaError::runtime("Unknown VM"))?;
+    let luavm = lua
+        .app_data_ref::<WeakLuaVM>()
+        .ok_or(LuaError::runtime("Services are not available in this VM"))?
+        .clone();
+    let mut services = SERVICES.lock().unwrap();
+    if let Some(other) = services.get(name) {
+        if other.owner.id != owner.id {
+            return Err(LuaError::runtime(format!(
+                "Service `{}` is already provided by {}",
+                name, other.owner.name
+            )));
+        }
+    }
+    services.insert(
+        name.to_string(),
+        Service {
+            owner,
+            luavm,
+            table: Arc::new(lua.create_registry_value(table)?),
+        },
+    );
+
+    Ok(())
+}
+
+/// Remove service `name` if it is provided by the VM of `lua`.
+pub fn revoke(lua: &Lua, name: &str) -> bool {
+    let Some(owner) = VmInfo::of(lua) else {
+        return false;
+    };
+    let mut services = SERVICES.lock().unwrap();
+    if services.get(name).map(|s| s.owner.id) != Some(owner.id) {
+        return false;
+    }
+    services.remove(name).is_some()
+}
+
+pub fn is_provided(name: &str) -> bool {
+    SERVICES.lock().unwrap().contains_key(name)
+}
+
+/// Remove all services of a VM. Returns the number of removed services.
+pub fn remove_owner(vm_id: u64) -> usize {
+    let mut services = SERVICES.lock().unwrap();
+    let len = services.len();
+    services.retain(|_, s| s.owner.id != vm_id);
+    len - services.len()
+}
+
+/// Names of the services of a VM, sorted.
+pub fn names_of(vm_id: u64) -> Vec<String> {
+    let mut names = SERVICES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, s)| s.owner.id == vm_id)
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// A pending call of `caller` to `provider`, removed when dropped.
+struct Waiting {
+    caller: u64,
+    provider: u64,
+}
+
+impl Waiting {
+    /// Record that `caller` waits for `provider`. Returns `None` if `provider`
+    /// is (indirectly) waiting for `caller`, which would never finish.
+    fn start(caller: u64, provider: u64) -> Option<Waiting> {
+        let mut waiting = WAITING.lock().unwrap();
+        let mut pending = vec![provider];
+        let mut visited = vec![];
+        while let Some(vm) = pending.pop() {
+            if vm == caller {
+                return None;
+            }
+            if visited.contains(&vm) {
+                continue;
+            }
+            visited.push(vm);
+            if let Some(providers) = waiting.get(&vm) {
+                pending.extend(providers);
+            }
+        }
+        waiting.entry(caller).or_default().push(provider);
+        Some(Waiting { caller, provider })
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let mut waiting = WAITING.lock().unwrap();
+        let Some(providers) = waiting.get_mut(&self.caller) else {
+            return;
+        };
+        if let Some(i) = providers.iter().position(|&p| p == self.provider) {
+            providers.swap_remove(i);
+        }
+        if providers.is_empty() {
+            waiting.remove(&self.caller);
+        }
+    }
+}
+
+/// Call `method` of service `name` with copies of `args`, returns copies of its
+/// results.
+///
+/// The provider runs in its own VM, so the call waits for it to be idle. Fails
+/// if the provider doesn't reply within `timeout` or is unloaded meanwhile, and
+/// at once if the provider is waiting for a call of the caller.
+pub async fn call<'lua>(
+    lua: &'lua Lua,
+    name: &str,
+    method: &str,
+    args: LuaMultiValue<'lua>,
+    timeout: Duration,
+) -> LuaResult<LuaMultiValue<'lua>> {
+    let args = args
+        .into_iter()
+        .map(|arg| payload::from_lua(lua, arg))
+        .collect::<LuaResult<Vec<_>>>()?;
+    let service = SERVICES
+        .lock()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .ok_or(LuaError::runtime(format!("Unknown service: `{}`", name)))?;
+    let caller = VmInfo::of(lua).ok_or(LuaError::runtime("Unknown VM"))?;
+
+    let unloaded = || {
+        LuaError::runtime(format!(
+            "Service `{}` was unloaded with {}",
+            name, service.owner.name
+        ))
+    };
+    let reply = async {
+        // the caller VM is already locked when a script calls its own service
+        if service.owner.id == caller.id {
+            return invoke(lua, &service, name, method, &args).await;
+        }
+        let _waiting = Waiting::start(caller.id, service.owner.id).ok_or_else(|| {
+            LuaError::runtime(format!(
+                "Cyclic call of `{}.{}`: {} is waiting for {}",
+                name, method, service.owner.name, caller.name
+            ))
+        })?;
+        let luavm = service.luavm.upgrade().ok_or_else(unloaded)?;
+        let luavm = luavm.lock().await;
+        if !luavm.is_running() {
+            return Err(unloaded());
+        }
+        invoke(&luavm.lua, &service, name, method, &args).await
+    };
+    let results = tokio::time::timeout(timeout, reply).await.map_err(|_| {
+        LuaError::runtime(format!(
+            "Call of `{}.{}` timed out after {}ms",
+            name,
+            method,
+            timeout.as_millis()
+        ))
+    })??;
+
+    results
+        .iter()
+        .map(|result| payload::into_lua(lua, result))
+        .collect::<LuaResult<Vec<_>>>()
+        .map(LuaMultiValue::from_vec)
+}
+
+/// Call a method in the provider VM.
+async fn invoke(
+    lua: &Lua,
+    service: &Service,
+    name: &str,
+    method: &str,
+    args: &[Payload],
+) -> LuaResult<Vec<Payload>> {
+    let table: LuaTable = lua.registry_value(&service.table)?;
+    let f: LuaFunction = match table.get(method)? {
+        LuaValue::Function(f) => f,
+        _ => {
+            return Err(LuaError::runtime(format!(
+                "Service `{}` has no method `{}`",
+                name, method
+            )))
+        }
+    };
+    let args = args
+        .iter()
+        .map(|arg| payload::into_lua(lua, arg))
+        .collect::<LuaResult<Vec<_>>>()?;
+    let results = f
+        .call_async::<_, LuaMultiValue>(LuaMultiValue::from_vec(args))
+        .await
+        .map_err(|e| {
+            LuaError::runtime(format!(
+                "Error in `{}.{}` of {}: {}",
+                name, method, service.owner.name, e
+            ))
+        })?;
+
+    results
+        .into_iter()
+        .map(|result| payload::from_lua(lua, result))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::luavm::LuaVM;
+
+    #[tokio::test]
+    async fn test_invoke() {
+        let lua = Lua::new();
+        let table = lua
+            .load(
+                r#"{
+                    add = function(a, b) return a + b, "sum" end,
+                    fail = function() error("boom") end,
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        let service = Service {
+            owner: VmInfo {
+                id: 1,
+                name: "hud.lua".to_string(),
+            },
+            luavm: WeakLuaVM::new(),
+            table: Arc::new(lua.create_registry_value::<LuaTable>(table).unwrap()),
+        };
+        let args = [Payload::from(1), Payload::from(2.5)];
+
+        let results = invoke(&lua, &service, "hud", "add", &args).await.unwrap();
+        assert_eq!(results, vec![Payload::from(3.5), Payload::from("sum")]);
+        let e = invoke(&lua, &service, "hud", "fail", &[])
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("`hud.fail` of hud.lua"));
+        assert!(invoke(&lua, &service, "hud", "draw", &[]).await.is_err());
+    }
+
+    #[test]
+    fn test_concurrent_waiting() {
+        // two calls of coroutines of VM 101
+        let first = Waiting::start(101, 102).unwrap();
+        let second = Waiting::start(101, 103).unwrap();
+        assert!(Waiting::start(102, 101).is_none());
+        drop(first);
+        // 101 still waits for 103
+        assert!(Waiting::start(103, 101).is_none());
+        assert!(Waiting::start(102, 101).is_some());
+        drop(second);
+        assert!(Waiting::start(103, 101).is_some());
+        assert!(!WAITING.lock().unwrap().contains_key(&101));
+    }
+
+    /// A running VM providing `table` as service `name`, with `call(name, method, ...)`.
+    async fn provider(name: &str, table: &str) -> Arc<tokio::sync::Mutex<LuaVM>> {
+        let luavm = Arc::new(tokio::sync::Mutex::new(LuaVM::new(name)));
+        let mut vm = luavm.lock().await;
+        vm.lua.set_app_data(Arc::downgrade(&luavm));
+        let call = vm
+            .lua
+            .create_async_function(
+                |lua, (name, method, args): (String, String, LuaMultiValue)| async move {
+                    call(lua, &name, &method, args, Duration::from_secs(2)).await
+                },
+            )
+            .unwrap();
+        vm.lua.globals().set("call", call).unwrap();
+        vm.run("").await.unwrap();
+        let table = vm.lua.load(table).eval().unwrap();
+        provide(&vm.lua, name, table).unwrap();
+        drop(vm);
+        luavm
+    }
+
+    #[tokio::test]
+    async fn test_call_between_vms() {
+        let a = provider("test_a", r#"{ value = function() return 1 end }"#).await;
+        let b = provider(
+            "test_b",
+            r#"{
+                echo = function(x) return x end,
+                ping = function() return call("test_a", "value") end,
+            }"#,
+        )
+        .await;
+
+        // `a` is locked while running the calling script
+        let vm = a.lock().await;
+        let args = LuaMultiValue::from_vec(vec![LuaValue::Integer(7)]);
+        let results = call(&vm.lua, "test_b", "echo", args, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(results.into_vec()[0].as_i64(), Some(7));
+
+        let start = std::time::Instant::now();
+        let args = LuaMultiValue::new();
+        let e = call(&vm.lua, "test_b", "ping", args, Duration::from_secs(2))
+            .await
+            .unwrap_err();
+        assert!(
+            e.to_string().contains("Cyclic call of `test_a.value`"),
+            "{}",
+            e
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let caller = VmInfo::of(&vm.lua).unwrap();
+        assert!(!WAITING.lock().unwrap().contains_key(&caller.id));
+        drop(vm);
+
+        let vm = b.lock().await;
+        let results = call(
+            &vm.lua,
+            "test_b",
+            "ping",
+            LuaMultiValue::new(),
+            DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(results.into_vec()[0].as_i64(), Some(1));
+        remove_owner(VmInfo::of(&vm.lua).unwrap().id);
+        drop(vm);
+        remove_owner(caller.id);
+    }
+}
@@ -1,63 +1,274 @@
-use log::{debug, error, info, warn};
+use log::{log, Level};
 use mlua::prelude::*;
 use mlua::Variadic;
 
-pub fn fn_debug(_: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
-    debug!(
-        "{}",
-        args.iter()
-            .map(|arg| display_value(arg))
-            .collect::<Vec<_>>()
-            .join(", ")
-    );
+use crate::logger::script_target;
+use crate::luavm::VmInfo;
+
+pub fn fn_debug(lua: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
+    log_args(lua, Level::Debug, &args);
     Ok(())
 }
 
-pub fn fn_info(_: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
-    info!(
-        "{}",
-        args.iter()
-            .map(|arg| display_value(arg))
-            .collect::<Vec<_>>()
-            .join(", ")
-    );
+pub fn fn_info(lua: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
+    log_args(lua, Level::Info, &args);
     Ok(())
 }
 
-pub fn fn_warn(_: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
-    warn!(
-        "{}",
-        args.iter()
-            .map(|arg| display_value(arg))
-            .collect::<Vec<_>>()
-            .join(", ")
-    );
+pub fn fn_warn(lua: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
+    log_args(lua, Level::Warn, &args);
     Ok(())
 }
 
-pub fn fn_error(_: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
-    error!(
+pub fn fn_error(lua: &Lua, args: Variadic<mlua::Value>) -> LuaResult<()> {
+    log_args(lua, Level::Error, &args);
+    Ok(())
+}
+
+fn log_args(lua: &Lua, level: Level, args: &[mlua::Value]) {
+    log!(
+        target: &target(lua),
+        level,
         "{}",
         args.iter()
             .map(|arg| display_value(arg))
             .collect::<Vec<_>>()
             .join(", ")
     );
-    Ok(())
 }
 
-fn display_value(value: &mlua::Value) -> String {
+/// Log target of the script of `lua`, records are tagged with its name.
+pub fn target(lua: &Lua) -> String {
+    match VmInfo::of(lua) {
+        Some(info) => script_target(&info.name),
+        None => script_target("?"),
+    }
+}
+
+/// Nesting shown at most, deeper tables are shown as `{...}`.
+const MAX_DEPTH: usize = 4;
+/// Entries shown at most per table.
+const MAX_ENTRIES: usize = 50;
+/// Tables shorter than this are shown on one line.
+const MAX_INLINE: usize = 80;
+const INDENT: &str = "  ";
+
+/// Text of a value for the log, tables are pretty-printed.
+///
+/// Top-level strings are shown as is, so `Print("a")` logs `a`.
+pub fn display_value(value: &mlua::Value) -> String {
     match value {
-        LuaNil => "nil".to_string(),
-        LuaValue::Boolean(b) => format!("{b}"),
-        LuaValue::LightUserData(ud) => format!("{ud:?}"),
-        LuaValue::Integer(i) => format!("{i}"),
-        LuaValue::Number(n) => format!("{n}"),
         LuaValue::String(s) => s.to_str().unwrap_or("<invalid string>").to_string(),
-        LuaValue::Table(t) => format!("{t:?}"),
-        LuaValue::Function(f) => format!("{f:?}"),
-        LuaValue::Thread(t) => format!("{t:?}"),
-        LuaValue::UserData(ud) => format!("{ud:?}"),
-        LuaValue::Error(e) => format!("{e}"),
+        value => Pretty::default().format(value, 0),
+    }
+}
+
+/// Recursive formatter, tracking the tables being formatted to stop at cycles.
+#[derive(Default)]
+struct Pretty {
+    visiting: Vec<*const std::ffi::c_void>,
+}
+
+impl Pretty {
+    fn format(&mut self, value: &LuaValue, depth: usize) -> String {
+        match value {
+            LuaNil => "nil".to_string(),
+            LuaValue::Boolean(b) => format!("{b}"),
+            LuaValue::LightUserData(ud) => format!("lightuserdata: {:p}", ud.0),
+            LuaValue::Integer(i) => format!("{i}"),
+            LuaValue::Number(n) => format!("{n}"),
+            LuaValue::String(s) => format!("{:?}", s.to_string_lossy()),
+            LuaValue::Table(t) => self.format_table(t, depth),
+            LuaValue::Function(f) => format!("function: {:p}", f.to_pointer()),
+            LuaValue::Thread(t) => format!("thread: {:p}", t.to_pointer()),
+            LuaValue::UserData(ud) => format_userdata(ud),
+            LuaValue::Error(e) => format!("{e}"),
+        }
+    }
+
+    fn format_table(&mut self, table: &LuaTable, depth: usize) -> String {
+        let metatable = table.get_metatable();
+        if let Some(text) = metatable.as_ref().and_then(|mt| call_tostring(mt, table)) {
+            return text;
+        }
+        let name = metatable
+            .and_then(|mt| mt.raw_get::<_, Option<String>>("__name").ok().flatten())
+            .map(|name| format!("{} ", name))
+            .unwrap_or_default();
+        let pointer = table.to_pointer();
+        if self.visiting.contains(&pointer) {
+            return format!("{}<cycle>", name);
+        }
+        if depth >= MAX_DEPTH {
+            return format!("{}{{...}}", name);
+        }
+
+        self.visiting.push(pointer);
+        let entries = sorted_entries(table);
+        let total = entries.len();
+        let mut items = entries
+            .into_iter()
+            .take(MAX_ENTRIES)
+            .map(|(key, value)| {
+                let value = self.format(&value, depth + 1);
+                match key {
+                    None => value,
+                    Some(key) => format!("{} = {}", key, value),
+                }
+            })
+            .collect::<Vec<_>>();
+        self.visiting.pop();
+        if total > MAX_ENTRIES {
+            items.push(format!("...{} more", total - MAX_ENTRIES));
+        }
+
+        if items.is_empty() {
+            return format!("{}{{}}", name);
+        }
+        let inline = format!("{}{{ {} }}", name, items.join(", "));
+        if inline.len() <= MAX_INLINE && !inline.contains('\n') {
+            return inline;
+        }
+        let indent = INDENT.repeat(depth + 1);
+        let mut text = format!("{}{{\n", name);
+        for item in items {
+            text.push_str(&format!("{}{},\n", indent, item));
+        }
+        text.push_str(&format!("{}}}", INDENT.repeat(depth)));
+        text
+    }
+}
+
+/// Entries of a table: the sequence first without keys, then the other keys
+/// sorted by type and value.
+fn sorted_entries<'lua>(table: &LuaTable<'lua>) -> Vec<(Option<String>, LuaValue<'lua>)> {
+    let len = table.raw_len();
+    let mut entries = (1..=len)
+        .map(|i| (None, table.raw_get(i).unwrap_or(LuaNil)))
+        .collect::<Vec<_>>();
+    let mut keyed = table
+        .clone()
+        .pairs::<LuaValue, LuaValue>()
+        .filter_map(|pair| pair.ok())
+        .filter(|(key, _)| !matches!(key, LuaValue::Integer(i) if *i >= 1 && *i as usize <= len))
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| key_order(a).partial_cmp(&key_order(b)).unwrap());
+    entries.extend(
+        keyed
+            .into_iter()
+            .map(|(key, value)| (Some(format_key(&key)), value)),
+    );
+    entries
+}
+
+/// Sort key of a table key: numbers, then strings, then the rest.
+fn key_order(key: &LuaValue) -> (u8, f64, String) {
+    match key {
+        LuaValue::Integer(i) => (0, *i as f64, String::new()),
+        LuaValue::Number(n) if !n.is_nan() => (0, *n, String::new()),
+        LuaValue::String(s) => (1, 0.0, s.to_string_lossy().into_owned()),
+        other => (2, 0.0, Pretty::default().format(other, MAX_DEPTH)),
+    }
+}
+
+/// `name` for identifiers, `["a b"]` or `[5]` otherwise.
+fn format_key(key: &LuaValue) -> String {
+    if let LuaValue::String(s) = key {
+        let s = s.to_string_lossy();
+        let mut chars = s.chars();
+        if chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return s.into_owned();
+        }
+    }
+    format!("[{}]", Pretty::default().format(key, MAX_DEPTH))
+}
+
+/// Userdata by `__tostring`, like `RawPtr(0x1400000 + 0x10)`, or by `__name`.
+fn format_userdata(ud: &LuaAnyUserData) -> String {
+    let Ok(metatable) = ud.get_metatable() else {
+        return format!("userdata: {:p}", ud.to_pointer());
+    };
+    if let Ok(tostring) = metatable.get::<LuaFunction>(LuaMetaMethod::ToString) {
+        if let Ok(text) = tostring.call::<_, String>(ud.clone()) {
+            return text;
+        }
+    }
+    let name = metatable
+        .get::<String>("__name")
+        .unwrap_or_else(|_| "userdata".to_string());
+    format!("{}: {:p}", name, ud.to_pointer())
+}
+
+fn call_tostring(metatable: &LuaTable, table: &LuaTable) -> Option<String> {
+    let tostring = metatable.raw_get::<_, LuaFunction>("__tostring").ok()?;
+    tostring.call::<_, String>(table.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::luavm::libs::memory::RawPtr;
+
+    fn show(lua: &Lua, code: &str) -> String {
+        display_value(&lua.load(code).eval::<LuaValue>().unwrap())
+    }
+
+    #[test]
+    fn test_display_value() {
+        let lua = Lua::new();
+        assert_eq!(show(&lua, "'a'"), "a");
+        assert_eq!(show(&lua, "{}"), "{}");
+        assert_eq!(
+            show(
+                &lua,
+                "{ 1, 'two', z = true, a = { x = 1 }, [10] = 0, ['a b'] = 2 }"
+            ),
+            r#"{ 1, "two", [10] = 0, a = { x = 1 }, ["a b"] = 2, z = true }"#
+        );
+        assert_eq!(show(&lua, "{ { { { { 1 } } } } }"), "{ { { { {...} } } } }");
+        let long = show(
+            &lua,
+            "{ name = string.rep('x', 40), title = string.rep('y', 40) }",
+        );
+        assert_eq!(
+            long,
+            format!(
+                "{{\n  name = \"{}\",\n  title = \"{}\",\n}}",
+                "x".repeat(40),
+                "y".repeat(40)
+            )
+        );
+        let many = show(&lua, "local t = {} for i = 1, 60 do t[i] = i end return t");
+        assert!(many.ends_with("50,\n  ...10 more,\n}"));
+    }
+
+    #[test]
+    fn test_display_metatables() {
+        let lua = Lua::new();
+        assert_eq!(
+            show(&lua, "local t = { a = 1 } t.self = t return t"),
+            "{ a = 1, self = <cycle> }"
+        );
+        assert_eq!(
+            show(
+                &lua,
+                "return setmetatable({}, { __tostring = function() return 'Point(1, 2)' end })"
+            ),
+            "Point(1, 2)"
+        );
+        assert_eq!(
+            show(&lua, "return setmetatable({ x = 1 }, { __name = 'Vec' })"),
+            "Vec { x = 1 }"
+        );
+
+        let mut ptr = RawPtr::new();
+        ptr.set_base(0x1400000);
+        ptr.offsets(&[0x10, -0x8]);
+        let ptr = LuaValue::UserData(lua.create_userdata(ptr).unwrap());
+        assert_eq!(display_value(&ptr), "RawPtr(0x1400000 + 0x10 -> -0x8)");
     }
 }
@@ -1,6 +1,11 @@
 use std::{
+    fmt,
     path::Path,
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, SystemTime},
 };
 
 use log::debug;
@@ -9,9 +14,12 @@ use snafu::prelude::*;
 use tokio::sync::Mutex;
 
 use super::libs;
+use super::repl::{self, Evaluation};
 
 pub type WeakLuaVM = Weak<Mutex<LuaVM>>;
 
+static NEXT_VM_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug, Snafu)]
 pub enum LuaVMError {
     #[snafu(display("Failed to load script file: {}", source))]
@@ -20,6 +28,10 @@ pub enum LuaVMError {
     NotLoaded,
     #[snafu(display("Failed to load script: {}", source))]
     LuaRuntime { source: mlua::Error },
+    #[snafu(display("Lua VM is not running"))]
+    NotRunning,
+    #[snafu(display("{}", source))]
+    Eval { source: mlua::Error },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -27,19 +39,56 @@ pub enum RinningState {
     Unloaded,
     Loaded,
     Running,
+    Stopped,
+}
+
+impl fmt::Display for RinningState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            RinningState::Unloaded => "unloaded",
+            RinningState::Loaded => "loaded",
+            RinningState::Running => "running",
+            RinningState::Stopped => "stopped",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+/// Identity of a VM, stored as app data of the VM.
+///
+/// `id` is unique per VM instance, so a reloaded script gets a new one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VmInfo {
+    pub id: u64,
+    pub name: String,
+}
+
+impl VmInfo {
+    pub fn of(lua: &Lua) -> Option<VmInfo> {
+        lua.app_data_ref::<VmInfo>().map(|info| info.clone())
+    }
 }
 
 #[derive(Debug)]
 pub struct LuaVM {
     pub lua: Lua,
     running_state: RinningState,
+    /// the libs are (partly) loaded, so the stop phase must run even if the
+    /// script failed before running
+    libs_loaded: bool,
 }
 
 impl LuaVM {
-    pub fn new() -> Self {
+    pub fn new(name: &str) -> Self {
+        let lua = Lua::new();
+        lua.set_app_data(VmInfo {
+            id: NEXT_VM_ID.fetch_add(1, Ordering::Relaxed),
+            name: name.to_string(),
+        });
         Self {
-            lua: Lua::new(),
+            lua,
             running_state: RinningState::Unloaded,
+            libs_loaded: false,
         }
     }
 
@@ -52,6 +101,13 @@ impl LuaVM {
         self.running_state = RinningState::Running;
         Ok(())
     }
+
+    /// Run the stop phase of the libs. Listeners are no longer dispatched afterwards.
+    pub fn stop(&mut self) {
+        libs::unload_libs(&self.lua);
+        self.libs_loaded = false;
+        self.running_state = RinningState::Stopped;
+    }
 }
 
 #[derive(Debug)]
@@ -59,6 +115,10 @@ pub struct LuaHandlerData {
     pub name: String,
     pub file_path: Option<String>,
     pub script: Option<String>,
+    /// when the script file was read
+    pub loaded_at: Option<SystemTime>,
+    /// error of the last run
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -74,8 +134,10 @@ impl LuaHandler {
                 name: name.to_string(),
                 file_path: None,
                 script: None,
+                loaded_at: None,
+                error: None,
             })),
-            luavm: Arc::new(Mutex::new(LuaVM::new())),
+            luavm: Arc::new(Mutex::new(LuaVM::new(name))),
         }
     }
 
@@ -87,6 +149,7 @@ impl LuaHandler {
     }
 
     async fn load_libs(&self) -> LuaResult<()> {
+        self.luavm.lock().await.libs_loaded = true;
         libs::load_libs(self.get_luavm_weak()).await
     }
 
@@ -95,20 +158,47 @@ impl LuaHandler {
     }
 
     pub async fn run(&self) -> Result<(), LuaVMError> {
-        let data = self.data.lock().await;
+        let mut data = self.data.lock().await;
         if data.script.is_none() {
             return Err(LuaVMError::NotLoaded);
         }
 
         let script = &data.script.clone().unwrap();
         debug!("Lua VM `{}` start running", data.name);
-        self.run_inner(script)
-            .await
-            .map_err(|e| LuaVMError::LuaRuntime { source: e })?;
+        let result = self.run_inner(script).await;
+        data.error = result.as_ref().err().map(|e| e.to_string());
+        result.map_err(|e| LuaVMError::LuaRuntime { source: e })?;
 
         Ok(())
     }
 
+    /// Evaluate an input of the REPL in the running VM.
+    pub async fn eval(&self, input: &str, timeout: Duration) -> Result<Evaluation, LuaVMError> {
+        let luavm = self.luavm.lock().await;
+        if !luavm.is_running() {
+            return Err(LuaVMError::NotRunning);
+        }
+        repl::eval(&luavm.lua, input, timeout)
+            .await
+            .map_err(|e| LuaVMError::Eval { source: e })
+    }
+
+    pub async fn state(&self) -> RinningState {
+        self.luavm.lock().await.running_state
+    }
+
+    /// Memory, listeners and other resources of the VM.
+    pub async fn usage(&self) -> libs::VmUsage {
+        libs::vm_usage(&self.luavm).await
+    }
+
+    pub async fn stop(&self) {
+        let mut luavm = self.luavm.lock().await;
+        if luavm.libs_loaded {
+            luavm.stop();
+        }
+    }
+
     pub async fn load_file<P>(&mut self, file_path: P) -> Result<(), LuaVMError>
     where
         P: AsRef<Path>,
@@ -117,6 +207,7 @@ impl LuaHandler {
         let mut data = self.data.lock().await;
         data.file_path = Some(file_path.as_ref().to_string_lossy().to_string());
         data.script = Some(script);
+        data.loaded_at = Some(SystemTime::now());
 
         Ok(())
     }
@@ -127,8 +218,12 @@ impl LuaHandler {
             return Err(LuaVMError::NotLoaded);
         }
         let file_path = data.file_path.clone().unwrap();
+        let name = data.name.clone();
         drop(data);
 
+        // start over with a fresh VM, so that listeners of the previous run are dropped
+        self.stop().await;
+        self.luavm = Arc::new(Mutex::new(LuaVM::new(&name)));
         self.load_file(file_path).await?;
         self.run().await?;
 
@@ -1,4 +1,11 @@
+mod deadline;
 mod libs;
 mod luavm;
+mod repl;
 
+pub use libs::{
+    commands, event, init_game_events, install_hooks, is_input_hooked, list_freezes, load_layout,
+    show_engine_message, show_script_error, FreezeEntry,
+};
 pub use luavm::*;
+pub use repl::{Evaluation, Repl};
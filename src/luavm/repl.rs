@@ -0,0 +1,148 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+use super::deadline::call_until;
+use super::libs::display_value;
+
+/// Result of evaluating an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Evaluation {
+    /// the input continues on the next line, like an open `function`
+    Incomplete,
+    /// pretty-printed results
+    Done(String),
+}
+
+/// Evaluate an input in `lua`, as an expression if it is one, otherwise as
+/// statements. Aborted after `timeout`.
+pub async fn eval(lua: &Lua, input: &str, timeout: Duration) -> LuaResult<Evaluation> {
+    let expression = lua
+        .load(format!("return {}", input))
+        .set_name("=eval")
+        .into_function();
+    let func = match expression {
+        Ok(func) => func,
+        Err(_) => match lua.load(input).set_name("=eval").into_function() {
+            Ok(func) => func,
+            Err(LuaError::SyntaxError {
+                incomplete_input: true,
+                ..
+            }) => return Ok(Evaluation::Incomplete),
+            Err(e) => return Err(e),
+        },
+    };
+
+    let values: LuaMultiValue = call_until(
+        lua,
+        func,
+        (),
+        Instant::now() + timeout,
+        "Evaluation timed out",
+    )
+    .await?;
+    if values.is_empty() {
+        return Ok(Evaluation::Done("ok".to_string()));
+    }
+    let values = values.iter().map(display_value).collect::<Vec<_>>();
+
+    Ok(Evaluation::Done(values.join(", ")))
+}
+
+/// Pending lines and history of the chat REPL.
+#[derive(Debug, Default)]
+pub struct Repl {
+    /// incomplete input by target VM
+    pending: HashMap<String, String>,
+    history: VecDeque<String>,
+}
+
+impl Repl {
+    /// Append a line to the pending input of `target`, returns the whole input.
+    pub fn input(&mut self, target: &str, line: &str) -> String {
+        match self.pending.remove(target) {
+            Some(pending) => format!("{}\n{}", pending, line),
+            None => line.to_string(),
+        }
+    }
+
+    /// Keep an incomplete input of `target` until the next line.
+    pub fn keep(&mut self, target: &str, input: String) {
+        self.pending.insert(target.to_string(), input);
+    }
+
+    /// Drop the pending input of `target`, returns whether there was one.
+    pub fn cancel(&mut self, target: &str) -> bool {
+        self.pending.remove(target).is_some()
+    }
+
+    /// Add an evaluated input to the history, keeping the latest `max`.
+    pub fn record(&mut self, entry: String, max: usize) {
+        self.history.push_back(entry);
+        while self.history.len() > max {
+            self.history.pop_front();
+        }
+    }
+
+    /// The latest `n` inputs, oldest first.
+    pub fn history(&self, n: usize) -> Vec<String> {
+        let skip = self.history.len().saturating_sub(n);
+        self.history.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run(lua: &Lua, input: &str) -> LuaResult<Evaluation> {
+        eval(lua, input, Duration::from_millis(200)).await
+    }
+
+    #[tokio::test]
+    async fn test_eval() {
+        let lua = Lua::new();
+        let done = |s: &str| Evaluation::Done(s.to_string());
+        assert_eq!(run(&lua, "1 + 2").await.unwrap(), done("3"));
+        assert_eq!(run(&lua, "x = { 1, a = 'b' }").await.unwrap(), done("ok"));
+        assert_eq!(
+            run(&lua, "x, #x").await.unwrap(),
+            done(r#"{ 1, a = "b" }, 1"#)
+        );
+        assert_eq!(
+            run(&lua, "function f()").await.unwrap(),
+            Evaluation::Incomplete
+        );
+        assert_eq!(
+            run(&lua, "function f()\nreturn 4 end").await.unwrap(),
+            done("ok")
+        );
+        assert_eq!(run(&lua, "f()").await.unwrap(), done("4"));
+        assert!(run(&lua, "1 +* 2").await.is_err());
+        assert!(run(&lua, "error('boom')").await.is_err());
+        let timeout = run(&lua, "while true do end").await.unwrap_err();
+        assert!(timeout.to_string().contains("timed out"), "{}", timeout);
+        // usable after a timeout
+        assert_eq!(run(&lua, "f()").await.unwrap(), done("4"));
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut repl = Repl::default();
+        let input = repl.input("scratch", "for i = 1, 2 do");
+        repl.keep("scratch", input);
+        assert_eq!(repl.input("hud.lua", "x"), "x");
+        assert_eq!(
+            repl.input("scratch", "print(i) end"),
+            "for i = 1, 2 do\nprint(i) end"
+        );
+        assert!(!repl.cancel("scratch"));
+
+        for i in 0..5 {
+            repl.record(format!("exec: {}", i), 3);
+        }
+        assert_eq!(repl.history(2), ["exec: 3", "exec: 4"]);
+        assert_eq!(repl.history(10).len(), 3);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+
+pub const SCRIPTS_PATH: &str = "LuaEngineEx/scripts.toml";
+
+#[derive(Debug, Snafu)]
+pub enum ScriptsError {
+    #[snafu(display("Failed to read script settings: {}", source))]
+    Read { source: std::io::Error },
+    #[snafu(display("Failed to parse script settings: {}", source))]
+    Parse { source: toml::de::Error },
+    #[snafu(display("Failed to serialize script settings: {}", source))]
+    Serialize { source: toml::ser::Error },
+    #[snafu(display("Failed to write script settings: {}", source))]
+    Write { source: std::io::Error },
+}
+
+/// Script settings changed by `/lua enable|disable`, kept across sessions in
+/// `LuaEngineEx/scripts.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptSettings {
+    /// file names of the scripts not loaded on start
+    pub disabled: BTreeSet<String>,
+}
+
+impl ScriptSettings {
+    pub fn from_str(s: &str) -> Result<ScriptSettings, ScriptsError> {
+        toml::from_str(s).context(ParseSnafu)
+    }
+
+    /// Load the settings file. A missing file enables all scripts.
+    pub fn load() -> Result<ScriptSettings, ScriptsError> {
+        match std::fs::read_to_string(SCRIPTS_PATH) {
+            Ok(s) => ScriptSettings::from_str(&s),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ScriptSettings::default()),
+            Err(e) => Err(ScriptsError::Read { source: e }),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), ScriptsError> {
+        let s = toml::to_string(self).context(SerializeSnafu)?;
+        std::fs::write(SCRIPTS_PATH, s).context(WriteSnafu)
+    }
+
+    pub fn is_enabled(&self, script: &str) -> bool {
+        !self.disabled.contains(script)
+    }
+
+    /// Returns whether the setting changed.
+    pub fn set_enabled(&mut self, script: &str, enabled: bool) -> bool {
+        match enabled {
+            true => self.disabled.remove(script),
+            false => self.disabled.insert(script.to_string()),
+        }
+    }
+}
+
+/// Manifest of a script, from the `@key value` tags of its leading comments:
+///
+/// ```lua
+/// -- @name HUD
+/// -- @version 1.2.0
+/// ```
+pub fn parse_manifest(script: &str) -> BTreeMap<String, String> {
+    let mut manifest = BTreeMap::new();
+    for line in script.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+        let Some(comment) = line.strip_prefix("--") else {
+            break;
+        };
+        let Some(tag) = comment.trim_start_matches('-').trim().strip_prefix('@') else {
+            continue;
+        };
+        let (key, value) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        if !key.is_empty() {
+            manifest.insert(key.to_string(), value.trim().to_string());
+        }
+    }
+    manifest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings() {
+        let mut settings = ScriptSettings::from_str(r#"disabled = ["hud.lua"]"#).unwrap();
+        assert!(!settings.is_enabled("hud.lua"));
+        assert!(settings.is_enabled("meter.lua"));
+        assert!(settings.set_enabled("meter.lua", false));
+        assert!(!settings.set_enabled("meter.lua", false));
+        assert!(settings.set_enabled("hud.lua", true));
+        let saved = toml::to_string(&settings).unwrap();
+        assert_eq!(ScriptSettings::from_str(&saved).unwrap(), settings);
+        assert_eq!(
+            ScriptSettings::from_str("").unwrap(),
+            ScriptSettings::default()
+        );
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let script = r#"
+            --- HUD of the hunter
+            -- @name HUD
+            -- @version  1.2.0
+            -- @experimental
+
+            -- @author Hunter
+            local hp = 0
+            -- @ignored
+        "#;
+        let manifest = parse_manifest(script);
+        assert_eq!(
+            manifest.into_iter().collect::<Vec<_>>(),
+            [
+                ("author".to_string(), "Hunter".to_string()),
+                ("experimental".to_string(), String::new()),
+                ("name".to_string(), "HUD".to_string()),
+                ("version".to_string(), "1.2.0".to_string()),
+            ]
+        );
+        assert!(parse_manifest("print(1)\n-- @name x").is_empty());
+    }
+}